@@ -1,4 +1,6 @@
 mod ticks;
+mod schedule;
+pub use schedule::*;
 use std::thread::JoinHandle;
 use async_std::sync::Mutex;
 use ticks::*;
@@ -13,7 +15,7 @@ use winit::{event::{Event, WindowEvent}, event_loop::{ControlFlow, EventLoop}, p
 
 use crate::rendering::Renderer;
 use crate::sync::AtomicWaiter;
-use crate::{entity::EntityComponentManager, sync::{Runtime, block_on}};
+use crate::{entity::{EntityComponentManager, Worlds, RollbackBuffer, RollbackFrame}, sync::{Runtime, block_on}};
 
 //o------------ User Trait ---------------o
 
@@ -37,7 +39,15 @@ pub struct SharedAppData {
 pub struct FixedStepData {
     pub(crate) input_state: Mutex<InputState>,
     pub ecm: EntityComponentManager,
+    pub worlds: Worlds,
     pub(crate) fixed_delta_time: AtomicU64,
+    pub(crate) tick: AtomicU64,
+    pub(crate) rollback: Mutex<RollbackBuffer<InputState>>,
+    pub(crate) rollback_request: Mutex<Option<RollbackRequest>>,
+    /**
+     * Held while a fixed step or a rollback runs on the world.
+     */
+    pub(crate) step_lock: Mutex<()>,
 }
 
 pub struct VariableStepData {
@@ -78,6 +88,69 @@ impl FixedStepData {
         let dt = self.fixed_delta_time.load(std::sync::atomic::Ordering::Relaxed);
         std::time::Duration::from_nanos(dt)
     }
+
+    /**
+     * Number of the fixed step that is currently running, counting from 0.
+     */
+    pub fn fixed_tick(&self) -> u64 {
+        self.tick.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Keeps the world and the input of the last frames fixed steps, 0 (the default) disables recording.
+     */
+    pub async fn set_rollback_frames(&self, frames: usize) {
+        self.rollback.lock().await.set_capacity(frames);
+    }
+
+    /**
+     * The oldest and the newest tick that can be rolled back to.
+     */
+    pub async fn recorded_ticks(&self) -> Option<(u64, u64)> {
+        let rollback = self.rollback.lock().await;
+        Some((rollback.oldest_tick()?, rollback.newest_tick()?))
+    }
+
+    /**
+     * Changes the recorded state of a key in the input of the tick and requests a resimulation from that tick.
+     * Returns false, if the tick is not recorded.
+     */
+    pub async fn set_recorded_key(&self, tick: u64, key: VirtualKeyCode, pressed: bool) -> bool {
+        match self.rollback.lock().await.get_mut(tick) {
+            Some(frame) => frame.input.key_states[key as usize] = pressed,
+            None => return false,
+        }
+        self.request_rollback(RollbackRequest{ tick, resimulate: true }).await
+    }
+
+    /**
+     * Within the next variable step, before User::varaible_step runs, the world is rolled back to the tick
+     * and all steps since are simulated again with the recorded inputs. Fixed steps that fall due meanwhile wait for it.
+     * Returns false, if the tick is not recorded.
+     */
+    pub async fn resimulate_from(&self, tick: u64) -> bool {
+        self.request_rollback(RollbackRequest{ tick, resimulate: true }).await
+    }
+
+    /**
+     * Within the next variable step, the world is rolled back to the tick and the fixed steps continue from there with the live input.
+     * The steps after the tick are forgotten. Returns false, if the tick is not recorded.
+     */
+    pub async fn rewind_to(&self, tick: u64) -> bool {
+        self.request_rollback(RollbackRequest{ tick, resimulate: false }).await
+    }
+
+    async fn request_rollback(&self, request: RollbackRequest) -> bool {
+        if self.rollback.lock().await.get(request.tick).is_none() {
+            return false;
+        }
+        let mut pending = self.rollback_request.lock().await;
+        *pending = Some(match pending.take() {
+            Some(other) => RollbackRequest{ tick: other.tick.min(request.tick), resimulate: other.resimulate && request.resimulate },
+            None => request,
+        });
+        true
+    }
 }
 
 impl VariableStepData {
@@ -109,6 +182,7 @@ impl VariableStepData {
 
 //o------------ Application ---------------o
 
+#[derive(Clone)]
 pub(crate) struct InputState {
     pub(crate) key_states_old: Box<[bool; 512]>,
     pub(crate) key_states: Box<[bool; 512]>,
@@ -162,8 +236,13 @@ impl<T: User + 'static> Application<T>
             }),
             fixed_step_data: Arc::new(FixedStepData{
                 ecm: EntityComponentManager::new(),
+                worlds: Worlds::new(),
                 input_state: Mutex::new(InputState::default()),
                 fixed_delta_time: AtomicU64::from(33_000_000),
+                tick: AtomicU64::new(0),
+                rollback: Mutex::new(RollbackBuffer::new(0)),
+                rollback_request: Mutex::new(None),
+                step_lock: Mutex::new(()),
             }),
             event_loop: Some(event_loop),
             user: Arc::new(T::default()),
@@ -237,7 +316,7 @@ impl<T: User + 'static> Application<T>
         } else {
             let waiter = AtomicWaiter::new();
             let dep = waiter.make_dependency();
-            let vary_future = vary_tick(self.shared_data.clone(), self.variable_step_data.clone(), self.user.clone());
+            let vary_future = vary_tick(self.shared_data.clone(), self.fixed_step_data.clone(), self.variable_step_data.clone(), self.user.clone());
             let vary_future = async move {
                 let _d = dep;
                 vary_future.await;
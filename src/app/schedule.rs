@@ -0,0 +1,373 @@
+use std::any::TypeId;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::Future;
+
+use crate::sync::Runtime;
+
+pub type SystemFuture = Pin<Box<dyn Future<Output=()> + Send>>;
+
+type SystemFn<Ctx> = Arc<dyn Fn(Arc<Ctx>) -> SystemFuture + Send + Sync>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Access {
+    pub type_id: TypeId,
+    pub type_name: &'static str,
+    pub write: bool,
+}
+
+/**
+ * A unit of work of a Schedule, together with the data it reads and writes.
+ * The declared access is trusted, the system must lock exactly the stores it declares.
+ */
+pub struct System<Ctx> {
+    name: String,
+    run: SystemFn<Ctx>,
+    access: Vec<Access>,
+    before: Vec<String>,
+    after: Vec<String>,
+}
+
+impl<Ctx: Send + Sync + 'static> System<Ctx> {
+    #[allow(unused)]
+    pub fn new<F, Fut>(name: &str, f: F) -> Self
+    where
+        F: Fn(Arc<Ctx>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        Self{
+            name: String::from(name),
+            run: Arc::new(move |ctx| Box::pin(f(ctx))),
+            access: Vec::new(),
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+
+    #[allow(unused)]
+    pub fn reads<T: 'static>(mut self) -> Self {
+        self.declare(Access{ type_id: TypeId::of::<T>(), type_name: std::any::type_name::<T>(), write: false });
+        self
+    }
+
+    #[allow(unused)]
+    pub fn writes<T: 'static>(mut self) -> Self {
+        self.declare(Access{ type_id: TypeId::of::<T>(), type_name: std::any::type_name::<T>(), write: true });
+        self
+    }
+
+    /**
+     * This system has to finish before the system with the given name starts.
+     */
+    #[allow(unused)]
+    pub fn before(mut self, system: &str) -> Self {
+        self.before.push(String::from(system));
+        self
+    }
+
+    /**
+     * This system only starts after the system with the given name finished.
+     */
+    #[allow(unused)]
+    pub fn after(mut self, system: &str) -> Self {
+        self.after.push(String::from(system));
+        self
+    }
+
+    #[allow(unused)]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn declare(&mut self, access: Access) {
+        match self.access.iter_mut().find(|a| a.type_id == access.type_id) {
+            Some(existing) => existing.write |= access.write,
+            None => self.access.push(access),
+        }
+    }
+
+    fn conflict_with(&self, other: &System<Ctx>) -> Option<&'static str> {
+        self.access.iter()
+            .find(|a| other.access.iter().any(|b| a.type_id == b.type_id && (a.write || b.write)))
+            .map(|a| a.type_name)
+    }
+}
+
+/**
+ * Two systems that access the same data and at least one of them writes it.
+ * Conflicting systems never run at the same time and have to be ordered by before or after constraints.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct AccessConflict {
+    pub first: String,
+    pub second: String,
+    pub type_name: &'static str,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ScheduleError {
+    DuplicateSystem(String),
+    UnknownSystem(String),
+    Cycle(Vec<String>),
+    UnorderedConflict(AccessConflict),
+}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleError::DuplicateSystem(name) => write!(f, "system \"{}\" is allready part of the schedule", name),
+            ScheduleError::UnknownSystem(name) => write!(f, "ordering refers to unknown system \"{}\"", name),
+            ScheduleError::Cycle(names) => write!(f, "ordering constraints form a cycle between {:?}", names),
+            ScheduleError::UnorderedConflict(conflict) => write!(f, "systems \"{}\" and \"{}\" conflict in their access to {} but are not ordered by before or after", conflict.first, conflict.second, conflict.type_name),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+/**
+ * Runs systems on the runtime, systems that do not conflict in their access run at the same time.
+ * Systems are added with add_system, the schedule is then build once with build and run every step with run.
+ * Conflicting systems have to be ordered by constraints, so every run executes them in the same order.
+ */
+pub struct Schedule<Ctx> {
+    systems: Vec<System<Ctx>>,
+    conflicts: Vec<AccessConflict>,
+    successors: Vec<Vec<usize>>,
+    predecessor_counts: Vec<usize>,
+    built: bool,
+}
+
+impl<Ctx: Send + Sync + 'static> Default for Schedule<Ctx> {
+    fn default() -> Self {
+        Self{
+            systems: Vec::new(),
+            conflicts: Vec::new(),
+            successors: Vec::new(),
+            predecessor_counts: Vec::new(),
+            built: false,
+        }
+    }
+}
+
+impl<Ctx: Send + Sync + 'static> Schedule<Ctx> {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Adds a system and records its access conflicts with the allready added systems.
+     * Every conflict has to be ordered by the before and after constraints of the systems added so far, directly or through other systems,
+     * otherwise the system is rejected with ScheduleError::UnorderedConflict.
+     */
+    #[allow(unused)]
+    pub fn add_system(&mut self, system: System<Ctx>) -> Result<(), ScheduleError> {
+        if self.systems.iter().any(|s| s.name == system.name) {
+            return Err(ScheduleError::DuplicateSystem(system.name));
+        }
+        let mut known = self.systems.iter().collect::<Vec<_>>();
+        known.push(&system);
+        let successors = known_successors(&known);
+        let added = self.systems.len();
+
+        let mut conflicts = Vec::new();
+        for (i, other) in self.systems.iter().enumerate() {
+            if let Some(type_name) = other.conflict_with(&system) {
+                let conflict = AccessConflict{ first: other.name.clone(), second: system.name.clone(), type_name };
+                if !reaches(&successors, i, added) && !reaches(&successors, added, i) {
+                    return Err(ScheduleError::UnorderedConflict(conflict));
+                }
+                conflicts.push(conflict);
+            }
+        }
+        self.conflicts.append(&mut conflicts);
+        self.systems.push(system);
+        self.built = false;
+        Ok(())
+    }
+
+    /**
+     * All access conflicts found while adding systems, in the order they were found.
+     */
+    #[allow(unused)]
+    pub fn conflicts(&self) -> &[AccessConflict] {
+        &self.conflicts
+    }
+
+    /**
+     * Resolves the ordering constraints, has to be called after adding systems and before running.
+     */
+    #[allow(unused)]
+    pub fn build(&mut self) -> Result<(), ScheduleError> {
+        let n = self.systems.len();
+        let index_of = |name: &str| {
+            self.systems.iter()
+                .position(|s| s.name == name)
+                .ok_or_else(|| ScheduleError::UnknownSystem(String::from(name)))
+        };
+
+        let mut successors = vec![Vec::<usize>::new(); n];
+        for (i, system) in self.systems.iter().enumerate() {
+            for before in &system.before {
+                successors[i].push(index_of(before)?);
+            }
+            for after in &system.after {
+                successors[index_of(after)?].push(i);
+            }
+        }
+        if let Some(cycle) = find_cycle(&successors) {
+            return Err(ScheduleError::Cycle(cycle.into_iter().map(|i| self.systems[i].name.clone()).collect()));
+        }
+
+        // add_system made sure conflicting systems are connected by the constraints, so they never run at the same time
+        let mut predecessor_counts = vec![0; n];
+        for list in successors.iter_mut() {
+            list.sort_unstable();
+            list.dedup();
+            for s in list.iter() {
+                predecessor_counts[*s] += 1;
+            }
+        }
+        self.successors = successors;
+        self.predecessor_counts = predecessor_counts;
+        self.built = true;
+        Ok(())
+    }
+
+    /**
+     * Runs every system once, returns after all of them finished.
+     * Systems are spawned as soon as all systems they have to wait for finished.
+     * Panics if a system panicked or its future was dropped before it finished, after the systems already running are done.
+     * Systems waiting on a failed system are not started.
+     */
+    #[allow(unused)]
+    pub async fn run(&self, runtime: &Runtime, ctx: Arc<Ctx>) {
+        assert!(self.built, "Schedule::build has to be called after adding systems and before running the schedule.");
+        let (done_snd, done_rcv) = async_std::channel::unbounded::<(usize, bool)>();
+        let mut remaining = self.predecessor_counts.clone();
+
+        let spawn = |i: usize| {
+            let future = (self.systems[i].run)(ctx.clone());
+            let completion = Completion{ index: i, sender: done_snd.clone(), finished: false };
+            runtime.spawn(async move {
+                // move the whole guard into the task, it reports when the task drops it
+                let mut completion = completion;
+                future.await;
+                completion.finished = true;
+            });
+        };
+
+        let mut running = 0;
+        for (i, count) in remaining.iter().enumerate() {
+            if *count == 0 {
+                spawn(i);
+                running += 1;
+            }
+        }
+        let mut failed = None;
+        while running > 0 {
+            let (finished, ok) = done_rcv.recv().await.unwrap();
+            running -= 1;
+            if !ok {
+                failed.get_or_insert(finished);
+            }
+            if failed.is_some() {
+                continue;
+            }
+            for successor in &self.successors[finished] {
+                remaining[*successor] -= 1;
+                if remaining[*successor] == 0 {
+                    spawn(*successor);
+                    running += 1;
+                }
+            }
+        }
+        if let Some(i) = failed {
+            panic!("System \"{}\" panicked or was dropped before it finished.", self.systems[i].name);
+        }
+    }
+}
+
+/**
+ * Reports the end of a spawned system when the task drops it, also if the system panicked or the task was dropped early.
+ */
+struct Completion {
+    index: usize,
+    sender: async_std::channel::Sender<(usize, bool)>,
+    finished: bool,
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        let _ = self.sender.try_send((self.index, self.finished));
+    }
+}
+
+/**
+ * Successors of the systems by their before and after constraints, constraints on systems that are not known yet are skipped.
+ */
+fn known_successors<Ctx>(systems: &[&System<Ctx>]) -> Vec<Vec<usize>> {
+    let index_of = |name: &String| systems.iter().position(|s| s.name == *name);
+    let mut successors = vec![Vec::<usize>::new(); systems.len()];
+    for (i, system) in systems.iter().enumerate() {
+        successors[i].extend(system.before.iter().filter_map(index_of));
+        for after in system.after.iter().filter_map(index_of) {
+            successors[after].push(i);
+        }
+    }
+    successors
+}
+
+fn reaches(successors: &[Vec<usize>], from: usize, to: usize) -> bool {
+    let mut visited = vec![false; successors.len()];
+    let mut stack = vec![from];
+    while let Some(i) = stack.pop() {
+        if i == to {
+            return true;
+        }
+        if !visited[i] {
+            visited[i] = true;
+            stack.extend(successors[i].iter().copied());
+        }
+    }
+    false
+}
+
+fn find_cycle(successors: &[Vec<usize>]) -> Option<Vec<usize>> {
+    // 0 = unvisited, 1 = on the current path, 2 = done
+    let mut state = vec![0u8; successors.len()];
+    let mut path = Vec::new();
+
+    fn visit(i: usize, successors: &[Vec<usize>], state: &mut Vec<u8>, path: &mut Vec<usize>) -> Option<Vec<usize>> {
+        state[i] = 1;
+        path.push(i);
+        for s in &successors[i] {
+            match state[*s] {
+                0 => {
+                    if let Some(cycle) = visit(*s, successors, state, path) {
+                        return Some(cycle);
+                    }
+                },
+                1 => {
+                    let start = path.iter().position(|p| p == s).unwrap();
+                    return Some(path[start..].to_vec());
+                },
+                _ => {},
+            }
+        }
+        path.pop();
+        state[i] = 2;
+        None
+    }
+
+    for i in 0..successors.len() {
+        if state[i] == 0 {
+            if let Some(cycle) = visit(i, successors, &mut state, &mut path) {
+                return Some(cycle);
+            }
+        }
+    }
+    None
+}
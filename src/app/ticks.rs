@@ -5,6 +5,12 @@ use super::*;
 
 pub(crate) struct FixedStepUpdateSignal;
 
+#[derive(Clone, Copy)]
+pub(crate) struct RollbackRequest {
+    pub(crate) tick: u64,
+    pub(crate) resimulate: bool,
+}
+
 pub(crate) fn fixed_time_step_notify(
     shared_data: Arc<SharedAppData>, 
     fixed_step_data: Arc<FixedStepData>,
@@ -38,6 +44,8 @@ pub(crate) async fn fixed_loop<T: User>(
 }
 
 async fn fixed_tick<T: User>(shared_data: Arc<SharedAppData>, fixed_step_data: Arc<FixedStepData>, variable_step_data: Arc<VariableStepData>, user: Arc<T>) {
+    // a rollback of the variable step holds the lock while it resimulates, the step waits for it
+    let _step = fixed_step_data.step_lock.lock().await;
     {
         let input_state_varstep = &mut*variable_step_data.input_state_frontbuffer.lock().await;
         let input_state_fixedstep = &mut*fixed_step_data.input_state.lock().await;
@@ -48,10 +56,71 @@ async fn fixed_tick<T: User>(shared_data: Arc<SharedAppData>, fixed_step_data: A
         }
     }
 
-    user.fixed_step(shared_data, fixed_step_data).await;
+    record_frame(&fixed_step_data).await;
+    run_fixed_step(shared_data, fixed_step_data.clone(), user).await;
+}
+
+async fn run_fixed_step<T: User>(shared_data: Arc<SharedAppData>, fixed_step_data: Arc<FixedStepData>, user: Arc<T>) {
+    user.fixed_step(shared_data, fixed_step_data.clone()).await;
+    fixed_step_data.ecm.cleanup().await;
+    fixed_step_data.ecm.update_events().await;
+    fixed_step_data.ecm.advance_tick().await;
+    fixed_step_data.tick.fetch_add(1, Ordering::Relaxed);
+}
+
+async fn record_frame(fixed_step_data: &FixedStepData) {
+    if fixed_step_data.rollback.lock().await.capacity() == 0 {
+        return;
+    }
+    let frame = RollbackFrame{
+        tick: fixed_step_data.fixed_tick(),
+        world: fixed_step_data.ecm.save_frame().await,
+        input: fixed_step_data.input_state.lock().await.clone(),
+    };
+    fixed_step_data.rollback.lock().await.push(frame);
+}
+
+/**
+ * Restores the world of the requested tick, when resimulating all recorded steps since are run again.
+ * The live input of the fixed step is kept aside and put back afterwards.
+ * Called by the variable step while it holds the step lock, so no fixed step runs on the world in between.
+ */
+async fn roll_back<T: User>(request: RollbackRequest, shared_data: Arc<SharedAppData>, fixed_step_data: Arc<FixedStepData>, user: Arc<T>) {
+    let now = fixed_step_data.fixed_tick();
+    let inputs = {
+        let rollback = fixed_step_data.rollback.lock().await;
+        let frame = match rollback.get(request.tick) {
+            Some(frame) => frame,
+            None => return,
+        };
+        fixed_step_data.ecm.restore_frame(&frame.world).await;
+        rollback.iter_from(request.tick).map(|frame| frame.input.clone()).collect::<Vec<_>>()
+    };
+    fixed_step_data.tick.store(request.tick, Ordering::Relaxed);
+    if !request.resimulate {
+        fixed_step_data.rollback.lock().await.truncate(request.tick);
+        return;
+    }
+
+    let live_input = fixed_step_data.input_state.lock().await.clone();
+    let mut previous_keys = None;
+    for mut input in inputs.into_iter().take((now - request.tick) as usize) {
+        if let Some(previous_keys) = previous_keys {
+            input.key_states_old = previous_keys;
+        }
+        previous_keys = Some(input.key_states.clone());
+        *fixed_step_data.input_state.lock().await = input;
+        record_frame(&fixed_step_data).await;
+        run_fixed_step(shared_data.clone(), fixed_step_data.clone(), user.clone()).await;
+    }
+    let mut input_state = fixed_step_data.input_state.lock().await;
+    *input_state = live_input;
+    if let Some(previous_keys) = previous_keys {
+        input_state.key_states_old = previous_keys;
+    }
 }
 
-pub(crate) async fn vary_tick<T: User>(shared_data: Arc<SharedAppData>, variable_step_data: Arc<VariableStepData>, user: Arc<T>) {
+pub(crate) async fn vary_tick<T: User>(shared_data: Arc<SharedAppData>, fixed_step_data: Arc<FixedStepData>, variable_step_data: Arc<VariableStepData>, user: Arc<T>) {
     {
         profiling::scope!("vary_tick before user");
     }
@@ -65,6 +134,13 @@ pub(crate) async fn vary_tick<T: User>(shared_data: Arc<SharedAppData>, variable
         }
     }
 
+    // requested rollbacks are done before the user sees the world, the fixed loop waits meanwhile
+    let request = fixed_step_data.rollback_request.lock().await.take();
+    if let Some(request) = request {
+        let _step = fixed_step_data.step_lock.lock().await;
+        roll_back(request, shared_data.clone(), fixed_step_data.clone(), user.clone()).await;
+    }
+
     user.varaible_step(shared_data, variable_step_data.clone()).await;
     variable_step_data.renderer.render().await.unwrap();
      
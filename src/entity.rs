@@ -3,13 +3,30 @@ pub mod entity_manager;
 pub mod component_storage;
 pub mod component_manager;
 pub mod iteration;
+pub mod snapshot;
+pub mod hierarchy;
+pub mod commands;
+pub mod query;
+pub mod events;
+pub mod prefab;
+pub mod bundle;
+pub mod index;
+pub mod spatial;
+pub mod hooks;
+pub mod world;
+pub mod delta;
+pub mod rollback;
+pub mod reflect;
+pub mod diagnostics;
 mod default_components;
 
 #[allow(unused)]
 pub use handle::{EntityHandle};
 #[allow(unused)]
-pub use component_storage::{DenseStore, LinearStore, Component};
-pub(crate) use component_storage::{GenericComponentStore, ComponentStore};
+pub use component_storage::{DenseStore, LinearStore, TagStore, BitSet, Component, LifecycleEvent, LINEAR_STORE_PAGE_SIZE};
+#[allow(unused)]
+pub use component_storage::{DynamicStore, DynamicComponentId, DynamicLayout, DynamicField, DynamicRow, FieldKind, FieldValue, LayoutError};
+pub use component_storage::{GenericComponentStore, ComponentStore, ComponentStoreAccessor, StoreMemory};
 #[allow(unused)]
 pub use component_manager::{EntityComponentManager};
 #[allow(unused)]
@@ -17,4 +34,34 @@ pub use entity_manager::{EntityManager};
 #[allow(unused)]
 pub use iteration::*;
 #[allow(unused)]
+pub use snapshot::{SnapshotComponent, SnapshotWriter, SnapshotReader, SnapshotError};
+#[allow(unused)]
+pub use hierarchy::{Parent, Children, GlobalTransform, propagate_transforms};
+#[allow(unused)]
+pub use commands::{Commands, AnyComponent};
+#[allow(unused)]
+pub use query::{Query, With, Without, Added, Changed};
+#[allow(unused)]
+pub use events::{Events, EventReader};
+#[allow(unused)]
+pub use prefab::{Prefab, Overrides};
+#[allow(unused)]
+pub use bundle::{Bundle, BundleStores};
+#[allow(unused)]
+pub use index::{ComponentIndex, HashIndex, OrderedIndex, IndexLookup};
+#[allow(unused)]
+pub use spatial::{SpatialGrid, SpatialExtent};
+#[allow(unused)]
+pub use hooks::{HookContext, ComponentHooks};
+#[allow(unused)]
+pub use world::{EntityMapping, Worlds};
+#[allow(unused)]
+pub use delta::{WorldState, WorldDelta, ComponentDelta};
+#[allow(unused)]
+pub use rollback::{WorldFrame, RollbackFrame, RollbackBuffer};
+#[allow(unused)]
+pub use reflect::{Reflect, ReflectValue, ReflectError, TypeInfo, FieldInfo};
+#[allow(unused)]
+pub use diagnostics::{WorldDiagnostics, StoreDiagnostics};
+#[allow(unused)]
 pub use default_components::*;
\ No newline at end of file
@@ -0,0 +1,214 @@
+use std::any::TypeId;
+use std::future::Future;
+use std::ops::DerefMut;
+use std::pin::Pin;
+
+use crate::entity::handle::*;
+use crate::entity::entity_manager::*;
+use crate::entity::component_storage::*;
+use crate::entity::component_manager::*;
+
+/**
+ * A group of components that is added to and removed from entities at once.
+ * Implemented for tuples of up to eight components, structs implement it with impl_bundle!.
+ * Every component type may appear only once in a bundle.
+ */
+pub trait Bundle: Send + Sync + 'static {
+    /**
+     * The components of the bundle, in the order they are listed.
+     */
+    fn components() -> Vec<BundleComponent>;
+
+    fn insert(self, stores: &mut BundleStores, index: EntityIndex);
+
+    fn remove(stores: &mut BundleStores, index: EntityIndex);
+}
+
+pub type StoreLockFuture<'a> = Pin<Box<dyn Future<Output = Box<dyn LockedStore>> + Send + 'a>>;
+
+/**
+ * Type information of one component of a bundle, together with the function that locks its store.
+ */
+#[derive(Clone, Copy)]
+pub struct BundleComponent {
+    pub type_id: TypeId,
+    pub type_name: &'static str,
+    lock: for<'a> fn(&'a EntityComponentManager) -> StoreLockFuture<'a>,
+}
+
+impl BundleComponent {
+    #[allow(unused)]
+    pub fn of<C: Component>() -> Self {
+        Self{
+            type_id: TypeId::of::<C>(),
+            type_name: std::any::type_name::<C>(),
+            lock: lock_store::<C>,
+        }
+    }
+}
+
+fn lock_store<C: Component>(ecm: &EntityComponentManager) -> StoreLockFuture<'_> {
+    Box::pin(async move {
+        let guard = ecm.get_store::<C>().await.write_arc().await;
+        Box::new(guard) as Box<dyn LockedStore>
+    })
+}
+
+/**
+ * Write guard of a store with its type erased.
+ */
+pub trait LockedStore: Send + Sync {
+    fn store(&mut self) -> &mut dyn GenericComponentStore;
+}
+
+impl<S: GenericComponentStore + Send + Sync + 'static, G: DerefMut<Target = S> + Send + Sync> LockedStore for G {
+    fn store(&mut self) -> &mut dyn GenericComponentStore {
+        &mut **self
+    }
+}
+
+/**
+ * Exclusive access to the stores of a set of component types.
+ * The stores are locked in the order of their TypeId, so locking the stores of different bundles at the same time can not deadlock.
+ */
+pub struct BundleStores {
+    stores: Vec<(TypeId, Box<dyn LockedStore>)>,
+}
+
+impl BundleStores {
+    /**
+     * Locks the stores of the components, in the order of their TypeId.
+     * Stores of components that are not registered yet are registered.
+     */
+    pub(crate) async fn lock(ecm: &EntityComponentManager, mut components: Vec<BundleComponent>) -> Self {
+        components.sort_by_key(|c| c.type_id);
+        components.dedup_by_key(|c| c.type_id);
+        let mut stores = Vec::with_capacity(components.len());
+        for component in components {
+            stores.push((component.type_id, (component.lock)(ecm).await));
+        }
+        Self{ stores }
+    }
+
+    #[allow(unused)]
+    pub fn contains<C: Component>(&self) -> bool {
+        self.stores.binary_search_by_key(&TypeId::of::<C>(), |(type_id, _)| *type_id).is_ok()
+    }
+
+    /**
+     * Panics if the store of C is not part of the locked stores.
+     */
+    #[allow(unused)]
+    pub fn get<C: Component>(&mut self) -> &mut C::Storage {
+        let i = self.stores.binary_search_by_key(&TypeId::of::<C>(), |(type_id, _)| *type_id)
+            .unwrap_or_else(|_| panic!("store of {} is not locked", std::any::type_name::<C>()));
+        self.stores[i].1.store().as_any_mut().downcast_mut::<C::Storage>().unwrap()
+    }
+}
+
+impl EntityManager {
+    /**
+     * Creates an entity with all components of the bundle.
+     */
+    #[allow(unused)]
+    pub fn spawn_bundle<B: Bundle>(&mut self, stores: &mut BundleStores, bundle: B) -> EntityHandle {
+        let entity = self.create();
+        bundle.insert(stores, entity.index);
+        entity
+    }
+
+    /**
+     * Adds all components of the bundle, components the entity allready has are replaced.
+     */
+    #[allow(unused)]
+    pub fn insert_bundle<B: Bundle>(&self, stores: &mut BundleStores, bundle: B, entity: EntityHandle) {
+        assert!(self.exists(entity));
+        bundle.insert(stores, entity.index);
+    }
+
+    /**
+     * Removes the components of the bundle the entity has.
+     */
+    #[allow(unused)]
+    pub fn remove_bundle<B: Bundle>(&self, stores: &mut BundleStores, entity: EntityHandle) {
+        assert!(self.exists(entity));
+        B::remove(stores, entity.index);
+    }
+}
+
+/**
+ * Adds the value to the entity, replacing the value the entity allready has.
+ * Used by Bundle implementations.
+ */
+#[allow(unused)]
+pub fn insert_bundle_component<C: Component>(stores: &mut BundleStores, index: EntityIndex, value: C) {
+    let store = stores.get::<C>();
+    if store.has(index) {
+        store.set(index, value);
+    } else {
+        store.add(index, value);
+    }
+}
+
+/**
+ * Removes the component from the entity, if it has it.
+ * Used by Bundle implementations.
+ */
+#[allow(unused)]
+pub fn remove_bundle_component<C: Component>(stores: &mut BundleStores, index: EntityIndex) {
+    let store = stores.get::<C>();
+    if store.has(index) {
+        store.rem(index);
+    }
+}
+
+macro_rules! impl_bundle_for_tuple {
+    ($($C:ident $i:tt),+) => {
+        impl<$($C: Component),+> Bundle for ($($C,)+) {
+            fn components() -> Vec<BundleComponent> {
+                vec![$(BundleComponent::of::<$C>()),+]
+            }
+
+            fn insert(self, stores: &mut BundleStores, index: EntityIndex) {
+                $(insert_bundle_component(stores, index, self.$i);)+
+            }
+
+            fn remove(stores: &mut BundleStores, index: EntityIndex) {
+                $(remove_bundle_component::<$C>(stores, index);)+
+            }
+        }
+    };
+}
+
+impl_bundle_for_tuple!(A 0);
+impl_bundle_for_tuple!(A 0, B 1);
+impl_bundle_for_tuple!(A 0, B 1, C 2);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/**
+ * Implements Bundle for a struct whose fields are components.
+ * syntax: impl_bundle!(StructName { field_name: ComponentType, ... });
+ */
+#[allow(unused)]
+#[macro_export]
+macro_rules! impl_bundle {
+    ($Bundle:ty { $($field:ident : $C:ty),+ $(,)? }) => {
+        impl eisen::entity::bundle::Bundle for $Bundle {
+            fn components() -> Vec<eisen::entity::bundle::BundleComponent> {
+                vec![$(eisen::entity::bundle::BundleComponent::of::<$C>()),+]
+            }
+
+            fn insert(self, stores: &mut eisen::entity::bundle::BundleStores, index: eisen::entity::handle::EntityIndex) {
+                $(eisen::entity::bundle::insert_bundle_component::<$C>(stores, index, self.$field);)+
+            }
+
+            fn remove(stores: &mut eisen::entity::bundle::BundleStores, index: eisen::entity::handle::EntityIndex) {
+                $(eisen::entity::bundle::remove_bundle_component::<$C>(stores, index);)+
+            }
+        }
+    };
+}
@@ -0,0 +1,198 @@
+use std::any::{Any, TypeId};
+use std::sync::Mutex;
+
+use crate::entity::handle::*;
+use crate::entity::entity_manager::*;
+use crate::entity::component_storage::*;
+
+/**
+ * A component value with its type erased.
+ * Knows how to create the store of its type and how to insert itself into it.
+ */
+pub trait AnyComponent: Send + Sync {
+    fn component_type(&self) -> TypeId;
+
+    fn make_store(&self, context: &StoreContext) -> Box<dyn ComponentStoreAccessor + Sync + Send>;
+
+    /**
+     * Adds the value to the entity, replacing the value the entity allready has.
+     * store must be the store of component_type.
+     */
+    fn insert_into(self: Box<Self>, store: &mut dyn GenericComponentStore, index: EntityIndex);
+
+    fn clone_box(&self) -> Box<dyn AnyComponent>;
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<C: Component> AnyComponent for C {
+    fn component_type(&self) -> TypeId {
+        TypeId::of::<C>()
+    }
+
+    fn make_store(&self, context: &StoreContext) -> Box<dyn ComponentStoreAccessor + Sync + Send> {
+        context.register_cloner::<C>();
+        make_store_accessor(<C::Storage as ComponentStore<C>>::new_in(context))
+    }
+
+    fn insert_into(self: Box<Self>, store: &mut dyn GenericComponentStore, index: EntityIndex) {
+        let store = store.as_any_mut().downcast_mut::<C::Storage>().unwrap();
+        if store.has(index) {
+            store.set(index, *self);
+        } else {
+            store.add(index, *self);
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn AnyComponent> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub(crate) enum Command {
+    Destroy(EntityHandle),
+    Add(EntityHandle, Box<dyn AnyComponent>),
+    Rem(EntityHandle, TypeId),
+}
+
+/**
+ * Thread safe buffer of structural changes.
+ * Can be filled while the EntityManager and stores are borrowed, for example from within parallel_over_entities.
+ * The commands are applied in the order they were recorded by EntityComponentManager::apply_commands,
+ * which also runs at the beginning of EntityComponentManager::cleanup.
+ * Commands targeting entities that do not exist anymore when they are applied are skipped.
+ */
+pub struct Commands {
+    queue: Mutex<Vec<Command>>,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Self{
+            queue: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Commands {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Reserves a new entity, it exists after the commands are applied.
+     * Components can be added to the returned handle right away.
+     */
+    #[allow(unused)]
+    pub fn create(&self, entities: &EntityManager) -> EntityHandle {
+        entities.reserve()
+    }
+
+    #[allow(unused)]
+    pub fn destroy(&self, entity: EntityHandle) {
+        self.push(Command::Destroy(entity));
+    }
+
+    /**
+     * Adds the component to the entity, replacing the value the entity allready has.
+     */
+    #[allow(unused)]
+    pub fn add<C: Component>(&self, entity: EntityHandle, value: C) {
+        self.push(Command::Add(entity, Box::new(value)));
+    }
+
+    /**
+     * Adds a type erased component to the entity, replacing the value the entity allready has.
+     */
+    #[allow(unused)]
+    pub fn add_boxed(&self, entity: EntityHandle, value: Box<dyn AnyComponent>) {
+        self.push(Command::Add(entity, value));
+    }
+
+    /**
+     * Removes the component from the entity, if it has it.
+     */
+    #[allow(unused)]
+    pub fn rem<C: Component>(&self, entity: EntityHandle) {
+        self.push(Command::Rem(entity, TypeId::of::<C>()));
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(&self, command: Command) {
+        self.queue.lock().unwrap().push(command);
+    }
+
+    pub(crate) fn take(&self) -> Vec<Command> {
+        std::mem::take(&mut *self.queue.lock().unwrap())
+    }
+}
+
+/**
+ * Applies the commands in order.
+ * Stores of components that are not registered yet are created on the fly.
+ */
+pub(crate) fn apply_commands(commands: Vec<Command>, entities: &mut EntityManager, stores: &mut StoreMap, context: &StoreContext, tick: Tick) {
+    entities.flush_reserved();
+    for command in commands {
+        match command {
+            Command::Destroy(entity) => {
+                if entities.exists(entity) {
+                    entities.destroy(entity);
+                }
+            },
+            Command::Add(entity, value) => {
+                if entities.exists(entity) {
+                    insert_component(stores, context, tick, entity.index, value);
+                }
+            },
+            Command::Rem(entity, type_id) => {
+                if !entities.exists(entity) {
+                    continue;
+                }
+                if let Some(store) = stores.get(&type_id) {
+                    store.exec(&mut |store: &mut dyn GenericComponentStore| {
+                        if store.has(entity.index) {
+                            store.rem(entity.index);
+                        }
+                    });
+                }
+            },
+        }
+    }
+}
+
+/**
+ * Adds a type erased component to the entity, replacing the value the entity allready has.
+ * The store of the component is created, if it is not registered yet.
+ */
+pub(crate) fn insert_component(stores: &mut StoreMap, context: &StoreContext, tick: Tick, index: EntityIndex, value: Box<dyn AnyComponent>) {
+    let store = stores.entry(value.component_type()).or_insert_with(|| {
+        let store = value.make_store(context);
+        store.exec(&mut |store: &mut dyn GenericComponentStore| store.set_change_tick(tick));
+        store
+    });
+    let mut value = Some(value);
+    store.exec(&mut |store: &mut dyn GenericComponentStore| {
+        value.take().unwrap().insert_into(store, index);
+    });
+}
@@ -3,20 +3,62 @@
 use async_std::sync::{RwLock};
 use std::any::*;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::ops::{Deref, DerefMut};
 
 use crate::entity::entity_manager::*;
 use crate::entity::component_storage::*;
+use crate::entity::snapshot::*;
+use crate::entity::hierarchy::*;
+use crate::entity::commands::*;
+use crate::entity::events::*;
+use crate::entity::prefab::*;
+use crate::entity::bundle::*;
+use crate::entity::index::*;
+use crate::entity::hooks::*;
+use crate::entity::world::*;
+use crate::entity::delta::*;
+use crate::entity::rollback::*;
+use crate::entity::reflect::*;
+use crate::entity::diagnostics::*;
+use crate::entity::handle::*;
 
 pub struct EntityComponentManager {
     pub entities: Arc<RwLock<EntityManager>>,
-    stores: RwLock<rustc_hash::FxHashMap<TypeId, Box<dyn ComponentStoreAccessor + Sync + Send>>>,
+    pub commands: Commands,
+    stores: RwLock<StoreMap>,
+    store_context: StoreContext,
+    serializers: RwLock<Vec<ComponentSerializer>>,
+    change_tick: AtomicU32,
+    resources: RwLock<rustc_hash::FxHashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+    events: RwLock<rustc_hash::FxHashMap<TypeId, (Arc<dyn EventQueue>, Arc<dyn Any + Send + Sync>)>>,
+    indices: RwLock<Vec<Arc<dyn IndexMaintainer>>>,
+    hooks: RwLock<rustc_hash::FxHashMap<TypeId, Box<dyn LifecycleHooks>>>,
+    replicated_sequence: AtomicU64,
+    reflection: RwLock<TypeRegistry>,
+    dynamic_stores: RwLock<Vec<Arc<RwLock<DynamicStore>>>>,
+    rollback_resources: RwLock<Vec<RollbackCloner>>,
+    rollback_events: RwLock<Vec<RollbackCloner>>,
 }
 
 impl Default for EntityComponentManager {
     fn default() -> Self {
         Self{
             entities: Arc::new(RwLock::new(EntityManager::new())),
-            stores: RwLock::new(rustc_hash::FxHashMap::default()),
+            commands: Commands::new(),
+            stores: RwLock::new(StoreMap::default()),
+            store_context: StoreContext::default(),
+            serializers: RwLock::new(Vec::new()),
+            change_tick: AtomicU32::new(0),
+            resources: RwLock::new(rustc_hash::FxHashMap::default()),
+            events: RwLock::new(rustc_hash::FxHashMap::default()),
+            indices: RwLock::new(Vec::new()),
+            hooks: RwLock::new(rustc_hash::FxHashMap::default()),
+            replicated_sequence: AtomicU64::new(0),
+            reflection: RwLock::new(TypeRegistry::default()),
+            dynamic_stores: RwLock::new(Vec::new()),
+            rollback_resources: RwLock::new(Vec::new()),
+            rollback_events: RwLock::new(Vec::new()),
         }
     }
 }
@@ -30,16 +72,21 @@ impl EntityComponentManager {
 
     #[allow(unused)]
     pub async fn register_component<T: 'static + Default + Clone + Component>(&self) {
+        self.register_component_with_store::<T>(<T::Storage as ComponentStore<T>>::new_in(&self.store_context)).await;
+    }
+
+    /**
+     * Registers a component with an allready constructed store.
+     * Use this for stores that need configuration beyond ComponentStore::new.
+     */
+    #[allow(unused)]
+    pub async fn register_component_with_store<T: 'static + Default + Clone + Component>(&self, mut store: T::Storage) {
         let type_id =  TypeId::of::<T>();
+        store.set_change_tick(self.change_tick());
         let mut stores = self.stores.write().await;
         assert!(!stores.contains_key(&type_id), "Can not register Component multiple times.");
-        if TypeId::of::<T::Storage>() == TypeId::of::<LinearStore<T>>() {
-            stores.insert(type_id, Box::new(Arc::new(RwLock::new(LinearStore::<T>::new()))));
-        }else if TypeId::of::<T::Storage>() == TypeId::of::<DenseStore<T>>() {
-            stores.insert(type_id, Box::new(Arc::new(RwLock::new(DenseStore::<T>::new()))));
-        } else {
-            assert!(false, "missing arm in register component function");
-        }
+        self.store_context.register_cloner::<T>();
+        stores.insert(type_id, make_store_accessor(store));
     }
 
     #[allow(unused)]
@@ -52,23 +99,393 @@ impl EntityComponentManager {
         self.stores.read().await.get(&&type_id).unwrap().as_any_ref().downcast_ref::<Arc<RwLock<C::Storage>>>().unwrap().clone()
     }
 
+    /**
+     * Registers a component whose layout is only known at run time, for example read from a mod or a data file.
+     * Dynamic components are removed with their entities and are part of rollback frames,
+     * they are not copied between worlds, snapshotted or reflected.
+     */
+    #[allow(unused)]
+    pub async fn register_dynamic_component(&self, layout: DynamicLayout) -> DynamicComponentId {
+        let mut dynamic_stores = self.dynamic_stores.write().await;
+        for store in dynamic_stores.iter() {
+            assert!(store.read().await.layout().name() != layout.name(), "Dynamic component \"{}\" is allready registered.", layout.name());
+        }
+        let mut store = DynamicStore::new(Arc::new(layout));
+        store.set_change_tick(self.change_tick());
+        dynamic_stores.push(Arc::new(RwLock::new(store)));
+        DynamicComponentId(dynamic_stores.len() as u32 - 1)
+    }
+
+    /**
+     * Looks up a dynamic component by the name of its layout.
+     */
+    #[allow(unused)]
+    pub async fn dynamic_component(&self, name: &str) -> Option<DynamicComponentId> {
+        for (id, store) in self.dynamic_stores.read().await.iter().enumerate() {
+            if store.read().await.layout().name() == name {
+                return Some(DynamicComponentId(id as u32));
+            }
+        }
+        None
+    }
+
+    #[allow(unused)]
+    pub async fn get_dynamic_store(&self, id: DynamicComponentId) -> Arc<RwLock<DynamicStore>> {
+        self.dynamic_stores.read().await[id.0 as usize].clone()
+    }
+
+    #[allow(unused)]
+    pub fn change_tick(&self) -> Tick {
+        self.change_tick.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Starts a new change tick in every store.
+     * Changes stay visible to added, changed and removed queries for the tick they happened in and the following one.
+     * Called by the app after every fixed step.
+     */
+    #[allow(unused)]
+    pub async fn advance_tick(&self) {
+        let stores = self.stores.read().await;
+        let tick = self.change_tick.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        for (_, store) in stores.iter() {
+            store.exec(&mut |store: &mut dyn GenericComponentStore| store.set_change_tick(tick));
+        }
+        for store in self.dynamic_stores.read().await.iter() {
+            store.write().await.set_change_tick(tick);
+        }
+    }
+
+    /**
+     * Inserts a global singleton, replacing and returning the previous value of the same type.
+     */
+    #[allow(unused)]
+    pub async fn insert_resource<R: Send + Sync + 'static>(&self, value: R) -> Option<R> {
+        // decide between insert and replace under one write lock, so concurrent first inserts can not both insert
+        let existing = match self.resources.write().await.entry(TypeId::of::<R>()) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.get().clone(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(Arc::new(RwLock::new(value)));
+                return None;
+            },
+        };
+        // the map lock is released before waiting for the resource, holders of a guard may still look up other resources
+        let resource = existing.downcast::<RwLock<R>>().unwrap();
+        let mut guard = resource.write().await;
+        Some(std::mem::replace(&mut *guard, value))
+    }
+
+    /**
+     * Removes the resource, returns false if it did not exist.
+     * Guards that are still held keep the value alive until they are dropped.
+     */
+    #[allow(unused)]
+    pub async fn remove_resource<R: Send + Sync + 'static>(&self) -> bool {
+        self.resources.write().await.remove(&TypeId::of::<R>()).is_some()
+    }
+
+    #[allow(unused)]
+    pub async fn has_resource<R: Send + Sync + 'static>(&self) -> bool {
+        self.resources.read().await.contains_key(&TypeId::of::<R>())
+    }
+
+    /**
+     * Get shared access to a resource, waits while it is accessed exclusively.
+     * Panics if the resource was not inserted.
+     */
+    #[allow(unused)]
+    pub async fn get_resource<R: Send + Sync + 'static>(&self) -> impl Deref<Target = R> {
+        self.resource_lock::<R>().await.read_arc().await
+    }
+
+    /**
+     * Get exclusive access to a resource, waits while it is accessed.
+     * Panics if the resource was not inserted.
+     */
+    #[allow(unused)]
+    pub async fn get_resource_mut<R: Send + Sync + 'static>(&self) -> impl DerefMut<Target = R> {
+        self.resource_lock::<R>().await.write_arc().await
+    }
+
+    async fn resource_lock<R: Send + Sync + 'static>(&self) -> Arc<RwLock<R>> {
+        self.resources.read().await
+            .get(&TypeId::of::<R>())
+            .unwrap_or_else(|| panic!("Resource {} was not inserted.", std::any::type_name::<R>()))
+            .clone()
+            .downcast::<RwLock<R>>()
+            .unwrap()
+    }
+
+    /**
+     * Makes the resource part of rollback frames, save_frame clones it and restore_frame puts the clone back.
+     * A resource that was not inserted when the frame was saved is removed on restore.
+     * Resources that are not registered keep their value when a frame is restored.
+     */
+    #[allow(unused)]
+    pub async fn register_rollback_resource<R: Clone + Send + Sync + 'static>(&self) {
+        let mut resources = self.rollback_resources.write().await;
+        if resources.iter().all(|cloner| cloner.type_id != TypeId::of::<R>()) {
+            resources.push(RollbackCloner{ type_id: TypeId::of::<R>(), save: save_resource::<R>, restore: restore_resource::<R> });
+        }
+    }
+
+    /**
+     * Get the event queue of type E, it is created on first use.
+     * The queue can be kept and shared, for example with variable step code.
+     */
+    #[allow(unused)]
+    pub async fn events<E: Send + Sync + 'static>(&self) -> Arc<Events<E>> {
+        if let Some((_, events)) = self.events.read().await.get(&TypeId::of::<E>()) {
+            return events.clone().downcast::<Events<E>>().unwrap();
+        }
+        let mut queues = self.events.write().await;
+        let (_, events) = queues.entry(TypeId::of::<E>()).or_insert_with(|| {
+            let events = Arc::new(Events::<E>::new());
+            (events.clone(), events)
+        });
+        events.clone().downcast::<Events<E>>().unwrap()
+    }
+
+    #[allow(unused)]
+    pub async fn send_event<E: Send + Sync + 'static>(&self, event: E) {
+        self.events::<E>().await.send(event);
+    }
+
+    /**
+     * Makes the event queue part of rollback frames, restore_frame puts back the events the queue kept when the frame was saved.
+     * Queues that are not registered keep their events when a frame is restored.
+     */
+    #[allow(unused)]
+    pub async fn register_rollback_events<E: Clone + Send + Sync + 'static>(&self) {
+        let mut events = self.rollback_events.write().await;
+        if events.iter().all(|cloner| cloner.type_id != TypeId::of::<E>()) {
+            events.push(RollbackCloner{ type_id: TypeId::of::<E>(), save: save_events::<E>, restore: restore_events::<E> });
+        }
+    }
+
+    /**
+     * Updates all event queues, events are dropped after two updates.
+     * Called by the app after every fixed step.
+     */
+    #[allow(unused)]
+    pub async fn update_events(&self) {
+        for (queue, _) in self.events.read().await.values() {
+            queue.update();
+        }
+    }
+
+    /**
+     * Declares a hash index over the key extracted from the component C.
+     * The returned index can be shared, its lookups are refreshed against the store they are given.
+     */
+    #[allow(unused)]
+    pub async fn register_hash_index<C, K>(&self, key: impl Fn(&C) -> K + Send + Sync + 'static) -> Arc<HashIndex<C, K>>
+    where
+        C: Component,
+        K: std::hash::Hash + Eq + Clone + Send + Sync + 'static,
+    {
+        self.register_index(HashIndex::new(key)).await
+    }
+
+    /**
+     * Declares an ordered index over the key extracted from the component C, it supports range lookups.
+     */
+    #[allow(unused)]
+    pub async fn register_ordered_index<C, K>(&self, key: impl Fn(&C) -> K + Send + Sync + 'static) -> Arc<OrderedIndex<C, K>>
+    where
+        C: Component,
+        K: Ord + Clone + Send + Sync + 'static,
+    {
+        self.register_index(OrderedIndex::new(key)).await
+    }
+
+    async fn register_index<C, K, M>(&self, index: ComponentIndex<C, K, M>) -> Arc<ComponentIndex<C, K, M>>
+    where
+        C: Component,
+        K: Clone + PartialEq + Send + Sync + 'static,
+        M: IndexMap<K>,
+    {
+        let index = Arc::new(index);
+        let store = self.get_store::<C>().await;
+        let entities = self.entities.read().await;
+        let mut store = store.write().await;
+        store.log_changes();
+        index.refresh(&*store, &entities);
+        self.indices.write().await.push(index.clone());
+        index
+    }
+
+    /**
+     * Brings all indices up to date with the components, cleanup does this after every fixed step.
+     * Lookups refresh their index anyway, refreshing all indices up front only keeps the work of later lookups small.
+     */
+    #[allow(unused)]
+    pub async fn refresh_indices(&self) {
+        let entities = self.entities.read().await;
+        let stores = self.stores.read().await;
+        self.refresh_indices_with(&entities, &stores).await;
+    }
+
+    async fn refresh_indices_with(&self, entities: &EntityManager, stores: &StoreMap) {
+        for index in self.indices.read().await.iter() {
+            if let Some(store) = stores.get(&index.component_type()) {
+                store.exec_ref(&mut |store: &dyn GenericComponentStore| index.refresh_erased(store, entities));
+            }
+        }
+    }
+
+    /**
+     * Registers a callback that runs for every C added to an entity.
+     * Hooks run deferred, in cleanup and run_hooks, in the order the changes happened.
+     */
+    #[allow(unused)]
+    pub async fn on_add<C: Component>(&self, hook: impl Fn(&HookContext, EntityHandle, &C) + Send + Sync + 'static) {
+        self.with_hooks::<C>(|hooks| hooks.on_add.push(Box::new(hook))).await;
+    }
+
+    /**
+     * Registers a callback that runs for every C overwritten with ComponentStore::set, it gets the old and the new value.
+     */
+    #[allow(unused)]
+    pub async fn on_replace<C: Component>(&self, hook: impl Fn(&HookContext, EntityHandle, &C, &C) + Send + Sync + 'static) {
+        self.with_hooks::<C>(|hooks| hooks.on_replace.push(Box::new(hook))).await;
+    }
+
+    /**
+     * Registers a callback that runs for every C removed from an entity, also when the entity is destroyed.
+     * For destroyed entities the handle is the one the entity had before it was destroyed.
+     */
+    #[allow(unused)]
+    pub async fn on_remove<C: Component>(&self, hook: impl Fn(&HookContext, EntityHandle, &C) + Send + Sync + 'static) {
+        self.with_hooks::<C>(|hooks| hooks.on_remove.push(Box::new(hook))).await;
+    }
+
+    async fn with_hooks<C: Component>(&self, f: impl FnOnce(&mut ComponentHooks<C>)) {
+        let store = self.get_store::<C>().await;
+        assert!(store.write().await.record_lifecycle(), "The store of {} can not record lifecycle events.", std::any::type_name::<C>());
+        let mut hooks = self.hooks.write().await;
+        let hooks = hooks.entry(TypeId::of::<C>()).or_insert_with(|| Box::new(ComponentHooks::<C>::default()));
+        f(hooks.as_any_mut().downcast_mut::<ComponentHooks<C>>().unwrap());
+    }
+
+    /**
+     * Runs the lifecycle hooks for all changes since the last run, cleanup does this after removing destroyed entities.
+     * Commands pushed by the hooks are applied by the next apply_commands or cleanup.
+     */
+    #[allow(unused)]
+    pub async fn run_hooks(&self) {
+        let entities = self.entities.read().await;
+        let stores = self.stores.read().await;
+        self.run_hooks_with(&entities, &stores).await;
+    }
+
+    async fn run_hooks_with(&self, entities: &EntityManager, stores: &StoreMap) {
+        let context = HookContext{ commands: &self.commands, entities };
+        for (type_id, hooks) in self.hooks.read().await.iter() {
+            if let Some(store) = stores.get(type_id) {
+                let mut events = None;
+                store.exec(&mut |store: &mut dyn GenericComponentStore| events = store.take_lifecycle());
+                if let Some(events) = events {
+                    hooks.dispatch_erased(events, &context);
+                }
+            }
+        }
+    }
+
     #[allow(unused)]
     pub fn get_entities(&self) -> &RwLock<EntityManager> {
         &self.entities
     }
 
+    /**
+     * Applies the recorded commands in order.
+     */
+    #[allow(unused)]
+    pub async fn apply_commands(&self) {
+        let mut entities = self.entities.write().await;
+        let mut stores = self.stores.write().await;
+        apply_commands(self.commands.take(), &mut entities, &mut stores, &self.store_context, self.change_tick());
+    }
+
+    /**
+     * Locks the stores of all components of the bundle for writing, in the order of their TypeId.
+     * Use it with EntityManager::spawn_bundle, insert_bundle and remove_bundle.
+     * Lock the entities before the stores, every function of the EntityComponentManager locks in that order.
+     */
     #[allow(unused)]
-    pub async fn cleanup(&mut self) {
+    pub async fn lock_bundle<B: Bundle>(&self) -> BundleStores {
+        BundleStores::lock(self, B::components()).await
+    }
+
+    /**
+     * Creates an entity with all components of the bundle.
+     */
+    #[allow(unused)]
+    pub async fn spawn_bundle<B: Bundle>(&self, bundle: B) -> EntityHandle {
         let mut entities = self.entities.write().await;
+        let mut stores = self.lock_bundle::<B>().await;
+        entities.spawn_bundle(&mut stores, bundle)
+    }
+
+    /**
+     * Creates an instance of the prefab, children of the prefab are instantiated and attached as well.
+     * Stores of components that are not registered yet are created on the fly.
+     */
+    #[allow(unused)]
+    pub async fn instantiate(&self, prefab: &Prefab) -> EntityHandle {
+        self.instantiate_with(prefab, Overrides::new()).await
+    }
+
+    /**
+     * Creates an instance of the prefab, the overrides replace or extend the components of the root entity.
+     */
+    #[allow(unused)]
+    pub async fn instantiate_with(&self, prefab: &Prefab, overrides: Overrides) -> EntityHandle {
+        let mut overrides = Some(overrides);
+        self.instantiate_many_with(prefab, 1, |_, o| *o = overrides.take().unwrap()).await[0]
+    }
+
+    #[allow(unused)]
+    pub async fn instantiate_many(&self, prefab: &Prefab, count: usize) -> Vec<EntityHandle> {
+        self.instantiate_many_with(prefab, count, |_, _| {}).await
+    }
+
+    /**
+     * Creates count instances of the prefab while holding the locks only once.
+     * f is called with the number of each instance and can fill in its overrides.
+     */
+    #[allow(unused)]
+    pub async fn instantiate_many_with(&self, prefab: &Prefab, count: usize, mut f: impl FnMut(usize, &mut Overrides)) -> Vec<EntityHandle> {
+        let mut entities = self.entities.write().await;
+        let mut stores = self.stores.write().await;
+        let tick = self.change_tick();
+        entities.flush_reserved();
+        let mut overrides = Overrides::new();
+        (0..count)
+            .map(|i| {
+                f(i, &mut overrides);
+                prefab.build(
+                    &mut || entities.create(),
+                    &mut |entity, value| insert_component(&mut stores, &self.store_context, tick, entity.index, value),
+                    overrides.take(),
+                )
+            })
+            .collect()
+    }
+
+    /**
+     * Applies the recorded commands, removes the components of destroyed entities, runs the lifecycle hooks and refreshes the indices.
+     * Called by the app after every fixed step.
+     */
+    #[allow(unused)]
+    pub async fn cleanup(&self) {
+        let mut entities = self.entities.write().await;
+        let mut stores = self.stores.write().await;
+        apply_commands(self.commands.take(), &mut entities, &mut stores, &self.store_context, self.change_tick());
+        expand_destruction(&mut entities, &stores);
         {   
             let entities = &mut *entities;
             let destruct_queue = &*entities.entity_destruct_queue;
-            let slots = &mut *entities.entity_slots;
-            for ent in  destruct_queue {
-                slots[*ent as usize].version += 1;
-            }
-    
-            let mut stores = self.stores.write().await;
             for (_, store) in &mut*stores {
                 store.exec(&mut |store: &mut dyn GenericComponentStore| {
                     for index in destruct_queue {
@@ -78,11 +495,690 @@ impl EntityComponentManager {
                     }
                 });
             }
+            for store in self.dynamic_stores.read().await.iter() {
+                let mut store = store.write().await;
+                for index in destruct_queue {
+                    if store.has(*index) {
+                        store.rem(*index);
+                    }
+                }
+            }
+        }
+        self.run_hooks_with(&entities, &stores).await;
+        {
+            let entities = &mut *entities;
+            let destruct_queue = &*entities.entity_destruct_queue;
+            let slots = &mut *entities.entity_slots;
+            for ent in  destruct_queue {
+                slots[*ent as usize].version += 1;
+            }
+            for (_, store) in &mut*stores {
+                store.exec(&mut |store: &mut dyn GenericComponentStore| store.maintain());
+            }
         }
 
         while let Some(index) = entities.entity_destruct_queue.pop() {
             entities.entity_free_list.push(index);
         }
+        self.refresh_indices_with(&entities, &stores).await;
+    }
+
+    /**
+     * Reports the entity count and the memory of every store, including dynamic components.
+     */
+    #[allow(unused)]
+    pub async fn diagnostics(&self) -> WorldDiagnostics {
+        let entities = self.entities.read().await;
+        let stores = self.stores.read().await;
+        let mut diagnostics = WorldDiagnostics{
+            entities: entities.entity_slots.iter().filter(|slot| slot.alive).count(),
+            entity_slots: entities.entity_slots.len(),
+            free_slots: entities.entity_free_list.len(),
+            entity_bytes: entities.entity_slots.capacity() * std::mem::size_of::<EntitySlot>()
+                + (entities.entity_free_list.capacity() + entities.entity_destruct_queue.capacity()) * std::mem::size_of::<EntityIndex>(),
+            stores: Vec::with_capacity(stores.len()),
+        };
+        for (_, store) in stores.iter() {
+            store.exec_ref(&mut |store: &dyn GenericComponentStore| {
+                diagnostics.stores.push(StoreDiagnostics{ name: store.type_name().to_string(), memory: store.memory() });
+            });
+        }
+        for store in self.dynamic_stores.read().await.iter() {
+            let store = store.read().await;
+            diagnostics.stores.push(StoreDiagnostics{ name: store.layout().name().to_string(), memory: store.memory() });
+        }
+        diagnostics.stores.sort_by(|a, b| b.memory.bytes.cmp(&a.memory.bytes).then_with(|| a.name.cmp(&b.name)));
+        diagnostics
+    }
+
+    /**
+     * Compacts every store, see GenericComponentStore::optimize.
+     * Best called after large amounts of entities were destroyed, like unloading a level.
+     */
+    #[allow(unused)]
+    pub async fn optimize(&self) {
+        for (_, store) in self.stores.read().await.iter() {
+            store.exec(&mut |store: &mut dyn GenericComponentStore| store.optimize());
+        }
+        for store in self.dynamic_stores.read().await.iter() {
+            store.write().await.optimize();
+        }
+    }
+
+    /**
+     * Copies the entities, their descendants and all their components into the target world.
+     * Parent and Children are remapped to the copies, links to entities that are not copied are dropped.
+     * Other components holding EntityHandles keep pointing into this world, use the returned mapping to fix them.
+     */
+    #[allow(unused)]
+    pub async fn copy_entities_to(&self, target: &EntityComponentManager, entities: &[EntityHandle]) -> EntityMapping {
+        self.migrate(target, entities, false).await
+    }
+
+    /**
+     * Like copy_entities_to, but destroys the entities in this world afterwards.
+     * The source components are removed by the next cleanup of this world, so on_remove hooks run there.
+     */
+    #[allow(unused)]
+    pub async fn move_entities_to(&self, target: &EntityComponentManager, entities: &[EntityHandle]) -> EntityMapping {
+        self.migrate(target, entities, true).await
+    }
+
+    async fn migrate(&self, target: &EntityComponentManager, roots: &[EntityHandle], destroy: bool) -> EntityMapping {
+        assert!(!std::ptr::eq(self, target), "Can not migrate entities into the same world.");
+        let mut entities = self.entities.write().await;
+        let stores = self.stores.read().await;
+
+        let mut migrated = roots.iter().copied().filter(|entity| entities.exists(*entity)).collect::<Vec<_>>();
+        let mut i = 0;
+        while i < migrated.len() {
+            let index = migrated[i].index;
+            if let Some(store) = stores.get(&TypeId::of::<Children>()) {
+                let mut children = Vec::new();
+                store.exec_ref(&mut |store: &dyn GenericComponentStore| {
+                    let store = store.as_any().downcast_ref::<<Children as Component>::Storage>().unwrap();
+                    children.extend(store.get(index).map_or(&[][..], |c| &c.0[..]).iter().copied());
+                });
+                for child in children {
+                    if entities.exists(child) && !migrated.contains(&child) {
+                        migrated.push(child);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let components = migrated.iter()
+            .map(|entity| {
+                let mut components = Vec::new();
+                for (type_id, store) in stores.iter() {
+                    store.exec_ref(&mut |store: &dyn GenericComponentStore| {
+                        if store.has(entity.index) {
+                            components.extend(self.store_context.clone_component(*type_id, store, entity.index));
+                        }
+                    });
+                }
+                components
+            })
+            .collect::<Vec<_>>();
+
+        let mut target_entities = target.entities.write().await;
+        let mut target_stores = target.stores.write().await;
+        let tick = target.change_tick();
+        target_entities.flush_reserved();
+        let mut mapping = EntityMapping::default();
+        for entity in &migrated {
+            mapping.insert(*entity, target_entities.create());
+        }
+        for (entity, components) in migrated.iter().zip(components) {
+            let copy = mapping.get(*entity).unwrap();
+            for mut value in components {
+                if let Some(Parent(parent)) = value.as_any_mut().downcast_mut::<Parent>() {
+                    match mapping.get(*parent) {
+                        Some(mapped) => *parent = mapped,
+                        None => continue,
+                    }
+                }
+                if let Some(Children(children)) = value.as_any_mut().downcast_mut::<Children>() {
+                    *children = children.iter().filter_map(|child| mapping.get(*child)).collect();
+                }
+                insert_component(&mut target_stores, &target.store_context, tick, copy.index, value);
+            }
+        }
+
+        if destroy {
+            for entity in &migrated {
+                entities.destroy(*entity);
+            }
+        }
+        mapping
+    }
+
+    /**
+     * Registers a component for reflection under the name of its type, the base of inspectors and console commands.
+     */
+    #[allow(unused)]
+    pub async fn register_reflect<C: Component + Reflect>(&self) {
+        drop(self.get_store::<C>().await);
+        let component = ReflectComponent::of::<C>();
+        let mut reflection = self.reflection.write().await;
+        assert!(!reflection.components.contains_key(component.info.name), "Component \"{}\" is allready registered for reflection.", component.info.name);
+        reflection.components.insert(component.info.name, component);
+    }
+
+    /**
+     * The type infos of all reflected components, sorted by name.
+     */
+    #[allow(unused)]
+    pub async fn reflected_components(&self) -> Vec<TypeInfo> {
+        let mut infos = self.reflection.read().await.components.values().map(|component| component.info.clone()).collect::<Vec<_>>();
+        infos.sort_by_key(|info| info.name);
+        infos
+    }
+
+    /**
+     * The names of the reflected components the entity has, sorted by name.
+     */
+    #[allow(unused)]
+    pub async fn reflected_components_of(&self, entity: EntityHandle) -> Vec<&'static str> {
+        let entities = self.entities.read().await;
+        let stores = self.stores.read().await;
+        if !entities.exists(entity) {
+            return Vec::new();
+        }
+        let mut names = Vec::new();
+        for (name, component) in self.reflection.read().await.components.iter() {
+            stores[&component.type_id].exec_ref(&mut |store: &dyn GenericComponentStore| {
+                if store.has(entity.index) {
+                    names.push(*name);
+                }
+            });
+        }
+        names.sort();
+        names
+    }
+
+    /**
+     * Calls f with the component of the entity, component is the name it was registered for reflection with.
+     */
+    #[allow(unused)]
+    pub async fn with_reflect<R>(&self, entity: EntityHandle, component: &str, f: impl FnOnce(&dyn Reflect) -> R) -> Result<R, ReflectError> {
+        let entities = self.entities.read().await;
+        let stores = self.stores.read().await;
+        let reflect = self.reflected(&entities, entity, component).await?;
+        let mut f = Some(f);
+        let mut result = None;
+        stores[&reflect.type_id].exec_ref(&mut |store: &dyn GenericComponentStore| {
+            result = (reflect.get)(store, entity.index).map(|value| f.take().unwrap()(value));
+        });
+        result.ok_or_else(|| ReflectError::MissingComponent(component.to_string()))
+    }
+
+    /**
+     * Like with_reflect, but the component can be changed, it is marked as changed.
+     */
+    #[allow(unused)]
+    pub async fn with_reflect_mut<R>(&self, entity: EntityHandle, component: &str, f: impl FnOnce(&mut dyn Reflect) -> R) -> Result<R, ReflectError> {
+        let entities = self.entities.read().await;
+        let stores = self.stores.read().await;
+        let reflect = self.reflected(&entities, entity, component).await?;
+        let mut f = Some(f);
+        let mut result = None;
+        stores[&reflect.type_id].exec(&mut |store: &mut dyn GenericComponentStore| {
+            if store.has(entity.index) {
+                result = (reflect.get_mut)(store, entity.index).map(|value| f.take().unwrap()(value));
+            }
+        });
+        result.ok_or_else(|| ReflectError::MissingComponent(component.to_string()))
+    }
+
+    async fn reflected(&self, entities: &EntityManager, entity: EntityHandle, component: &str) -> Result<ReflectComponent, ReflectError> {
+        if !entities.exists(entity) {
+            return Err(ReflectError::NoEntity);
+        }
+        self.reflection.read().await.components.get(component).cloned().ok_or_else(|| ReflectError::UnknownComponent(component.to_string()))
+    }
+
+    /**
+     * Reads a field by its path starting with the component name, for example "Transform.position.x".
+     */
+    #[allow(unused)]
+    pub async fn get_field(&self, entity: EntityHandle, path: &str) -> Result<ReflectValue, ReflectError> {
+        let (component, fields) = path.split_once('.').unwrap_or((path, ""));
+        self.with_reflect(entity, component, |value| value.get_path(fields)).await?
+            .map_err(|err| prefix_path(err, component))
+    }
+
+    /**
+     * Writes a field by its path starting with the component name, for example "Transform.position.x".
+     */
+    #[allow(unused)]
+    pub async fn set_field(&self, entity: EntityHandle, path: &str, value: ReflectValue) -> Result<(), ReflectError> {
+        let (component, fields) = path.split_once('.').unwrap_or((path, ""));
+        self.with_reflect_mut(entity, component, |reflect| reflect.set_path(fields, value)).await?
+            .map_err(|err| prefix_path(err, component))
+    }
+
+    /**
+     * Registers a component to be part of world snapshots.
+     */
+    #[allow(unused)]
+    pub async fn register_snapshot_component<C: SnapshotComponent>(&self) {
+        drop(self.get_store::<C>().await);
+        let mut serializers = self.serializers.write().await;
+        assert!(!serializers.iter().any(|s| s.type_id == TypeId::of::<C>()), "Can not register snapshot component multiple times.");
+        assert!(!serializers.iter().any(|s| s.name == C::NAME), "Snapshot component name \"{}\" is allready used.", C::NAME);
+        let position = serializers.partition_point(|s| s.name < C::NAME);
+        serializers.insert(position, ComponentSerializer::of::<C>());
+    }
+
+    /**
+     * Writes all entities and all components registered via register_snapshot_component.
+     * Components are written sorted by their name, so equal worlds produce equal snapshots.
+     */
+    #[allow(unused)]
+    pub async fn save_snapshot(&self, writer: &mut dyn SnapshotWriter) {
+        let entities = self.entities.read().await;
+        let stores = self.stores.read().await;
+        let serializers = self.serializers.read().await;
+
+        write_header(writer);
+        save_entities(&entities, writer);
+        writer.begin("components");
+        writer.write_u32("component_count", serializers.len() as u32);
+        for serializer in serializers.iter() {
+            stores[&serializer.type_id].exec_ref(&mut |store: &dyn GenericComponentStore| {
+                writer.begin("component");
+                writer.write_str("name", serializer.name);
+                writer.write_u32("count", store.iter_indices().count() as u32);
+                for index in store.iter_indices() {
+                    writer.begin("entry");
+                    writer.write_u32("entity", index);
+                    (serializer.save)(store, index, writer);
+                    writer.end();
+                }
+                writer.end();
+            });
+        }
+        writer.end();
+    }
+
+    #[allow(unused)]
+    pub async fn save_snapshot_binary(&self) -> Vec<u8> {
+        let mut writer = BinarySnapshotWriter::new();
+        self.save_snapshot(&mut writer).await;
+        writer.data
+    }
+
+    #[allow(unused)]
+    pub async fn save_snapshot_text(&self) -> String {
+        let mut writer = TextSnapshotWriter::new();
+        self.save_snapshot(&mut writer).await;
+        writer.text
+    }
+
+    /**
+     * Replaces the whole world with the snapshot.
+     * Entity indices and versions are restored exactly, so EntityHandles taken before saving stay valid.
+     * Components that are not part of the snapshot are removed, dynamic components are never part of it.
+     * On error the world is left empty.
+     */
+    #[allow(unused)]
+    pub async fn load_snapshot(&self, reader: &mut dyn SnapshotReader) -> Result<(), SnapshotError> {
+        let mut entities = self.entities.write().await;
+        let stores = self.stores.read().await;
+        let serializers = self.serializers.read().await;
+        let dynamic_store_list = self.dynamic_stores.read().await;
+        let mut dynamic_stores = Vec::with_capacity(dynamic_store_list.len());
+        for store in dynamic_store_list.iter() {
+            dynamic_stores.push(store.write().await);
+        }
+
+        let mut clear_stores = || {
+            for (_, store) in stores.iter() {
+                store.exec(&mut |store: &mut dyn GenericComponentStore| {
+                    let indices = store.iter_indices().collect::<Vec<_>>();
+                    for index in indices {
+                        store.rem(index);
+                    }
+                    store.maintain();
+                });
+            }
+            for store in dynamic_stores.iter_mut() {
+                let indices = store.iter_indices().collect::<Vec<_>>();
+                for index in indices {
+                    store.rem(index);
+                }
+            }
+        };
+        clear_stores();
+        *entities = EntityManager::new();
+
+        let result = (|| {
+            read_header(reader)?;
+            let loaded_entities = load_entities(reader)?;
+            reader.begin("components")?;
+            let component_count = reader.read_u32("component_count")?;
+            for _ in 0..component_count {
+                reader.begin("component")?;
+                let name = reader.read_string("name")?;
+                let serializer = serializers.iter()
+                    .find(|s| s.name == name)
+                    .ok_or(SnapshotError::UnknownComponent(name))?;
+                let count = reader.read_u32("count")?;
+                let mut result = Ok(());
+                stores[&serializer.type_id].exec(&mut |store: &mut dyn GenericComponentStore| {
+                    result = (|| {
+                        for _ in 0..count {
+                            reader.begin("entry")?;
+                            let index = checked_index(reader.read_u32("entity")?, loaded_entities.entity_slots.len() as u32)?;
+                            if store.has(index) {
+                                return Err(SnapshotError::Malformed(format!("entity {} has component {} twice", index, serializer.name)));
+                            }
+                            (serializer.load)(store, index, reader)?;
+                            reader.end()?;
+                        }
+                        Ok(())
+                    })();
+                    store.maintain();
+                });
+                result?;
+                reader.end()?;
+            }
+            reader.end()?;
+            Ok(loaded_entities)
+        })();
+
+        match result {
+            Ok(loaded_entities) => {
+                *entities = loaded_entities;
+                Ok(())
+            },
+            Err(err) => {
+                clear_stores();
+                Err(err)
+            },
+        }
+    }
+
+    #[allow(unused)]
+    pub async fn load_snapshot_binary(&self, data: &[u8]) -> Result<(), SnapshotError> {
+        self.load_snapshot(&mut BinarySnapshotReader::new(data)).await
+    }
+
+    #[allow(unused)]
+    pub async fn load_snapshot_text(&self, text: &str) -> Result<(), SnapshotError> {
+        self.load_snapshot(&mut TextSnapshotReader::new(text)).await
+    }
+
+    /**
+     * Saves the entities, the components of all stores that support rollback
+     * and the resources and event queues registered with register_rollback_resource and register_rollback_events.
+     * The stores are maintained first, so the rows of the TableStores match the saved archetype registry.
+     * Pending commands are not part of the frame.
+     */
+    #[allow(unused)]
+    pub async fn save_frame(&self) -> WorldFrame {
+        let entities = self.entities.read().await;
+        let stores = self.stores.read().await;
+        for store in stores.values() {
+            store.exec(&mut |store: &mut dyn GenericComponentStore| store.maintain());
+        }
+        let tables = self.store_context.tables.lock().unwrap().save_state();
+        let mut frame = WorldFrame{
+            entities: entities.save_state(),
+            stores: Vec::with_capacity(stores.len()),
+            dynamic_stores: Vec::new(),
+            tables,
+            resources: Vec::new(),
+            events: Vec::new(),
+        };
+        for (type_id, store) in stores.iter() {
+            store.exec_ref(&mut |store: &dyn GenericComponentStore| frame.stores.push((*type_id, store.save_state())));
+        }
+        for store in self.dynamic_stores.read().await.iter() {
+            frame.dynamic_stores.push(store.read().await.save_state().unwrap());
+        }
+        for cloner in self.rollback_resources.read().await.iter() {
+            frame.resources.push((cloner.type_id, (cloner.save)(self).await));
+        }
+        for cloner in self.rollback_events.read().await.iter() {
+            frame.events.push((cloner.type_id, (cloner.save)(self).await));
+        }
+        frame
+    }
+
+    /**
+     * Puts the world back into the state of the frame.
+     * Stores that can not be rolled back keep their components, stores registered after the frame was saved are emptied.
+     * All stores are maintained before the archetype registry is rewound, the TableStores restore their rows afterwards.
+     * Resources and event queues that were registered for rollback when the frame was saved are restored, the others are kept.
+     * Pending commands and lifecycle events are dropped and the indices are rebuilt.
+     */
+    #[allow(unused)]
+    pub async fn restore_frame(&self, frame: &WorldFrame) {
+        let mut entities = self.entities.write().await;
+        let stores = self.stores.read().await;
+        drop(self.commands.take());
+        *entities = frame.entities.save_state();
+        for (type_id, store) in stores.iter() {
+            let saved = frame.stores.iter().any(|(id, _)| id == type_id);
+            if !saved {
+                store.exec(&mut |store: &mut dyn GenericComponentStore| {
+                    let indices = store.iter_indices().collect::<Vec<_>>();
+                    for index in indices {
+                        store.rem(index);
+                    }
+                    drop(store.take_lifecycle());
+                });
+            }
+        }
+        for store in stores.values() {
+            store.exec(&mut |store: &mut dyn GenericComponentStore| store.maintain());
+        }
+        self.store_context.tables.lock().unwrap().restore_state(frame.tables.as_ref());
+        for (type_id, store) in stores.iter() {
+            if let Some((_, Some(state))) = frame.stores.iter().find(|(id, _)| id == type_id) {
+                store.exec(&mut |store: &mut dyn GenericComponentStore| {
+                    store.restore_state(state.as_ref());
+                    store.maintain();
+                });
+            }
+        }
+        for (id, store) in self.dynamic_stores.read().await.iter().enumerate() {
+            let mut store = store.write().await;
+            match frame.dynamic_stores.get(id) {
+                Some(state) => store.restore_state(state.as_ref()),
+                None => {
+                    let indices = store.iter_indices().collect::<Vec<_>>();
+                    for index in indices {
+                        store.rem(index);
+                    }
+                },
+            }
+        }
+        for cloner in self.rollback_resources.read().await.iter() {
+            if let Some((_, state)) = frame.resources.iter().find(|(id, _)| *id == cloner.type_id) {
+                (cloner.restore)(self, state.as_deref()).await;
+            }
+        }
+        for cloner in self.rollback_events.read().await.iter() {
+            if let Some((_, state)) = frame.events.iter().find(|(id, _)| *id == cloner.type_id) {
+                (cloner.restore)(self, state.as_deref()).await;
+            }
+        }
+        for index in self.indices.read().await.iter() {
+            index.invalidate();
+        }
+        self.refresh_indices_with(&entities, &stores).await;
+    }
+
+    /**
+     * Captures the entities and all components registered via register_snapshot_component as the base or target of a WorldDelta.
+     */
+    #[allow(unused)]
+    pub async fn capture_state(&self, sequence: u64) -> WorldState {
+        let entities = self.entities.read().await;
+        let stores = self.stores.read().await;
+        let serializers = self.serializers.read().await;
+
+        let mut state = WorldState{
+            sequence,
+            slots: entities.entity_slots.iter().map(|slot| (slot.version, slot.alive)).collect(),
+            ..WorldState::default()
+        };
+        for serializer in serializers.iter() {
+            let components = state.components.entry(serializer.name).or_default();
+            stores[&serializer.type_id].exec_ref(&mut |store: &dyn GenericComponentStore| {
+                for index in store.iter_indices() {
+                    let mut writer = BinarySnapshotWriter::new();
+                    (serializer.save)(store, index, &mut writer);
+                    components.insert(index, writer.data);
+                }
+            });
+        }
+        state
+    }
+
+    /**
+     * The sequence number of the last delta applied to this world, 0 if none was applied.
+     */
+    #[allow(unused)]
+    pub fn replicated_sequence(&self) -> u64 {
+        self.replicated_sequence.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Applies a delta computed by WorldDelta::between on another world.
+     * The delta must be based on the replicated sequence of this world, deltas based on sequence 0 replace the whole world.
+     * Entity indices and versions are taken over exactly, so EntityHandles are the same in both worlds.
+     * On errors found while decoding components the world can be partially updated, request a delta based on sequence 0 then.
+     */
+    #[allow(unused)]
+    pub async fn apply_delta(&self, delta: &WorldDelta) -> Result<(), SnapshotError> {
+        let mut entities = self.entities.write().await;
+        let stores = self.stores.read().await;
+        let serializers = self.serializers.read().await;
+
+        let current = self.replicated_sequence();
+        if delta.base_sequence != 0 && delta.base_sequence != current {
+            return Err(SnapshotError::SequenceMismatch{ expected: current, found: delta.base_sequence });
+        }
+        let component_serializers = delta.components.iter()
+            .map(|component| serializers.iter().find(|s| s.name == component.name).ok_or_else(|| SnapshotError::UnknownComponent(component.name.clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut destroyed = Vec::new();
+        if delta.base_sequence == 0 {
+            destroyed.extend((0..entities.entity_slots.len() as EntityIndex).filter(|index| entities.exists_index(*index)));
+            *entities = EntityManager::new();
+        }
+        destroyed.extend((delta.slot_count..entities.entity_slots.len() as u32).filter(|index| entities.exists_index(*index)));
+        for (index, version, alive) in &delta.slots {
+            if entities.exists_index(*index) && (!*alive || entities.entity_slots[*index as usize].version != *version) {
+                destroyed.push(*index);
+            }
+        }
+        for (_, store) in stores.iter() {
+            store.exec(&mut |store: &mut dyn GenericComponentStore| {
+                for index in &destroyed {
+                    if store.has(*index) {
+                        store.rem(*index);
+                    }
+                }
+            });
+        }
+        for store in self.dynamic_stores.read().await.iter() {
+            let mut store = store.write().await;
+            for index in &destroyed {
+                if store.has(*index) {
+                    store.rem(*index);
+                }
+            }
+        }
+
+        entities.entity_slots.resize_with(delta.slot_count as usize, EntitySlot::new);
+        for (index, version, alive) in &delta.slots {
+            entities.entity_slots[*index as usize] = EntitySlot{ version: *version, alive: *alive };
+        }
+        entities.entity_free_list = (0..delta.slot_count).rev().filter(|index| !entities.exists_index(*index)).collect();
+        entities.entity_destruct_queue.clear();
+
+        for (component, serializer) in delta.components.iter().zip(component_serializers) {
+            let mut result = Ok(());
+            stores[&serializer.type_id].exec(&mut |store: &mut dyn GenericComponentStore| {
+                result = (|| {
+                    for index in &component.removed {
+                        if store.has(*index) {
+                            store.rem(*index);
+                        }
+                    }
+                    for (index, bytes) in &component.set {
+                        (serializer.load)(store, *index, &mut BinarySnapshotReader::new(bytes))?;
+                    }
+                    Ok(())
+                })();
+            });
+            result?;
+        }
+        for (_, store) in stores.iter() {
+            store.exec(&mut |store: &mut dyn GenericComponentStore| store.maintain());
+        }
+        self.replicated_sequence.store(delta.sequence, Ordering::Relaxed);
+        Ok(())
+    }
+
+    #[allow(unused)]
+    pub async fn apply_delta_bytes(&self, data: &[u8]) -> Result<(), SnapshotError> {
+        self.apply_delta(&WorldDelta::from_bytes(data)?).await
+    }
+}
+
+fn save_resource<R: Clone + Send + Sync + 'static>(ecm: &EntityComponentManager) -> RollbackSaveFuture<'_> {
+    Box::pin(async move {
+        let resource = ecm.resources.read().await.get(&TypeId::of::<R>())?.clone();
+        let value = resource.downcast::<RwLock<R>>().unwrap().read().await.clone();
+        Some(Box::new(value) as Box<dyn Any + Send + Sync>)
+    })
+}
+
+fn restore_resource<'a, R: Clone + Send + Sync + 'static>(ecm: &'a EntityComponentManager, state: Option<&'a (dyn Any + Send + Sync)>) -> RollbackRestoreFuture<'a> {
+    Box::pin(async move {
+        match state {
+            Some(state) => {
+                ecm.insert_resource(state.downcast_ref::<R>().unwrap().clone()).await;
+            },
+            None => {
+                ecm.remove_resource::<R>().await;
+            },
+        }
+    })
+}
+
+fn save_events<E: Clone + Send + Sync + 'static>(ecm: &EntityComponentManager) -> RollbackSaveFuture<'_> {
+    Box::pin(async move {
+        Some(ecm.events::<E>().await.save_state())
+    })
+}
+
+fn restore_events<'a, E: Clone + Send + Sync + 'static>(ecm: &'a EntityComponentManager, state: Option<&'a (dyn Any + Send + Sync)>) -> RollbackRestoreFuture<'a> {
+    Box::pin(async move {
+        if let Some(state) = state {
+            ecm.events::<E>().await.restore_state(state);
+        }
+    })
+}
+
+/**
+ * Makes the paths in reflection errors start with the component name.
+ */
+fn prefix_path(err: ReflectError, component: &str) -> ReflectError {
+    let prefix = |path: String| if path.is_empty() { component.to_string() } else { format!("{}.{}", component, path) };
+    match err {
+        ReflectError::UnknownField(path) => ReflectError::UnknownField(prefix(path)),
+        ReflectError::NotAValue(path) => ReflectError::NotAValue(prefix(path)),
+        ReflectError::TypeMismatch{ path, expected } => ReflectError::TypeMismatch{ path: prefix(path), expected },
+        other => other,
     }
 }
 
@@ -116,6 +1212,34 @@ macro_rules! get_components {
     };
 }
 
+/**
+ * Get shared reference to a resource.
+ * syntax:  (manager: my_entity_component_manager; resources: ResourceTypes... => resource_reference_names...)
+ */
+#[allow(unused)]
+#[macro_export]
+macro_rules! get_resources {
+    ($ecm:expr; $ResourceType:ty $(,$($RestTypes:ty),+)? => $name:ident $(,$($RestNames:ident),+)?) => {
+        let $name = $ecm.get_resource::<$ResourceType>().await;
+        let $name = &*$name;
+        $(eisen::get_resources!($ecm; $($RestTypes),+ => $($RestNames),+))?
+    };
+}
+
+/**
+ * Get exclusive reference to a resource.
+ * syntax:  (manager: my_entity_component_manager; resources: ResourceTypes... => resource_reference_names...)
+ */
+#[allow(unused)]
+#[macro_export]
+macro_rules! get_resources_mut {
+    ($ecm:expr; $ResourceType:ty $(,$($RestTypes:ty),+)? => $name:ident $(,$($RestNames:ident),+)?) => {
+        let mut $name = $ecm.get_resource_mut::<$ResourceType>().await;
+        let $name = &mut*$name;
+        $(eisen::get_resources_mut!($ecm; $($RestTypes),+ => $($RestNames),+))?
+    };
+}
+
 /**
  * Get shared reference to a component storage.
  * syntax:  (manager: component_manager_name => reference_name)
@@ -1,7 +1,10 @@
 use std::any::*;
 use std::sync::Arc;
 
+use async_std::sync::RwLock;
+
 use crate::entity::handle::*;
+use crate::entity::commands::AnyComponent;
 
 mod dense_store;
 pub use dense_store::*;
@@ -9,7 +12,49 @@ pub use dense_store::*;
 mod linear_store;
 pub use linear_store::*;
 
+mod table_store;
+pub use table_store::*;
+
+mod tag_store;
+pub use tag_store::*;
+
+mod dense_group;
+pub use dense_group::*;
+
+mod dynamic_store;
+pub use dynamic_store::*;
+
+/**
+ * Memory report of a store, bytes are the heap allocations of the store, including allocated but unused slots.
+ */
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct StoreMemory {
+    pub len: usize,
+    /**
+     * Number of components the store has allocated room for.
+     */
+    pub capacity: usize,
+    pub bytes: usize,
+}
+
+impl StoreMemory {
+    /**
+     * Share of the allocated slots that hold no component, 0.0 for stores without allocations.
+     */
+    #[allow(unused)]
+    pub fn fragmentation(&self) -> f32 {
+        if self.capacity == 0 {
+            0.0
+        } else {
+            1.0 - self.len as f32 / self.capacity as f32
+        }
+    }
+}
+
 pub trait GenericComponentStore {
+    /**
+     * Releases memory that is not needed for the current components.
+     */
     fn optimize(&mut self);
 
     fn as_any(&self) -> &dyn Any;
@@ -21,6 +66,158 @@ pub trait GenericComponentStore {
     fn rem(&mut self, index: EntityIndex);
 
     fn len(&self) -> usize;
+
+    /**
+     * Stores that can not list their entities yield none, snapshots, rollback frames and indices skip their components.
+     */
+    fn iter_indices(&self) -> Box<dyn Iterator<Item = EntityIndex> + '_> {
+        Box::new(std::iter::empty())
+    }
+
+    /**
+     * Stores that do not track their allocations only report their length.
+     */
+    fn memory(&self) -> StoreMemory {
+        StoreMemory{ len: self.len(), capacity: self.len(), bytes: 0 }
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    fn change_tick(&self) -> Tick {
+        0
+    }
+
+    /**
+     * Called by the EntityComponentManager when its change tick advances.
+     */
+    fn set_change_tick(&mut self, _tick: Tick) {}
+
+    fn component_ticks(&self, _index: EntityIndex) -> Option<ComponentTicks> {
+        None
+    }
+
+    /**
+     * Entities whose component was removed during the last or the current change tick.
+     */
+    fn removed(&self) -> &[(EntityIndex, Tick)] {
+        &[]
+    }
+
+    /**
+     * True, if the component was added during the last or the current change tick.
+     */
+    fn is_added(&self, index: EntityIndex) -> bool {
+        self.component_ticks(index).map_or(false, |ticks| is_recent_tick(ticks.added, self.change_tick()))
+    }
+
+    /**
+     * True, if the component was added, set or mutably accessed during the last or the current change tick.
+     */
+    fn is_changed(&self, index: EntityIndex) -> bool {
+        self.component_ticks(index).map_or(false, |ticks| is_recent_tick(ticks.changed, self.change_tick()))
+    }
+
+    fn was_removed(&self, index: EntityIndex) -> bool {
+        self.removed().iter().any(|(i, tick)| *i == index && is_recent_tick(*tick, self.change_tick()))
+    }
+
+    /**
+     * Called by EntityComponentManager::cleanup after the components of destroyed entities were removed.
+     */
+    fn maintain(&mut self) {}
+
+    /**
+     * Starts recording lifecycle events for hooks, returns false if the store can not record them.
+     */
+    fn record_lifecycle(&mut self) -> bool {
+        false
+    }
+
+    /**
+     * The lifecycle events recorded since the last call, as a Vec<LifecycleEvent<T>> of the component type.
+     */
+    fn take_lifecycle(&mut self) -> Option<Box<dyn Any>> {
+        None
+    }
+
+    /**
+     * Copy of the contents of the store for rollback, None if the store can not be rolled back.
+     */
+    fn save_state(&self) -> Option<Box<dyn Any + Send + Sync>> {
+        None
+    }
+
+    /**
+     * Replaces the contents with a state returned by save_state, pending lifecycle events are dropped.
+     */
+    fn restore_state(&mut self, _state: &(dyn Any + Send + Sync)) {}
+
+    /**
+     * Takes sole ownership of storage shared with saved states, so writes from several threads do not copy it.
+     * Called by parallel_over_entities for every mut store before the batches are spawned.
+     */
+    fn unshare_pages(&mut self) {}
+
+    /**
+     * Starts logging the entities whose component is added, written or removed, returns false if the store can not log them.
+     */
+    fn log_changes(&mut self) -> bool {
+        false
+    }
+
+    fn change_cursor(&self) -> ChangeCursor {
+        ChangeCursor::default()
+    }
+
+    /**
+     * Entities whose component was added, written or removed since the cursor was taken, in order and possibly repeated.
+     * None, if the store does not log changes or can not tell, then every component has to be looked at.
+     */
+    fn changes_since(&self, _cursor: ChangeCursor) -> Option<&[EntityIndex]> {
+        None
+    }
+
+    /**
+     * Called with true for every mut store before parallel batches write it from several threads and with false after they finished.
+     * In between writes through get_mut are not logged, the parallel writes count as a write to every component.
+     */
+    fn pause_change_log(&mut self, _paused: bool) {}
+
+    /**
+     * True if the batches of the store only end at LinearStore page boundaries.
+     */
+    fn page_aligned_batches(&self) -> bool {
+        false
+    }
+
+    /**
+     * The entities of a TagStore, joins over several tags are driven by the intersection of their sets.
+     */
+    fn tag_bits(&self) -> Option<&BitSet> {
+        None
+    }
+
+    /**
+     * The archetype layout of a TableStore, joins over table stores walk the columns of the archetypes they share in lock-step.
+     */
+    fn table_layout(&self) -> Option<&dyn TableLayout> {
+        None
+    }
+
+    /**
+     * Replays the archetype moves a TableStore did not see yet, see TableStore::sync.
+     */
+    fn sync_layout(&mut self) {}
+
+    /**
+     * Number of positions iter_entity_range of the store accepts, parallel batches are ranges of these positions.
+     * Dense stores count their slots, stores addressed by entity index cover every index they have room for.
+     */
+    fn position_count(&self) -> usize {
+        self.len()
+    }
 }
 
 pub trait ComponentStore<T: Default + Clone> {
@@ -28,6 +225,14 @@ pub trait ComponentStore<T: Default + Clone> {
 
     fn new() -> Self;
 
+    /**
+     * Creates the store for a component registered in an EntityComponentManager.
+     * Stores that share state with other stores of the same manager (like TableStore) take it from the context.
+     */
+    fn new_in(_context: &StoreContext) -> Self where Self: Sized {
+        Self::new()
+    }
+
     fn get(&self, index: EntityIndex) -> Option<&T>;
 
     fn get_mut(&mut self, index: EntityIndex) -> Option<&mut T>;
@@ -37,7 +242,311 @@ pub trait ComponentStore<T: Default + Clone> {
     fn add(&mut self, index: EntityIndex, value: T);
 }
 
-pub trait Component : Clone + Default + Sync + Send
+/**
+ * Stores that can split their entities into batches, needed to drive a Query.
+ */
+pub trait BatchedStore<T> {
+    fn batches(&self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &T)> + Send + '_>>;
+
+    fn batches_mut(&mut self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &mut T)> + Send + '_>>;
+}
+
+pub type Tick = u32;
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct ComponentTicks {
+    pub added: Tick,
+    pub changed: Tick,
+}
+
+impl ComponentTicks {
+    pub fn new(tick: Tick) -> Self {
+        Self{
+            added: tick,
+            changed: tick,
+        }
+    }
+}
+
+/**
+ * A tick counts as recent, if it is the current or the previous one.
+ */
+pub fn is_recent_tick(tick: Tick, current: Tick) -> bool {
+    current.wrapping_sub(tick) <= 1
+}
+
+/**
+ * Position of a reader in the change log of a store, taken with GenericComponentStore::change_cursor.
+ */
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct ChangeCursor {
+    position: u64,
+    rewrites: u64,
+}
+
+const CHANGE_LOG_LIMIT: usize = 1 << 16;
+
+/**
+ * Current change tick and removal log of a store.
+ * Once log_changes was called, it also logs the entities whose component was added, written or removed.
+ * The log keeps the entries of the current and the previous change tick, writes through mutable iterators
+ * count as a rewrite of every component, readers that fell behind or saw a rewrite have to look at every component again.
+ */
+#[derive(Clone, Default)]
+pub struct ChangeTracker {
+    pub tick: Tick,
+    removed: Vec<(EntityIndex, Tick)>,
+    logging: bool,
+    paused: bool,
+    changed: Vec<EntityIndex>,
+    changed_offset: u64,
+    tick_start: u64,
+    rewrites: u64,
+}
+
+impl ChangeTracker {
+    pub fn set_tick(&mut self, tick: Tick) {
+        self.tick = tick;
+        self.removed.retain(|(_, removed_tick)| is_recent_tick(*removed_tick, tick));
+        self.paused = false;
+        self.changed.drain(..(self.tick_start - self.changed_offset) as usize);
+        self.changed_offset = self.tick_start;
+        self.tick_start = self.changed_end();
+    }
+
+    pub fn log_removal(&mut self, index: EntityIndex) {
+        self.removed.push((index, self.tick));
+        self.log_change(index);
+    }
+
+    pub fn removed(&self) -> &[(EntityIndex, Tick)] {
+        &self.removed
+    }
+
+    pub fn log_changes(&mut self) {
+        if !self.logging {
+            self.logging = true;
+            self.rewrites += 1;
+        }
+    }
+
+    /**
+     * Logs an add, set or removal, these never happen during parallel writes, so they also end a pause.
+     */
+    pub fn log_change(&mut self, index: EntityIndex) {
+        self.paused = false;
+        self.log_access(index);
+    }
+
+    /**
+     * Logs a write through get_mut, which parallel batches also use, so it is not logged while paused.
+     */
+    pub fn log_access(&mut self, index: EntityIndex) {
+        if !self.logging || self.paused {
+            return;
+        }
+        if self.changed.len() >= CHANGE_LOG_LIMIT {
+            self.changed_offset = self.changed_end();
+            self.tick_start = self.changed_offset;
+            self.changed.clear();
+            self.rewrites += 1;
+        }
+        self.changed.push(index);
+    }
+
+    /**
+     * Logs a write to every component, for mutable iterators and restored states.
+     */
+    pub fn log_rewrite(&mut self) {
+        self.rewrites += 1;
+    }
+
+    /**
+     * Logs a rewrite, unless parallel batches write the store from several threads, their pause counts as the rewrite.
+     */
+    pub fn log_unpaused_rewrite(&mut self) {
+        if !self.paused {
+            self.rewrites += 1;
+        }
+    }
+
+    /**
+     * Counts as a rewrite and stops logging single writes until the next add, set, removal or change tick.
+     */
+    pub fn pause(&mut self, paused: bool) {
+        if paused {
+            self.rewrites += 1;
+        }
+        self.paused = paused;
+    }
+
+    pub fn cursor(&self) -> ChangeCursor {
+        ChangeCursor{ position: self.changed_end(), rewrites: self.rewrites }
+    }
+
+    pub fn changes_since(&self, cursor: ChangeCursor) -> Option<&[EntityIndex]> {
+        if !self.logging || self.paused || cursor.rewrites != self.rewrites || cursor.position < self.changed_offset {
+            return None;
+        }
+        Some(&self.changed[(cursor.position - self.changed_offset) as usize..])
+    }
+
+    fn changed_end(&self) -> u64 {
+        self.changed_offset + self.changed.len() as u64
+    }
+}
+
+/**
+ * Structural change of one component, values are the old and the new component.
+ */
+pub enum LifecycleEvent<T> {
+    Added(EntityIndex, T),
+    Replaced(EntityIndex, T, T),
+    Removed(EntityIndex, T),
+}
+
+/**
+ * Lifecycle events of a store, only recorded once lifecycle hooks are registered for its component.
+ */
+pub struct LifecycleLog<T> {
+    recording: bool,
+    events: Vec<LifecycleEvent<T>>,
+}
+
+impl<T> Default for LifecycleLog<T> {
+    fn default() -> Self {
+        Self{
+            recording: false,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> LifecycleLog<T> {
+    pub fn record(&mut self) {
+        self.recording = true;
+    }
+
+    pub fn added(&mut self, index: EntityIndex, value: &T) {
+        if self.recording {
+            self.events.push(LifecycleEvent::Added(index, value.clone()));
+        }
+    }
+
+    pub fn replaced(&mut self, index: EntityIndex, old: T, new: &T) {
+        if self.recording {
+            self.events.push(LifecycleEvent::Replaced(index, old, new.clone()));
+        }
+    }
+
+    pub fn removed(&mut self, index: EntityIndex, old: T) {
+        if self.recording {
+            self.events.push(LifecycleEvent::Removed(index, old));
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<LifecycleEvent<T>> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+/**
+ * Any type implementing ComponentStore<Self> + GenericComponentStore can be used as the storage of a component.
+ * The store is created through ComponentStore::new when the component is registered,
+ * or handed over directly via EntityComponentManager::register_component_with_store.
+ */
+pub trait Component : Clone + Default + Sync + Send + 'static
 {
-    type Storage : ComponentStore<Self>;
-}
\ No newline at end of file
+    type Storage : ComponentStore<Self> + GenericComponentStore + Send + Sync + 'static;
+}
+
+/**
+ * Type erased access to a registered store, as it is kept by the EntityComponentManager.
+ * Implemented for every Arc<RwLock<S>> where S is a GenericComponentStore.
+ */
+pub trait ComponentStoreAccessor {
+    fn exec(&self, f: &mut dyn FnMut(&mut dyn GenericComponentStore) -> ());
+
+    fn exec_ref(&self, f: &mut dyn FnMut(&dyn GenericComponentStore) -> ());
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn as_any_ref(&self) -> &dyn Any;
+}
+
+impl<S: 'static + GenericComponentStore> ComponentStoreAccessor for Arc<RwLock<S>> {
+    fn exec(&self, f: &mut dyn FnMut(&mut dyn GenericComponentStore) -> ()) {
+        let mut guard = spin_on!(self.try_write());
+
+        let generic_self: &mut dyn GenericComponentStore = &mut *guard;
+
+        f(generic_self);
+    }
+
+    fn exec_ref(&self, f: &mut dyn FnMut(&dyn GenericComponentStore) -> ()) {
+        let guard = spin_on!(self.try_read());
+
+        let generic_self: &dyn GenericComponentStore = &*guard;
+
+        f(generic_self);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/**
+ * State an EntityComponentManager shares between its stores.
+ */
+#[derive(Clone)]
+pub struct StoreContext {
+    pub tables: Arc<std::sync::Mutex<ArchetypeRegistry>>,
+    cloners: Arc<std::sync::Mutex<rustc_hash::FxHashMap<TypeId, ComponentCloner>>>,
+}
+
+impl Default for StoreContext {
+    fn default() -> Self {
+        Self{
+            tables: Arc::new(std::sync::Mutex::new(ArchetypeRegistry::default())),
+            cloners: Arc::new(std::sync::Mutex::new(rustc_hash::FxHashMap::default())),
+        }
+    }
+}
+
+type ComponentCloner = fn(&dyn GenericComponentStore, EntityIndex) -> Option<Box<dyn AnyComponent>>;
+
+impl StoreContext {
+    /**
+     * Remembers how to copy components of type C out of their type erased store, called whenever a store is created.
+     */
+    pub fn register_cloner<C: Component>(&self) {
+        self.cloners.lock().unwrap().insert(TypeId::of::<C>(), clone_component::<C>);
+    }
+
+    /**
+     * Copies the component of the entity out of the store of the component type.
+     */
+    pub fn clone_component(&self, type_id: TypeId, store: &dyn GenericComponentStore, index: EntityIndex) -> Option<Box<dyn AnyComponent>> {
+        let cloner = *self.cloners.lock().unwrap().get(&type_id)?;
+        cloner(store, index)
+    }
+}
+
+fn clone_component<C: Component>(store: &dyn GenericComponentStore, index: EntityIndex) -> Option<Box<dyn AnyComponent>> {
+    let store = store.as_any().downcast_ref::<C::Storage>().unwrap();
+    store.get(index).map(|value| Box::new(value.clone()) as Box<dyn AnyComponent>)
+}
+
+pub type StoreMap = rustc_hash::FxHashMap<TypeId, Box<dyn ComponentStoreAccessor + Sync + Send>>;
+
+/**
+ * Wraps a store into the accessor box that is kept in the StoreMap of an EntityComponentManager.
+ */
+pub fn make_store_accessor<S: 'static + GenericComponentStore + Send + Sync>(store: S) -> Box<dyn ComponentStoreAccessor + Sync + Send> {
+    Box::new(Arc::new(RwLock::new(store)))
+}
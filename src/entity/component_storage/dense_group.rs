@@ -0,0 +1,355 @@
+use super::*;
+
+use std::sync::Mutex;
+
+const NO_SLOT: u32 = !0;
+
+#[derive(Clone, Copy)]
+enum GroupMove {
+    /**
+     * The entity got the last component of the group and moves to slot, the first slot behind the group.
+     */
+    Join{ entity: EntityIndex, slot: u32 },
+    /**
+     * The member in slot lost a component of the group, it swaps with the last member in last and leaves the group.
+     */
+    Leave{ slot: u32, last: u32 },
+}
+
+/**
+ * Shared bookkeeping of the DenseStores owned by one DenseGroup.
+ * It tracks which of the group components every entity has, an entity that has all of them is a member.
+ * Entities joining or leaving the group are logged as moves, every member store replays the log
+ * to keep the members in the leading slots of its dense arrays, in the same order as the other stores.
+ */
+pub struct GroupRegistry {
+    store_count: usize,
+    owned: Vec<u32>,
+    slots: Vec<u32>,
+    members: Vec<EntityIndex>,
+    moves: Vec<GroupMove>,
+    moves_offset: usize,
+    cursors: Vec<Option<usize>>,
+    generation: u64,
+}
+
+impl GroupRegistry {
+    fn new(store_count: usize) -> Self {
+        assert!(store_count <= 32, "a DenseGroup can own at most 32 stores, was given {}", store_count);
+        Self{
+            store_count,
+            owned: Vec::new(),
+            slots: Vec::new(),
+            members: Vec::new(),
+            moves: Vec::new(),
+            moves_offset: 0,
+            cursors: vec![None; store_count],
+            generation: 0,
+        }
+    }
+
+    fn all_owned(&self) -> u32 {
+        (((1u64 << self.store_count) - 1) & u32::MAX as u64) as u32
+    }
+
+    fn moves_end(&self) -> usize {
+        self.moves_offset + self.moves.len()
+    }
+
+    fn moves_since(&self, cursor: usize) -> &[GroupMove] {
+        &self.moves[cursor - self.moves_offset..]
+    }
+
+    fn advance_store(&mut self, store: usize, cursor: usize) {
+        self.cursors[store] = Some(cursor);
+        self.trim_moves();
+    }
+
+    fn unregister_store(&mut self, store: usize) {
+        self.cursors[store] = None;
+        self.trim_moves();
+    }
+
+    fn trim_moves(&mut self) {
+        let min = self.cursors.iter().filter_map(|c| *c).min().unwrap_or(self.moves_end());
+        if min > self.moves_offset {
+            self.moves.drain(..min - self.moves_offset);
+            self.moves_offset = min;
+        }
+    }
+
+    /**
+     * Replaces the components the given store owns and logs the joins of all members from scratch.
+     * Every store of the group then replays the log from the reset on, starting with an empty group.
+     */
+    fn reset(&mut self, store: usize, entities: &[EntityIndex]) {
+        self.generation += 1;
+        for owned in self.owned.iter_mut() {
+            *owned &= !(1 << store);
+        }
+        self.slots.fill(NO_SLOT);
+        self.members.clear();
+        self.moves_offset = self.moves_end();
+        self.moves.clear();
+        for cursor in self.cursors.iter_mut().filter(|c| c.is_some()) {
+            *cursor = Some(self.moves_offset);
+        }
+        for index in entities {
+            self.set_owned(store, *index, true);
+        }
+    }
+
+    fn assure_index(&mut self, index: EntityIndex) {
+        if self.owned.len() <= index as usize {
+            self.owned.resize(index as usize + 1, 0);
+            self.slots.resize(index as usize + 1, NO_SLOT);
+        }
+    }
+
+    /**
+     * Records that the given store added or removed the component of the entity, logs a move if that changes its membership.
+     */
+    fn set_owned(&mut self, store: usize, index: EntityIndex, owned: bool) {
+        self.assure_index(index);
+        let was_member = self.owned[index as usize] == self.all_owned();
+        if owned {
+            self.owned[index as usize] |= 1 << store;
+        } else {
+            self.owned[index as usize] &= !(1 << store);
+        }
+        let is_member = self.owned[index as usize] == self.all_owned();
+
+        if !was_member && is_member {
+            let slot = self.members.len() as u32;
+            self.slots[index as usize] = slot;
+            self.members.push(index);
+            self.moves.push(GroupMove::Join{ entity: index, slot });
+        } else if was_member && !is_member {
+            let slot = self.slots[index as usize];
+            let last = self.members.len() as u32 - 1;
+            self.members.swap_remove(slot as usize);
+            if slot != last {
+                self.slots[self.members[slot as usize] as usize] = slot;
+            }
+            self.slots[index as usize] = NO_SLOT;
+            self.moves.push(GroupMove::Leave{ slot, last });
+        }
+    }
+}
+
+/**
+ * Membership of a DenseStore in a group, replayed tells how far the store replayed the moves of the group.
+ * A store whose generation is behind the registry has not reported its entities since the group was reset.
+ */
+pub(crate) struct GroupMembership {
+    registry: Arc<Mutex<GroupRegistry>>,
+    store: usize,
+    generation: u64,
+    replayed: usize,
+    len: usize,
+}
+
+impl GroupMembership {
+    pub(crate) fn new(registry: Arc<Mutex<GroupRegistry>>, store: usize, len: usize) -> Self {
+        let (generation, replayed) = {
+            let mut registry = registry.lock().unwrap();
+            let replayed = registry.moves_end();
+            registry.advance_store(store, replayed);
+            (registry.generation, replayed)
+        };
+        Self{ registry, store, generation, replayed, len }
+    }
+
+    pub(crate) fn registry(&self) -> &Arc<Mutex<GroupRegistry>> {
+        &self.registry
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub(crate) fn replayed(&self) -> usize {
+        self.replayed
+    }
+
+    /**
+     * Number of members in the leading slots of the store, as far as it replayed the moves.
+     */
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /**
+     * Records the add or removal of the component and calls swap for every move the store has to replay, in order.
+     * If the group was reset since the last update, the store starts over with an empty group,
+     * the joins from the reset on then arrange it from any order.
+     */
+    pub(crate) fn update(&mut self, changed: Option<(EntityIndex, bool)>, mut swap: impl FnMut(GroupSwap)) {
+        let mut registry = self.registry.lock().unwrap();
+        if self.generation != registry.generation {
+            // the cursor of the store was moved to the reset, so no move since was trimmed
+            self.generation = registry.generation;
+            self.replayed = registry.moves_offset;
+            self.len = 0;
+        }
+        if let Some((index, owned)) = changed {
+            registry.set_owned(self.store, index, owned);
+        }
+        for group_move in registry.moves_since(self.replayed) {
+            match *group_move {
+                GroupMove::Join{ entity, slot } => {
+                    swap(GroupSwap::Entity(entity, slot as usize));
+                    self.len += 1;
+                },
+                GroupMove::Leave{ slot, last } => {
+                    swap(GroupSwap::Slots(slot as usize, last as usize));
+                    self.len -= 1;
+                },
+            }
+        }
+        self.replayed = registry.moves_end();
+        registry.advance_store(self.store, self.replayed);
+    }
+
+    /**
+     * Called when the store replaced its contents with the given entities, resets the group so all its stores arrange themselves again on their next access.
+     */
+    pub(crate) fn reset(&mut self, entities: &[EntityIndex]) {
+        self.registry.lock().unwrap().reset(self.store, entities);
+    }
+}
+
+impl Drop for GroupMembership {
+    fn drop(&mut self) {
+        if let Ok(mut registry) = self.registry.lock() {
+            registry.unregister_store(self.store);
+        }
+    }
+}
+
+/**
+ * Reordering a member store has to do to replay a move of its group.
+ */
+pub(crate) enum GroupSwap {
+    /**
+     * Swap the slot of the entity with the given slot.
+     */
+    Entity(EntityIndex, usize),
+    /**
+     * Swap the two slots.
+     */
+    Slots(usize, usize),
+}
+
+/**
+ * Store whose dense arrays can be owned by a DenseGroup.
+ */
+pub trait GroupedStore: GenericComponentStore {
+    fn dense_entities(&self) -> &[EntityIndex];
+
+    /**
+     * Registry of the group owning the store, the generation the store reported its entities in and how far it replayed the moves.
+     */
+    fn group_state(&self) -> Option<(*const Mutex<GroupRegistry>, u64, usize)>;
+
+    /**
+     * Hands the store to a group, the members are moved to the leading slots in the order of members.
+     * Panics if the store is owned by another group.
+     */
+    fn join_group(&mut self, registry: Arc<Mutex<GroupRegistry>>, store: usize, members: &[EntityIndex]);
+
+    /**
+     * Replays the moves other stores of the group made since the last add, rem or sync of this store.
+     */
+    fn sync_group(&mut self);
+}
+
+/**
+ * Owning group over several DenseStores, modelled after the owning groups of EnTT.
+ * The entities that have all components of the group sit in the first len entries of every store, in the same order,
+ * so joins over the group walk the stores in lock-step with iterate_over_group!.
+ * The group is maintained by the stores: an entity that gets the last group component is swapped into the slot behind the group,
+ * one that loses a group component is swapped with the last member and out of the group.
+ * Like the archetype moves of TableStores, the stores that did not add or remove the component replay these swaps lazily
+ * on their next add, rem, sync_group and EntityComponentManager::cleanup, until then joins over the group fall back to lookups.
+ * Restoring a rollback frame into a member store resets the group to the restored components,
+ * every store starts over on its next access and arranges itself by replaying the joins logged by the reset.
+ */
+pub struct DenseGroup {
+    registry: Arc<Mutex<GroupRegistry>>,
+}
+
+impl DenseGroup {
+    /**
+     * Declares the group and arranges the stores once, afterwards they keep themselves arranged.
+     * Panics if one of the stores is owned by another group.
+     */
+    #[allow(unused)]
+    pub fn new(stores: &mut [&mut dyn GroupedStore]) -> Self {
+        let mut group = Self{ registry: Arc::new(Mutex::new(GroupRegistry::new(stores.len()))) };
+        group.rebuild(stores);
+        group
+    }
+
+    /**
+     * Number of entities that have all components of the group.
+     */
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.registry.lock().unwrap().members.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /**
+     * True, if the stores are owned by this group and replayed all of its moves, so they can be walked in lock-step.
+     */
+    #[allow(unused)]
+    pub fn is_arranged(&self, stores: &[&dyn GroupedStore]) -> bool {
+        let registry = self.registry.lock().unwrap();
+        stores.iter().all(|store| {
+            store.group_state() == Some((Arc::as_ptr(&self.registry), registry.generation, registry.moves_end()))
+        })
+    }
+
+    /**
+     * Arranges the stores right away, which they otherwise do on their next access.
+     * Returns false, if no store had to start over after a reset of the group.
+     */
+    #[allow(unused)]
+    pub fn arrange(&mut self, stores: &mut [&mut dyn GroupedStore]) -> bool {
+        let generation = self.registry.lock().unwrap().generation;
+        let reset = stores.iter().any(|store| store.group_state().map(|(_, store_generation, _)| store_generation) != Some(generation));
+        for store in stores.iter_mut() {
+            store.sync_group();
+        }
+        reset
+    }
+
+    fn rebuild(&mut self, stores: &mut [&mut dyn GroupedStore]) {
+        let mut members = Vec::new();
+        if let Some(smallest) = stores.iter().min_by_key(|store| store.dense_entities().len()) {
+            members = smallest.dense_entities().iter().copied().filter(|index| stores.iter().all(|store| store.has(*index))).collect();
+        }
+        members.sort_unstable();
+        {
+            let mut registry = self.registry.lock().unwrap();
+            for (store_slot, store) in stores.iter().enumerate() {
+                for index in store.dense_entities() {
+                    registry.assure_index(*index);
+                    registry.owned[*index as usize] |= 1 << store_slot;
+                }
+            }
+            for (slot, index) in members.iter().enumerate() {
+                registry.slots[*index as usize] = slot as u32;
+            }
+            registry.members = members.clone();
+        }
+        for (store_slot, store) in stores.iter_mut().enumerate() {
+            store.join_group(self.registry.clone(), store_slot, &members);
+        }
+    }
+}
@@ -4,9 +4,13 @@ pub struct DenseStore<T: Default + Clone> {
     sparse_indices: Vec<EntityIndex>,
     dense_indices: Vec<EntityIndex>,
     dense_values: Vec<T>,
+    dense_ticks: Vec<ComponentTicks>,
+    changes: ChangeTracker,
+    lifecycle: LifecycleLog<T>,
+    group: Option<GroupMembership>,
 }
 
-impl<T: 'static + Default + Clone> GenericComponentStore for DenseStore<T> {
+impl<T: 'static + Default + Clone + Send + Sync> GenericComponentStore for DenseStore<T> {
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -15,29 +19,124 @@ impl<T: 'static + Default + Clone> GenericComponentStore for DenseStore<T> {
         self
     }
 
-    fn optimize(&mut self) {}
+    fn optimize(&mut self) {
+        let used = self.dense_indices.iter().max().map_or(0, |index| *index as usize + 1);
+        self.sparse_indices.truncate(used);
+        self.sparse_indices.shrink_to_fit();
+        self.dense_indices.shrink_to_fit();
+        self.dense_values.shrink_to_fit();
+        self.dense_ticks.shrink_to_fit();
+    }
 
     fn has(&self, index: EntityIndex) -> bool {
         let index = index as usize;
         index < self.sparse_indices.len() && self.sparse_indices[index] != !(0 as EntityIndex)
     }
 
+    fn maintain(&mut self) {
+        self.sync_group();
+    }
+
     fn rem(&mut self, index: EntityIndex) {
         assert!(self.has(index), "index was {}, val was {}", index, self.sparse_indices[index as usize]);
-        self.assure_index(index);
+        self.update_group(Some((index, false)));
         let dense_index = self.sparse_indices[index as usize] as usize;
-        let last_value = self.dense_values.pop().unwrap();
-        let last_index = self.dense_indices.pop().unwrap();
-        self.dense_values[dense_index] = last_value;
-        self.dense_indices[dense_index] = last_index;
+        let old = self.dense_values.swap_remove(dense_index);
+        self.dense_indices.swap_remove(dense_index);
+        self.dense_ticks.swap_remove(dense_index);
+        if dense_index < self.dense_indices.len() {
+            let moved_index = self.dense_indices[dense_index];
+            self.sparse_indices[moved_index as usize] = dense_index as EntityIndex;
+        }
         self.sparse_indices[index as usize] = !0;
+        self.changes.log_removal(index);
+        self.lifecycle.removed(index, old);
     }
 
     fn len(&self) -> usize {
-        0
+        self.dense_indices.len()
+    }
+
+    fn iter_indices(&self) -> Box<dyn Iterator<Item = EntityIndex> + '_> {
+        Box::new(self.dense_indices.iter().map(|i| *i))
+    }
+
+    fn memory(&self) -> StoreMemory {
+        StoreMemory{
+            len: self.dense_indices.len(),
+            capacity: self.dense_values.capacity(),
+            bytes: (self.sparse_indices.capacity() + self.dense_indices.capacity()) * std::mem::size_of::<EntityIndex>()
+                + self.dense_values.capacity() * std::mem::size_of::<T>()
+                + self.dense_ticks.capacity() * std::mem::size_of::<ComponentTicks>(),
+        }
+    }
+
+    fn change_tick(&self) -> Tick {
+        self.changes.tick
+    }
+
+    fn set_change_tick(&mut self, tick: Tick) {
+        self.changes.set_tick(tick);
+    }
+
+    fn component_ticks(&self, index: EntityIndex) -> Option<ComponentTicks> {
+        if self.has(index) {
+            Some(self.dense_ticks[self.sparse_indices[index as usize] as usize])
+        } else {
+            None
+        }
+    }
+
+    fn removed(&self) -> &[(EntityIndex, Tick)] {
+        self.changes.removed()
+    }
+
+    fn log_changes(&mut self) -> bool {
+        self.changes.log_changes();
+        true
+    }
+
+    fn change_cursor(&self) -> ChangeCursor {
+        self.changes.cursor()
+    }
+
+    fn changes_since(&self, cursor: ChangeCursor) -> Option<&[EntityIndex]> {
+        self.changes.changes_since(cursor)
+    }
+
+    fn pause_change_log(&mut self, paused: bool) {
+        self.changes.pause(paused);
+    }
+
+    fn record_lifecycle(&mut self) -> bool {
+        self.lifecycle.record();
+        true
+    }
+
+    fn take_lifecycle(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new(self.lifecycle.take()))
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any + Send + Sync>> {
+        Some(Box::new((self.sparse_indices.clone(), self.dense_indices.clone(), self.dense_values.clone(), self.dense_ticks.clone())))
+    }
+
+    fn restore_state(&mut self, state: &(dyn Any + Send + Sync)) {
+        let (sparse_indices, dense_indices, dense_values, dense_ticks) = state.downcast_ref::<DenseState<T>>().unwrap();
+        self.sparse_indices.clone_from(sparse_indices);
+        self.dense_indices.clone_from(dense_indices);
+        self.dense_values.clone_from(dense_values);
+        self.dense_ticks.clone_from(dense_ticks);
+        self.lifecycle.take();
+        self.changes.log_rewrite();
+        if let Some(group) = self.group.as_mut() {
+            group.reset(&self.dense_indices);
+        }
     }
 }
 
+type DenseState<T> = (Vec<EntityIndex>, Vec<EntityIndex>, Vec<T>, Vec<ComponentTicks>);
+
 impl<T: Default + Clone> DenseStore<T> {
     #[allow(unused)]
     pub fn iter(&self) -> impl Iterator<Item = &T> {
@@ -46,7 +145,14 @@ impl<T: Default + Clone> DenseStore<T> {
     
     #[allow(unused)]
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
         self.dense_values.iter_mut()
+            .zip(self.dense_ticks.iter_mut())
+            .map(move |(value, ticks)| {
+                ticks.changed = tick;
+                value
+            })
     }
 
     #[allow(unused)]
@@ -56,7 +162,14 @@ impl<T: Default + Clone> DenseStore<T> {
     
     #[allow(unused)]
     pub fn iter_entity_mut(&mut self) -> impl Iterator<Item = (EntityIndex, &mut T)> {
-        self.dense_indices.iter().map(|i|*i).zip(self.dense_values.iter_mut())
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
+        self.dense_indices.iter().map(|i|*i)
+            .zip(self.dense_values.iter_mut().zip(self.dense_ticks.iter_mut()))
+            .map(move |(index, (value, ticks))| {
+                ticks.changed = tick;
+                (index, value)
+            })
     }
 
     #[allow(unused)]
@@ -80,6 +193,8 @@ impl<T: Default + Clone> DenseStore<T> {
 
         let n = self.dense_indices.len();
         
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
         (0..n).step_by(batch_size).into_iter().map(move |i| {
             let forgotten_self = unsafe{std::mem::transmute::<&mut Self, &mut Self>(self)};
             forgotten_self.dense_indices[i..]
@@ -88,28 +203,66 @@ impl<T: Default + Clone> DenseStore<T> {
                 .map(|i| *i)
                 .zip(forgotten_self.dense_values[i..]
                 .iter_mut()
+                .zip(forgotten_self.dense_ticks[i..].iter_mut())
                 .take(batch_size))
+                .map(move |(index, (value, ticks))| {
+                    ticks.changed = tick;
+                    (index, value)
+                })
         })
     }
 
+    /**
+     * Components of a range of dense slots, position_count is the number of slots.
+     */
+    #[allow(unused)]
+    pub fn iter_entity_range(&self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &T)> {
+        self.dense_indices[range.clone()].iter().copied().zip(self.dense_values[range].iter())
+    }
+
+    /**
+     * Like iter_entity_range, counts as a rewrite unless the change log is paused for parallel batches.
+     */
+    #[allow(unused)]
+    pub fn iter_entity_range_mut(&mut self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &mut T)> {
+        self.changes.log_unpaused_rewrite();
+        let tick = self.changes.tick;
+        self.dense_indices[range.clone()].iter().copied()
+            .zip(self.dense_values[range.clone()].iter_mut().zip(self.dense_ticks[range].iter_mut()))
+            .map(move |(index, (value, ticks))| {
+                ticks.changed = tick;
+                (index, value)
+            })
+    }
+
     fn assure_index(&mut self, index: EntityIndex) {
         if index as usize >= self.sparse_indices.len() {
             self.sparse_indices.resize(index as usize + 1, !0);
         }
     }
-    #[allow(unused)]
 
+    /**
+     * Reorders the dense arrays by entity index, the members of a DenseGroup keep their leading slots.
+     */
+    #[allow(unused)]
     pub fn sort(&mut self) {
+        if self.group.is_some() {
+            self.sort_by_key(|index| index);
+            return;
+        }
+
         let mut new_dense_values = Vec::<T>::with_capacity(self.dense_values.len());
         let mut new_dense_indices = Vec::<EntityIndex>::with_capacity(self.dense_values.len());
+        let mut new_dense_ticks = Vec::<ComponentTicks>::with_capacity(self.dense_values.len());
 
-        for dense_index in self.sparse_indices.iter_mut() {
+        for (entity_index, dense_index) in self.sparse_indices.iter_mut().enumerate() {
             *dense_index = 
             if *dense_index != !(0 as EntityIndex) {
-                new_dense_indices.push(*dense_index);
+                new_dense_indices.push(entity_index as EntityIndex);
                 let mut el = T::default();
                 std::mem::swap(&mut self.dense_values[*dense_index as usize], &mut el);
                 new_dense_values.push(el);
+                new_dense_ticks.push(self.dense_ticks[*dense_index as usize]);
                 (new_dense_indices.len() - 1) as EntityIndex
             } else {
                 !(0 as EntityIndex)
@@ -118,6 +271,107 @@ impl<T: Default + Clone> DenseStore<T> {
 
         self.dense_indices = new_dense_indices;
         self.dense_values = new_dense_values;
+        self.dense_ticks = new_dense_ticks;
+    }
+
+    /**
+     * Reorders the dense arrays by the key of each entity index.
+     * The members of a DenseGroup keep their leading slots, only the entries behind them are sorted.
+     */
+    #[allow(unused)]
+    pub fn sort_by_key<K: Ord>(&mut self, mut key: impl FnMut(EntityIndex) -> K) {
+        self.update_group(None);
+        let leading = self.group.as_ref().map_or(0, |group| group.len());
+        let mut tail = (leading..self.dense_indices.len()).collect::<Vec<_>>();
+        tail.sort_by_cached_key(|dense_index| key(self.dense_indices[*dense_index]));
+
+        let mut new_dense_values = Vec::<T>::with_capacity(self.dense_values.len());
+        let mut new_dense_indices = Vec::<EntityIndex>::with_capacity(self.dense_values.len());
+        let mut new_dense_ticks = Vec::<ComponentTicks>::with_capacity(self.dense_values.len());
+        for dense_index in (0..leading).chain(tail) {
+            let entity_index = self.dense_indices[dense_index];
+            self.sparse_indices[entity_index as usize] = new_dense_indices.len() as EntityIndex;
+            new_dense_indices.push(entity_index);
+            new_dense_values.push(std::mem::take(&mut self.dense_values[dense_index]));
+            new_dense_ticks.push(self.dense_ticks[dense_index]);
+        }
+
+        self.dense_indices = new_dense_indices;
+        self.dense_values = new_dense_values;
+        self.dense_ticks = new_dense_ticks;
+    }
+
+    fn swap_slots(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+        self.dense_indices.swap(a, b);
+        self.dense_values.swap(a, b);
+        self.dense_ticks.swap(a, b);
+        self.sparse_indices[self.dense_indices[a] as usize] = a as EntityIndex;
+        self.sparse_indices[self.dense_indices[b] as usize] = b as EntityIndex;
+    }
+
+    /**
+     * Records the change of the component in the group of the store and replays the moves of the group.
+     */
+    fn update_group(&mut self, changed: Option<(EntityIndex, bool)>) {
+        if let Some(mut group) = self.group.take() {
+            group.update(changed, |swap| match swap {
+                GroupSwap::Entity(index, slot) => {
+                    let dense_index = self.sparse_indices[index as usize] as usize;
+                    self.swap_slots(dense_index, slot);
+                },
+                GroupSwap::Slots(a, b) => self.swap_slots(a, b),
+            });
+            self.group = Some(group);
+        }
+    }
+
+    /**
+     * Iterates the first len entries of the dense arrays, used to walk the shared range of a DenseGroup.
+     */
+    #[allow(unused)]
+    pub fn iter_leading(&self, len: usize) -> impl Iterator<Item = (EntityIndex, &T)> {
+        self.dense_indices[..len].iter().map(|i| *i).zip(self.dense_values[..len].iter())
+    }
+
+    #[allow(unused)]
+    pub fn iter_leading_mut(&mut self, len: usize) -> impl Iterator<Item = (EntityIndex, &mut T)> {
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
+        self.dense_indices[..len].iter().map(|i| *i)
+            .zip(self.dense_values[..len].iter_mut().zip(self.dense_ticks[..len].iter_mut()))
+            .map(move |(index, (value, ticks))| {
+                ticks.changed = tick;
+                (index, value)
+            })
+    }
+}
+
+impl<T: 'static + Default + Clone + Send + Sync> GroupedStore for DenseStore<T> {
+    fn dense_entities(&self) -> &[EntityIndex] {
+        &self.dense_indices
+    }
+
+    fn group_state(&self) -> Option<(*const std::sync::Mutex<GroupRegistry>, u64, usize)> {
+        self.group.as_ref().map(|group| (Arc::as_ptr(group.registry()), group.generation(), group.replayed()))
+    }
+
+    fn join_group(&mut self, registry: Arc<std::sync::Mutex<GroupRegistry>>, store: usize, members: &[EntityIndex]) {
+        assert!(self.group.is_none(), "the store is already owned by another DenseGroup");
+
+        let mut slot_of = vec![!(0 as EntityIndex); self.sparse_indices.len()];
+        for (slot, index) in members.iter().enumerate() {
+            slot_of[*index as usize] = slot as EntityIndex;
+        }
+        let len = members.len();
+        self.sort_by_key(|index| (slot_of[index as usize], index));
+        self.group = Some(GroupMembership::new(registry, store, len));
+    }
+
+    fn sync_group(&mut self) {
+        self.update_group(None);
     }
 }
 
@@ -129,6 +383,10 @@ impl<T: 'static + Default + Clone + Component> ComponentStore<T> for DenseStore<
             sparse_indices: Vec::new(),
             dense_indices: Vec::new(),
             dense_values: Vec::new(),
+            dense_ticks: Vec::new(),
+            changes: ChangeTracker::default(),
+            lifecycle: LifecycleLog::default(),
+            group: None,
         }
     }
 
@@ -147,6 +405,8 @@ impl<T: 'static + Default + Clone + Component> ComponentStore<T> for DenseStore<
         if self.has(index) {
             let index = index as usize;
             let dense_index = self.sparse_indices[index] as usize;
+            self.dense_ticks[dense_index].changed = self.changes.tick;
+            self.changes.log_access(index as EntityIndex);
             Some(&mut self.dense_values[dense_index])
         }
         else {
@@ -158,7 +418,10 @@ impl<T: 'static + Default + Clone + Component> ComponentStore<T> for DenseStore<
         assert!(self.has(index));
         let index = index as usize;
         let dense_index = self.sparse_indices[index] as usize;
-        self.dense_values[dense_index] = value;
+        let old = std::mem::replace(&mut self.dense_values[dense_index], value);
+        self.dense_ticks[dense_index].changed = self.changes.tick;
+        self.changes.log_change(index as EntityIndex);
+        self.lifecycle.replaced(index as EntityIndex, old, &self.dense_values[dense_index]);
     }
 
     fn add(&mut self, index: EntityIndex, value: T) {
@@ -166,52 +429,24 @@ impl<T: 'static + Default + Clone + Component> ComponentStore<T> for DenseStore<
         self.assure_index(index);
         self.dense_values.push(value);
         self.dense_indices.push(index);
+        self.dense_ticks.push(ComponentTicks::new(self.changes.tick));
+        self.changes.log_change(index);
         self.sparse_indices[index as usize] = self.dense_indices.len() as EntityIndex - 1;
+        self.lifecycle.added(index, self.dense_values.last().unwrap());
+        self.update_group(Some((index, true)));
     }
 }
 
-use async_std::sync::RwLock;
-
-pub trait ComponentStoreAccessor: {
-    fn exec(&self, f: &mut dyn FnMut(&mut dyn GenericComponentStore) -> ());
-
-    fn as_any_mut(&mut self) -> &mut dyn Any;
-
-    fn as_any_ref(&self) -> &dyn Any;
-}
-
-impl<T: 'static + Default + Clone> ComponentStoreAccessor for Arc<RwLock<LinearStore<T>>> {
-    fn exec(&self, f: &mut dyn FnMut(&mut dyn GenericComponentStore) -> ()) {
-        let mut guard = spin_on!(self.try_write());
-
-        let generic_self: &mut dyn GenericComponentStore = &mut *guard;
-
-        f(generic_self);
-    }
-
-    fn as_any_mut(&mut self) -> &mut dyn Any {
-        self
+impl<T: 'static + Default + Clone + Send + Sync> BatchedStore<T> for DenseStore<T> {
+    fn batches(&self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &T)> + Send + '_>> {
+        self.iter_entity_batch(batch_size)
+            .map(|batch| Box::new(batch) as Box<dyn Iterator<Item = (EntityIndex, &T)> + Send + '_>)
+            .collect()
     }
 
-    fn as_any_ref(&self) -> &dyn Any {
-        self
+    fn batches_mut(&mut self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &mut T)> + Send + '_>> {
+        self.iter_entity_mut_batch(batch_size)
+            .map(|batch| Box::new(batch) as Box<dyn Iterator<Item = (EntityIndex, &mut T)> + Send + '_>)
+            .collect()
     }
 }
-
-impl<T: 'static + Default + Clone> ComponentStoreAccessor for Arc<RwLock<DenseStore<T>>> {
-    fn exec(&self, f: &mut dyn FnMut(&mut dyn GenericComponentStore) -> ()) {
-        let mut guard = spin_on!(self.try_write());
-
-        let generic_self: &mut dyn GenericComponentStore = &mut *guard;
-
-        f(generic_self);
-    }
-
-    fn as_any_mut(&mut self) -> &mut dyn Any {
-        self
-    }
-
-    fn as_any_ref(&self) -> &dyn Any {
-        self
-    }
-}
\ No newline at end of file
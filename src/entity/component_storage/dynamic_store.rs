@@ -0,0 +1,519 @@
+use super::*;
+
+use crate::entity::reflect::ReflectValue;
+
+/**
+ * Identifies a component registered at run time via EntityComponentManager::register_dynamic_component.
+ * Ids are only valid in the manager that handed them out.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct DynamicComponentId(pub(crate) u32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutError {
+    Syntax(String),
+    UnknownType(String),
+    DuplicateField(String),
+}
+
+impl std::fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutError::Syntax(text) => write!(f, "invalid layout \"{}\", expected: Name {{ field: type, ... }}", text),
+            LayoutError::UnknownType(name) => write!(f, "unknown field type \"{}\"", name),
+            LayoutError::DuplicateField(name) => write!(f, "field \"{}\" is declared twice", name),
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+/**
+ * Type of a field of a dynamic component, values are stored little endian.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FieldKind {
+    Bool,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+}
+
+impl FieldKind {
+    #[allow(unused)]
+    pub fn size(self) -> usize {
+        match self {
+            FieldKind::Bool => 1,
+            FieldKind::I32 | FieldKind::U32 | FieldKind::F32 => 4,
+            FieldKind::I64 | FieldKind::U64 | FieldKind::F64 => 8,
+        }
+    }
+
+    #[allow(unused)]
+    pub fn name(self) -> &'static str {
+        match self {
+            FieldKind::Bool => "bool",
+            FieldKind::I32 => "i32",
+            FieldKind::U32 => "u32",
+            FieldKind::I64 => "i64",
+            FieldKind::U64 => "u64",
+            FieldKind::F32 => "f32",
+            FieldKind::F64 => "f64",
+        }
+    }
+
+    #[allow(unused)]
+    pub fn from_name(name: &str) -> Option<Self> {
+        [FieldKind::Bool, FieldKind::I32, FieldKind::U32, FieldKind::I64, FieldKind::U64, FieldKind::F32, FieldKind::F64]
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+/**
+ * Position of a field inside a row, look it up once with DynamicLayout::field and reuse it for every entity.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DynamicField {
+    pub offset: usize,
+    pub kind: FieldKind,
+}
+
+/**
+ * Byte layout of a dynamic component, the fields are packed in declaration order without padding.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct DynamicLayout {
+    name: String,
+    fields: Vec<(String, DynamicField)>,
+    size: usize,
+}
+
+impl DynamicLayout {
+    #[allow(unused)]
+    pub fn new(name: &str, fields: &[(&str, FieldKind)]) -> Result<Self, LayoutError> {
+        let mut layout = Self{ name: name.to_string(), fields: Vec::with_capacity(fields.len()), size: 0 };
+        for (field_name, kind) in fields {
+            if layout.field(field_name).is_some() {
+                return Err(LayoutError::DuplicateField(field_name.to_string()));
+            }
+            layout.fields.push((field_name.to_string(), DynamicField{ offset: layout.size, kind: *kind }));
+            layout.size += kind.size();
+        }
+        Ok(layout)
+    }
+
+    /**
+     * Reads a layout written like a struct declaration, for example "Stats { hp: f32, armor: f32 }".
+     */
+    #[allow(unused)]
+    pub fn parse(text: &str) -> Result<Self, LayoutError> {
+        let syntax_error = || LayoutError::Syntax(text.to_string());
+        let (name, body) = text.trim().split_once('{').ok_or_else(syntax_error)?;
+        let body = body.trim_end().strip_suffix('}').ok_or_else(syntax_error)?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(syntax_error());
+        }
+        let mut fields = Vec::new();
+        for field in body.split(',').map(str::trim).filter(|field| !field.is_empty()) {
+            let (field_name, type_name) = field.split_once(':').ok_or_else(syntax_error)?;
+            let (field_name, type_name) = (field_name.trim(), type_name.trim());
+            if field_name.is_empty() {
+                return Err(syntax_error());
+            }
+            let kind = FieldKind::from_name(type_name).ok_or_else(|| LayoutError::UnknownType(type_name.to_string()))?;
+            fields.push((field_name, kind));
+        }
+        Self::new(name, &fields)
+    }
+
+    #[allow(unused)]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /**
+     * Size of one row in bytes.
+     */
+    #[allow(unused)]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    #[allow(unused)]
+    pub fn field(&self, name: &str) -> Option<DynamicField> {
+        self.fields.iter().find(|(field_name, _)| field_name == name).map(|(_, field)| *field)
+    }
+
+    #[allow(unused)]
+    pub fn fields(&self) -> impl Iterator<Item = (&str, DynamicField)> {
+        self.fields.iter().map(|(name, field)| (name.as_str(), *field))
+    }
+}
+
+/**
+ * Rust types that can be read from and written to a field of the matching kind.
+ */
+pub trait FieldValue: Copy {
+    const KIND: FieldKind;
+
+    fn read(bytes: &[u8]) -> Self;
+
+    fn write(self, bytes: &mut [u8]);
+}
+
+macro_rules! impl_field_value {
+    ($($T:ty => $kind:ident),+) => {
+        $(impl FieldValue for $T {
+            const KIND: FieldKind = FieldKind::$kind;
+
+            fn read(bytes: &[u8]) -> Self {
+                <$T>::from_le_bytes(bytes.try_into().unwrap())
+            }
+
+            fn write(self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_le_bytes());
+            }
+        })+
+    };
+}
+
+impl_field_value!(i32 => I32, u32 => U32, i64 => I64, u64 => U64, f32 => F32, f64 => F64);
+
+impl FieldValue for bool {
+    const KIND: FieldKind = FieldKind::Bool;
+
+    fn read(bytes: &[u8]) -> Self {
+        bytes[0] != 0
+    }
+
+    fn write(self, bytes: &mut [u8]) {
+        bytes[0] = self as u8;
+    }
+}
+
+/**
+ * The bytes of one dynamic component, accessed through the fields of its layout.
+ */
+#[repr(transparent)]
+pub struct DynamicRow([u8]);
+
+impl DynamicRow {
+    fn from_bytes(bytes: &[u8]) -> &Self {
+        unsafe{ &*(bytes as *const [u8] as *const Self) }
+    }
+
+    fn from_bytes_mut(bytes: &mut [u8]) -> &mut Self {
+        unsafe{ &mut *(bytes as *mut [u8] as *mut Self) }
+    }
+
+    #[allow(unused)]
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    #[allow(unused)]
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
+    fn field_bytes(&self, field: DynamicField) -> &[u8] {
+        &self.0[field.offset..field.offset + field.kind.size()]
+    }
+
+    /**
+     * Panics, if T does not match the kind of the field.
+     */
+    #[allow(unused)]
+    pub fn read<T: FieldValue>(&self, field: DynamicField) -> T {
+        assert_eq!(T::KIND, field.kind, "Field of kind {} read as {}.", field.kind.name(), T::KIND.name());
+        T::read(self.field_bytes(field))
+    }
+
+    /**
+     * Panics, if T does not match the kind of the field.
+     */
+    #[allow(unused)]
+    pub fn write<T: FieldValue>(&mut self, field: DynamicField, value: T) {
+        assert_eq!(T::KIND, field.kind, "Field of kind {} written as {}.", field.kind.name(), T::KIND.name());
+        value.write(&mut self.0[field.offset..field.offset + field.kind.size()]);
+    }
+
+    #[allow(unused)]
+    pub fn get(&self, field: DynamicField) -> ReflectValue {
+        let bytes = self.field_bytes(field);
+        match field.kind {
+            FieldKind::Bool => ReflectValue::Bool(bool::read(bytes)),
+            FieldKind::I32 => ReflectValue::Int(i32::read(bytes) as i64),
+            FieldKind::U32 => ReflectValue::Int(u32::read(bytes) as i64),
+            FieldKind::I64 => ReflectValue::Int(i64::read(bytes)),
+            FieldKind::U64 => ReflectValue::Int(u64::read(bytes) as i64),
+            FieldKind::F32 => ReflectValue::Float(f32::read(bytes) as f64),
+            FieldKind::F64 => ReflectValue::Float(f64::read(bytes)),
+        }
+    }
+
+    /**
+     * Returns false, if the field can not hold the value.
+     */
+    #[allow(unused)]
+    pub fn set(&mut self, field: DynamicField, value: ReflectValue) -> bool {
+        let bytes = &mut self.0[field.offset..field.offset + field.kind.size()];
+        match (field.kind, value) {
+            (FieldKind::Bool, ReflectValue::Bool(value)) => value.write(bytes),
+            (FieldKind::I32, ReflectValue::Int(value)) => match i32::try_from(value) {
+                Ok(value) => value.write(bytes),
+                Err(_) => return false,
+            },
+            (FieldKind::U32, ReflectValue::Int(value)) => match u32::try_from(value) {
+                Ok(value) => value.write(bytes),
+                Err(_) => return false,
+            },
+            (FieldKind::I64, ReflectValue::Int(value)) => value.write(bytes),
+            (FieldKind::U64, ReflectValue::Int(value)) => match u64::try_from(value) {
+                Ok(value) => value.write(bytes),
+                Err(_) => return false,
+            },
+            (FieldKind::F32, ReflectValue::Int(value)) => (value as f32).write(bytes),
+            (FieldKind::F32, ReflectValue::Float(value)) => (value as f32).write(bytes),
+            (FieldKind::F64, ReflectValue::Int(value)) => (value as f64).write(bytes),
+            (FieldKind::F64, ReflectValue::Float(value)) => value.write(bytes),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/**
+ * Dense storage for a component whose layout is only known at run time.
+ * Rows are kept in one byte buffer, the store can be used in the iteration macros next to the stores of static components.
+ */
+pub struct DynamicStore {
+    layout: Arc<DynamicLayout>,
+    sparse_indices: Vec<EntityIndex>,
+    dense_indices: Vec<EntityIndex>,
+    data: Vec<u8>,
+    dense_ticks: Vec<ComponentTicks>,
+    changes: ChangeTracker,
+}
+
+impl GenericComponentStore for DynamicStore {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn optimize(&mut self) {
+        let used = self.dense_indices.iter().max().map_or(0, |index| *index as usize + 1);
+        self.sparse_indices.truncate(used);
+        self.sparse_indices.shrink_to_fit();
+        self.dense_indices.shrink_to_fit();
+        self.data.shrink_to_fit();
+        self.dense_ticks.shrink_to_fit();
+    }
+
+    fn has(&self, index: EntityIndex) -> bool {
+        let index = index as usize;
+        index < self.sparse_indices.len() && self.sparse_indices[index] != !(0 as EntityIndex)
+    }
+
+    fn rem(&mut self, index: EntityIndex) {
+        assert!(self.has(index));
+        let stride = self.layout.size;
+        let dense_index = self.sparse_indices[index as usize] as usize;
+        let last = self.dense_indices.len() - 1;
+        self.data.copy_within(last * stride..(last + 1) * stride, dense_index * stride);
+        self.data.truncate(last * stride);
+        self.dense_indices.swap_remove(dense_index);
+        self.dense_ticks.swap_remove(dense_index);
+        if dense_index < self.dense_indices.len() {
+            let moved_index = self.dense_indices[dense_index];
+            self.sparse_indices[moved_index as usize] = dense_index as EntityIndex;
+        }
+        self.sparse_indices[index as usize] = !0;
+        self.changes.log_removal(index);
+    }
+
+    fn len(&self) -> usize {
+        self.dense_indices.len()
+    }
+
+    fn iter_indices(&self) -> Box<dyn Iterator<Item = EntityIndex> + '_> {
+        Box::new(self.dense_indices.iter().map(|i| *i))
+    }
+
+    fn memory(&self) -> StoreMemory {
+        StoreMemory{
+            len: self.dense_indices.len(),
+            capacity: self.dense_ticks.capacity(),
+            bytes: (self.sparse_indices.capacity() + self.dense_indices.capacity()) * std::mem::size_of::<EntityIndex>()
+                + self.data.capacity()
+                + self.dense_ticks.capacity() * std::mem::size_of::<ComponentTicks>(),
+        }
+    }
+
+    fn change_tick(&self) -> Tick {
+        self.changes.tick
+    }
+
+    fn set_change_tick(&mut self, tick: Tick) {
+        self.changes.set_tick(tick);
+    }
+
+    fn component_ticks(&self, index: EntityIndex) -> Option<ComponentTicks> {
+        if self.has(index) {
+            Some(self.dense_ticks[self.sparse_indices[index as usize] as usize])
+        } else {
+            None
+        }
+    }
+
+    fn removed(&self) -> &[(EntityIndex, Tick)] {
+        self.changes.removed()
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any + Send + Sync>> {
+        Some(Box::new((self.sparse_indices.clone(), self.dense_indices.clone(), self.data.clone(), self.dense_ticks.clone())))
+    }
+
+    fn restore_state(&mut self, state: &(dyn Any + Send + Sync)) {
+        let (sparse_indices, dense_indices, data, dense_ticks) = state.downcast_ref::<DynamicState>().unwrap();
+        self.sparse_indices.clone_from(sparse_indices);
+        self.dense_indices.clone_from(dense_indices);
+        self.data.clone_from(data);
+        self.dense_ticks.clone_from(dense_ticks);
+    }
+}
+
+type DynamicState = (Vec<EntityIndex>, Vec<EntityIndex>, Vec<u8>, Vec<ComponentTicks>);
+
+impl DynamicStore {
+    #[allow(unused)]
+    pub fn new(layout: Arc<DynamicLayout>) -> Self {
+        Self{
+            layout,
+            sparse_indices: Vec::new(),
+            dense_indices: Vec::new(),
+            data: Vec::new(),
+            dense_ticks: Vec::new(),
+            changes: ChangeTracker::default(),
+        }
+    }
+
+    #[allow(unused)]
+    pub fn layout(&self) -> &Arc<DynamicLayout> {
+        &self.layout
+    }
+
+    fn row(&self, dense_index: usize) -> &DynamicRow {
+        let stride = self.layout.size;
+        DynamicRow::from_bytes(&self.data[dense_index * stride..(dense_index + 1) * stride])
+    }
+
+    fn row_mut(&mut self, dense_index: usize) -> &mut DynamicRow {
+        let stride = self.layout.size;
+        DynamicRow::from_bytes_mut(&mut self.data[dense_index * stride..(dense_index + 1) * stride])
+    }
+
+    #[allow(unused)]
+    pub fn get(&self, index: EntityIndex) -> Option<&DynamicRow> {
+        if self.has(index) {
+            Some(self.row(self.sparse_indices[index as usize] as usize))
+        } else {
+            None
+        }
+    }
+
+    #[allow(unused)]
+    pub fn get_mut(&mut self, index: EntityIndex) -> Option<&mut DynamicRow> {
+        if self.has(index) {
+            let dense_index = self.sparse_indices[index as usize] as usize;
+            self.dense_ticks[dense_index].changed = self.changes.tick;
+            Some(self.row_mut(dense_index))
+        } else {
+            None
+        }
+    }
+
+    /**
+     * Adds the component with all bytes zeroed and returns it to be filled in.
+     */
+    #[allow(unused)]
+    pub fn add(&mut self, index: EntityIndex) -> &mut DynamicRow {
+        assert!(!self.has(index));
+        if index as usize >= self.sparse_indices.len() {
+            self.sparse_indices.resize(index as usize + 1, !0);
+        }
+        self.data.resize(self.data.len() + self.layout.size, 0);
+        self.dense_indices.push(index);
+        self.dense_ticks.push(ComponentTicks::new(self.changes.tick));
+        let dense_index = self.dense_indices.len() - 1;
+        self.sparse_indices[index as usize] = dense_index as EntityIndex;
+        self.row_mut(dense_index)
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity(&self) -> impl Iterator<Item = (EntityIndex, &DynamicRow)> {
+        (0..self.dense_indices.len()).map(move |dense_index| (self.dense_indices[dense_index], self.row(dense_index)))
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_mut(&mut self) -> impl Iterator<Item = (EntityIndex, &mut DynamicRow)> {
+        let n = self.dense_indices.len();
+        self.iter_range_mut(0, n)
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_batch(&self, batch_size: usize) -> impl Iterator<Item = impl Iterator<Item = (EntityIndex, &DynamicRow)>> {
+        let n = self.dense_indices.len();
+        (0..n).step_by(batch_size).map(move |i| {
+            (i..(i + batch_size).min(n)).map(move |dense_index| (self.dense_indices[dense_index], self.row(dense_index)))
+        })
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_mut_batch(&mut self, batch_size: usize) -> impl Iterator<Item = impl Iterator<Item = (EntityIndex, &mut DynamicRow)>> {
+        let n = self.dense_indices.len();
+        (0..n).step_by(batch_size).map(move |i| {
+            let forgotten_self = unsafe{std::mem::transmute::<&mut Self, &mut Self>(self)};
+            forgotten_self.iter_range_mut(i, (i + batch_size).min(n))
+        })
+    }
+
+    /**
+     * Rows of a range of dense slots, position_count is the number of slots.
+     */
+    #[allow(unused)]
+    pub fn iter_entity_range(&self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &DynamicRow)> {
+        range.map(move |dense_index| (self.dense_indices[dense_index], self.row(dense_index)))
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_range_mut(&mut self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &mut DynamicRow)> {
+        self.iter_range_mut(range.start, range.end)
+    }
+
+    /**
+     * Rows of the dense range marked as changed, the ranges of the batches never overlap.
+     */
+    fn iter_range_mut(&mut self, start: usize, end: usize) -> impl Iterator<Item = (EntityIndex, &mut DynamicRow)> {
+        let tick = self.changes.tick;
+        let stride = self.layout.size;
+        let data = self.data.as_mut_ptr();
+        self.dense_indices[start..end].iter().map(|i| *i)
+            .zip(self.dense_ticks[start..end].iter_mut())
+            .enumerate()
+            .map(move |(offset, (index, ticks))| {
+                ticks.changed = tick;
+                let bytes = unsafe{ std::slice::from_raw_parts_mut(data.add((start + offset) * stride), stride) };
+                (index, DynamicRow::from_bytes_mut(bytes))
+            })
+    }
+}
@@ -16,10 +16,16 @@ const PAGE_EXPONENT: usize = 7;
 const PAGE_SIZE: usize = 1 << PAGE_EXPONENT;
 const PAGE_MASK: usize = get_page_mask(PAGE_EXPONENT);
 
+/**
+ * Number of entity slots per page, batches of a LinearStore are made of whole pages.
+ */
+pub const LINEAR_STORE_PAGE_SIZE: usize = PAGE_SIZE;
+
 #[derive(Clone)]
 struct Page<T: Default + Clone, const N: usize> {
     slots: [T; N],
     slot_used: [bool; N],
+    ticks: [ComponentTicks; N],
     len: usize,
 }
 
@@ -28,6 +34,7 @@ impl<T: Default + Clone, const N: usize> Page<T,N> {
         Self{
             slots: [(); N].map(|_| T::default()),
             slot_used: [false; N],
+            ticks: [ComponentTicks::default(); N],
             len: 0,
         }
     }
@@ -41,11 +48,14 @@ impl<T: Default + Clone, const N: usize> Page<T,N> {
     }
 
     #[allow(unused)]
-    fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+    fn iter_mut(&mut self, tick: Tick) -> impl Iterator<Item = &mut T> {
         self.slots.iter_mut()
-            .zip(self.slot_used.iter())
-            .filter(|(_, used)| **used)
-            .map(|(slot, _)| slot)
+            .zip(self.slot_used.iter().zip(self.ticks.iter_mut()))
+            .filter(|(_, (used, _))| **used)
+            .map(move |(slot, (_, ticks))| {
+                ticks.changed = tick;
+                slot
+            })
     }
 
     #[allow(unused)]
@@ -58,20 +68,44 @@ impl<T: Default + Clone, const N: usize> Page<T,N> {
     }
 
     #[allow(unused)]
-    fn iter_entity_mut(&mut self, page_index: usize) -> impl Iterator<Item = (EntityIndex, &mut T)> {
+    fn iter_entity_mut(&mut self, page_index: usize, tick: Tick) -> impl Iterator<Item = (EntityIndex, &mut T)> {
         self.slots.iter_mut()
-            .zip(self.slot_used.iter())
+            .zip(self.slot_used.iter().zip(self.ticks.iter_mut()))
             .enumerate()
-            .filter(|(_, (_, used))| **used)
-            .map(move |(index, (slot, _))| ((index + (page_index << PAGE_EXPONENT)) as EntityIndex,  slot))
+            .filter(|(_, (_, (used, _)))| **used)
+            .map(move |(index, (slot, (_, ticks)))| {
+                ticks.changed = tick;
+                ((index + (page_index << PAGE_EXPONENT)) as EntityIndex,  slot)
+            })
     }
 }
 
+/**
+ * Write access to a page, the page is copied first if it is shared with a saved state.
+ * A page with a single owner is written in place without touching the reference count,
+ * so batches of a store whose pages were unshared can write different slots of one page from several threads.
+ */
+fn page_mut<T: Default + Clone, const N: usize>(page: &mut Arc<Page<T, N>>) -> &mut Page<T, N> {
+    if Arc::strong_count(page) != 1 {
+        Arc::make_mut(page);
+    }
+    // the store never creates weak references, a strong count of 1 means the page is owned by this store alone
+    unsafe{ &mut *(Arc::as_ptr(page) as *mut Page<T, N>) }
+}
+
+/**
+ * Pages are shared copy on write, saving the state of the store for rollback only clones the page pointers,
+ * a page is copied the first time it is written to afterwards.
+ * Pages without components point to one shared empty page, optimize returns emptied pages to it.
+ */
 pub struct LinearStore<T: Default + Clone> {
-    pages: Vec<Page<T, PAGE_SIZE>>,
+    pages: Vec<Arc<Page<T, PAGE_SIZE>>>,
+    empty_page: Arc<Page<T, PAGE_SIZE>>,
+    changes: ChangeTracker,
+    lifecycle: LifecycleLog<T>,
 }
 
-impl<T: 'static + Default + Clone> GenericComponentStore for LinearStore<T> {
+impl<T: 'static + Default + Clone + Send + Sync> GenericComponentStore for LinearStore<T> {
 
     fn as_any(&self) -> &dyn Any {
         self
@@ -81,7 +115,16 @@ impl<T: 'static + Default + Clone> GenericComponentStore for LinearStore<T> {
         self
     }
 
-    fn optimize(&mut self) {}
+    fn optimize(&mut self) {
+        for page in self.pages.iter_mut() {
+            if page.len == 0 && !Arc::ptr_eq(page, &self.empty_page) {
+                *page = self.empty_page.clone();
+            }
+        }
+        let used = self.pages.iter().rposition(|page| page.len > 0).map_or(0, |page_index| page_index + 1);
+        self.pages.truncate(used);
+        self.pages.shrink_to_fit();
+    }
 
     fn has(&self, index: EntityIndex) -> bool {
         let page_index = get_page_index(index, PAGE_EXPONENT);
@@ -93,14 +136,105 @@ impl<T: 'static + Default + Clone> GenericComponentStore for LinearStore<T> {
         let page_index = get_page_index(index, PAGE_EXPONENT);
         let page_offset = get_page_offset(index, PAGE_MASK);
         assert!(self.has_split(page_index, page_offset), "tried to remove non existing component of an entity");
-        let page = &mut self.pages[page_index];
-        page.slots[page_offset] = T::default();
+        let page = page_mut(&mut self.pages[page_index]);
+        let old = std::mem::take(&mut page.slots[page_offset]);
         page.slot_used[page_offset] = false;
         page.len -= 1;
+        self.changes.log_removal(index);
+        self.lifecycle.removed(index, old);
     }
 
     fn len(&self) -> usize {
-        0
+        self.pages.iter().map(|page| page.len).sum()
+    }
+
+    fn iter_indices(&self) -> Box<dyn Iterator<Item = EntityIndex> + '_> {
+        Box::new(self.iter_entity().map(|(index, _)| index))
+    }
+
+    /**
+     * Pages shared with saved rollback frames are counted in full.
+     */
+    fn memory(&self) -> StoreMemory {
+        let allocated_pages = self.pages.iter().filter(|page| !Arc::ptr_eq(page, &self.empty_page)).count();
+        StoreMemory{
+            len: self.len(),
+            capacity: allocated_pages * PAGE_SIZE,
+            bytes: self.pages.capacity() * std::mem::size_of::<Arc<Page<T, PAGE_SIZE>>>()
+                + (allocated_pages + 1) * std::mem::size_of::<Page<T, PAGE_SIZE>>(),
+        }
+    }
+
+    fn change_tick(&self) -> Tick {
+        self.changes.tick
+    }
+
+    fn set_change_tick(&mut self, tick: Tick) {
+        self.changes.set_tick(tick);
+    }
+
+    fn component_ticks(&self, index: EntityIndex) -> Option<ComponentTicks> {
+        let page_index = get_page_index(index, PAGE_EXPONENT);
+        let page_offset = get_page_offset(index, PAGE_MASK);
+        if self.has_split(page_index, page_offset) {
+            Some(self.pages[page_index].ticks[page_offset])
+        } else {
+            None
+        }
+    }
+
+    fn removed(&self) -> &[(EntityIndex, Tick)] {
+        self.changes.removed()
+    }
+
+    fn log_changes(&mut self) -> bool {
+        self.changes.log_changes();
+        true
+    }
+
+    fn change_cursor(&self) -> ChangeCursor {
+        self.changes.cursor()
+    }
+
+    fn changes_since(&self, cursor: ChangeCursor) -> Option<&[EntityIndex]> {
+        self.changes.changes_since(cursor)
+    }
+
+    fn pause_change_log(&mut self, paused: bool) {
+        self.changes.pause(paused);
+    }
+
+    fn record_lifecycle(&mut self) -> bool {
+        self.lifecycle.record();
+        true
+    }
+
+    fn take_lifecycle(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new(self.lifecycle.take()))
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any + Send + Sync>> {
+        Some(Box::new(self.pages.clone()))
+    }
+
+    fn restore_state(&mut self, state: &(dyn Any + Send + Sync)) {
+        self.pages = state.downcast_ref::<Vec<Arc<Page<T, PAGE_SIZE>>>>().unwrap().clone();
+        self.lifecycle.take();
+        self.changes.log_rewrite();
+    }
+
+    fn unshare_pages(&mut self) {
+        for page in self.pages.iter_mut().filter(|page| page.len > 0) {
+            page_mut(page);
+        }
+    }
+
+    fn page_aligned_batches(&self) -> bool {
+        true
+    }
+
+    fn position_count(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
     }
 }
 
@@ -112,7 +246,9 @@ impl<T: Default + Clone> LinearStore<T> {
     
     #[allow(unused)]
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
-        self.pages.iter_mut().filter(| page| page.len > 0).flat_map(|page| page.iter_mut())
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
+        self.pages.iter_mut().filter(| page| page.len > 0).flat_map(move |page| page_mut(page).iter_mut(tick))
     }
 
     #[allow(unused)]
@@ -127,38 +263,89 @@ impl<T: Default + Clone> LinearStore<T> {
     
     #[allow(unused)]
     pub fn iter_entity_mut(&mut self) -> impl Iterator<Item = (EntityIndex, &mut T)> {
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
         self.pages
             .iter_mut()
             .enumerate()
             .filter(|(_,  page)| page.len > 0)
             .map(|(entity,  page)| (entity, page))
-            .flat_map(|(page_index, page)| page.iter_entity_mut(page_index))
+            .flat_map(move |(page_index, page)| page_mut(page).iter_entity_mut(page_index, tick))
     }
 
+    /**
+     * Batches consist of whole pages, batch_size is rounded up to a multiple of the page size.
+     */
     #[allow(unused)]
     pub fn iter_entity_batch(&self, batch_size: usize) -> impl Iterator<Item = impl Iterator<Item = (EntityIndex, &T)>> {
-        let n = self.pages.len();
-
-        (0..n).into_iter()
-            .map(|page_index|{
-                self.pages[page_index].iter_entity(page_index).take(PAGE_SIZE)
+        self.page_batches(batch_size).into_iter()
+            .map(move |pages| {
+                pages.into_iter().flat_map(move |page_index| self.pages[page_index].iter_entity(page_index))
             })
     }
 
+    /**
+     * Like iter_entity_batch, no two batches share a page, so they can be written in parallel.
+     */
     #[allow(unused)]
     pub fn iter_entity_mut_batch(&mut self, batch_size: usize) -> impl Iterator<Item = impl Iterator<Item = (EntityIndex, &mut T)>> {
-        let n = self.pages.len();
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
+
+        self.page_batches(batch_size).into_iter()
+            .map(move |pages| {
+                let forgotten_self = unsafe{std::mem::transmute::<&mut Self, &mut Self>(self)};
+                pages.into_iter().flat_map(move |page_index| {
+                    let forgotten_self = unsafe{std::mem::transmute::<&mut Self, &mut Self>(forgotten_self)};
+                    page_mut(&mut forgotten_self.pages[page_index]).iter_entity_mut(page_index, tick)
+                })
+            })
+    }
+
+    /**
+     * Components of a range of entity indices, position_count covers every page.
+     */
+    #[allow(unused)]
+    pub fn iter_entity_range(&self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &T)> {
+        let pages = range.start / PAGE_SIZE..range.end.div_ceil(PAGE_SIZE).min(self.pages.len());
+        pages
+            .filter(|page_index| self.pages[*page_index].len > 0)
+            .flat_map(move |page_index| self.pages[page_index].iter_entity(page_index))
+            .filter(move |(index, _)| range.contains(&(*index as usize)))
+    }
 
-        (0..n).into_iter()
-            .map(|page_index|{
+    /**
+     * Like iter_entity_range, counts as a rewrite unless the change log is paused for parallel batches.
+     * Ranges of several threads may share a page once the pages were unshared.
+     */
+    #[allow(unused)]
+    pub fn iter_entity_range_mut(&mut self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &mut T)> {
+        self.changes.log_unpaused_rewrite();
+        let tick = self.changes.tick;
+        let pages = range.start / PAGE_SIZE..range.end.div_ceil(PAGE_SIZE).min(self.pages.len());
+        pages
+            .flat_map(move |page_index| {
                 let forgotten_self = unsafe{std::mem::transmute::<&mut Self, &mut Self>(self)};
-                forgotten_self.pages[page_index].iter_entity_mut(page_index).take(PAGE_SIZE)
+                let page = &mut forgotten_self.pages[page_index];
+                (page.len > 0).then(|| page_mut(page).iter_entity_mut(page_index, tick)).into_iter().flatten()
             })
+            .filter(move |(index, _)| range.contains(&(*index as usize)))
+    }
+
+    /**
+     * The pages holding components, grouped into batches of at least batch_size slots.
+     * Empty pages are skipped, so the shared empty page is never copied.
+     */
+    fn page_batches(&self, batch_size: usize) -> Vec<Vec<usize>> {
+        let pages_per_batch = batch_size.div_ceil(PAGE_SIZE).max(1);
+        let used_pages = (0..self.pages.len()).filter(|page_index| self.pages[*page_index].len > 0).collect::<Vec<_>>();
+        used_pages.chunks(pages_per_batch).map(|pages| pages.to_vec()).collect()
     }
 
     fn assure_page(&mut self, page_index: usize) {
         if self.pages.len() <= page_index {
-            self.pages.resize(page_index + 1, Page::new());
+            let empty_page = &self.empty_page;
+            self.pages.resize_with(page_index + 1, || empty_page.clone());
         }
     }
 
@@ -173,6 +360,9 @@ impl<T: Default + Clone + Component> ComponentStore<T> for LinearStore<T> {
     fn new() -> Self {
         Self{
             pages: Vec::new(),
+            empty_page: Arc::new(Page::new()),
+            changes: ChangeTracker::default(),
+            lifecycle: LifecycleLog::default(),
         }
     }
 
@@ -190,7 +380,10 @@ impl<T: Default + Clone + Component> ComponentStore<T> for LinearStore<T> {
         let page_index = get_page_index(index, PAGE_EXPONENT);
         let page_offset = get_page_offset(index, PAGE_MASK);
         if self.has_split(page_index,page_offset) {
-            Some(&mut self.pages[page_index].slots[page_offset])
+            let page = page_mut(&mut self.pages[page_index]);
+            page.ticks[page_offset].changed = self.changes.tick;
+            self.changes.log_access(index);
+            Some(&mut page.slots[page_offset])
         }else {
             None
         }
@@ -200,7 +393,11 @@ impl<T: Default + Clone + Component> ComponentStore<T> for LinearStore<T> {
         let page_index = get_page_index(index, PAGE_EXPONENT);
         let page_offset = get_page_offset(index, PAGE_MASK);
         assert!(self.has_split(page_index,page_offset));
-        self.pages[page_index].slots[page_offset] = value;
+        let page = page_mut(&mut self.pages[page_index]);
+        let old = std::mem::replace(&mut page.slots[page_offset], value);
+        page.ticks[page_offset].changed = self.changes.tick;
+        self.changes.log_change(index);
+        self.lifecycle.replaced(index, old, &page.slots[page_offset]);
     }
 
     fn add(&mut self, index: EntityIndex, value: T) {
@@ -208,9 +405,26 @@ impl<T: Default + Clone + Component> ComponentStore<T> for LinearStore<T> {
         let page_offset = get_page_offset(index, PAGE_MASK);
         assert!(!self.has_split(page_index, page_offset), "tried to add a component to an entity that allready has the given component");
         self.assure_page(page_index);
-        let page = &mut self.pages[page_index];
+        let page = page_mut(&mut self.pages[page_index]);
         page.slots[page_offset] = value;
         page.slot_used[page_offset] = true;
+        page.ticks[page_offset] = ComponentTicks::new(self.changes.tick);
         page.len += 1;
+        self.changes.log_change(index);
+        self.lifecycle.added(index, &page.slots[page_offset]);
     }
-}
\ No newline at end of file
+}
+
+impl<T: 'static + Default + Clone + Send + Sync> BatchedStore<T> for LinearStore<T> {
+    fn batches(&self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &T)> + Send + '_>> {
+        self.iter_entity_batch(batch_size)
+            .map(|batch| Box::new(batch) as Box<dyn Iterator<Item = (EntityIndex, &T)> + Send + '_>)
+            .collect()
+    }
+
+    fn batches_mut(&mut self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &mut T)> + Send + '_>> {
+        self.iter_entity_mut_batch(batch_size)
+            .map(|batch| Box::new(batch) as Box<dyn Iterator<Item = (EntityIndex, &mut T)> + Send + '_>)
+            .collect()
+    }
+}
@@ -0,0 +1,706 @@
+use super::*;
+
+use std::sync::Mutex;
+
+pub type ArchetypeId = u32;
+
+const NO_ARCHETYPE: ArchetypeId = !0;
+
+#[derive(Clone)]
+struct Archetype {
+    components: Vec<TypeId>,
+    entities: Vec<EntityIndex>,
+}
+
+#[derive(Clone, Copy)]
+struct TableMove {
+    entity: EntityIndex,
+    from: (ArchetypeId, u32),
+    to: ArchetypeId,
+}
+
+/**
+ * Shared bookkeeping of all TableStores of one EntityComponentManager.
+ * Every entity that owns table components belongs to exactly one archetype, the set of its table component types.
+ * Adding or removing a table component moves the entity to another archetype.
+ * The moves are logged, every TableStore replays the log to keep its columns in the same row order as the archetypes.
+ */
+pub struct ArchetypeRegistry {
+    archetypes: Vec<Archetype>,
+    lookup: rustc_hash::FxHashMap<Vec<TypeId>, ArchetypeId>,
+    locations: Vec<(ArchetypeId, u32)>,
+    moves: Vec<TableMove>,
+    moves_offset: usize,
+    cursors: Vec<Option<usize>>,
+}
+
+impl ArchetypeRegistry {
+    pub fn new() -> Self {
+        Self{
+            archetypes: Vec::new(),
+            lookup: rustc_hash::FxHashMap::default(),
+            locations: Vec::new(),
+            moves: Vec::new(),
+            moves_offset: 0,
+            cursors: Vec::new(),
+        }
+    }
+
+    #[allow(unused)]
+    pub fn archetype_count(&self) -> usize {
+        self.archetypes.len()
+    }
+
+    #[allow(unused)]
+    pub fn archetype_components(&self, archetype: ArchetypeId) -> &[TypeId] {
+        &self.archetypes[archetype as usize].components
+    }
+
+    #[allow(unused)]
+    pub fn archetype_entities(&self, archetype: ArchetypeId) -> &[EntityIndex] {
+        &self.archetypes[archetype as usize].entities
+    }
+
+    #[allow(unused)]
+    pub fn archetype_of(&self, index: EntityIndex) -> Option<ArchetypeId> {
+        match self.locations.get(index as usize) {
+            Some((archetype, _)) if *archetype != NO_ARCHETYPE => Some(*archetype),
+            _ => None,
+        }
+    }
+
+    fn archetype_has(&self, archetype: ArchetypeId, component: TypeId) -> bool {
+        archetype != NO_ARCHETYPE && self.archetypes[archetype as usize].components.binary_search(&component).is_ok()
+    }
+
+    fn moves_end(&self) -> usize {
+        self.moves_offset + self.moves.len()
+    }
+
+    fn register_store(&mut self) -> (usize, usize) {
+        let cursor = self.moves_end();
+        let slot = match self.cursors.iter().position(|c| c.is_none()) {
+            Some(slot) => slot,
+            None => {
+                self.cursors.push(None);
+                self.cursors.len() - 1
+            }
+        };
+        self.cursors[slot] = Some(cursor);
+        (slot, cursor)
+    }
+
+    fn unregister_store(&mut self, slot: usize) {
+        self.cursors[slot] = None;
+        self.trim_moves();
+    }
+
+    fn advance_store(&mut self, slot: usize, cursor: usize) {
+        self.cursors[slot] = Some(cursor);
+        self.trim_moves();
+    }
+
+    fn trim_moves(&mut self) {
+        let min = self.cursors.iter().filter_map(|c| *c).min().unwrap_or(self.moves_end());
+        if min > self.moves_offset {
+            self.moves.drain(..min - self.moves_offset);
+            self.moves_offset = min;
+        }
+    }
+
+    fn find_or_create_archetype(&mut self, components: Vec<TypeId>) -> ArchetypeId {
+        if let Some(archetype) = self.lookup.get(&components) {
+            return *archetype;
+        }
+        let archetype = self.archetypes.len() as ArchetypeId;
+        self.lookup.insert(components.clone(), archetype);
+        self.archetypes.push(Archetype{ components, entities: Vec::new() });
+        archetype
+    }
+
+    fn move_entity(&mut self, entity: EntityIndex, component: TypeId, insert: bool) -> TableMove {
+        if self.locations.len() <= entity as usize {
+            self.locations.resize(entity as usize + 1, (NO_ARCHETYPE, 0));
+        }
+        let from = self.locations[entity as usize];
+
+        let mut components = if from.0 != NO_ARCHETYPE { self.archetypes[from.0 as usize].components.clone() } else { Vec::new() };
+        match (components.binary_search(&component), insert) {
+            (Err(pos), true) => components.insert(pos, component),
+            (Ok(pos), false) => { components.remove(pos); },
+            _ => panic!("archetype of entity {} is out of sync with its table stores", entity),
+        }
+        let to = if components.is_empty() { NO_ARCHETYPE } else { self.find_or_create_archetype(components) };
+
+        if from.0 != NO_ARCHETYPE {
+            let entities = &mut self.archetypes[from.0 as usize].entities;
+            entities.swap_remove(from.1 as usize);
+            if (from.1 as usize) < entities.len() {
+                let moved = entities[from.1 as usize];
+                self.locations[moved as usize].1 = from.1;
+            }
+        }
+        self.locations[entity as usize] = if to != NO_ARCHETYPE {
+            let entities = &mut self.archetypes[to as usize].entities;
+            entities.push(entity);
+            (to, entities.len() as u32 - 1)
+        } else {
+            (NO_ARCHETYPE, 0)
+        };
+
+        let table_move = TableMove{ entity, from, to };
+        self.moves.push(table_move);
+        table_move
+    }
+}
+
+type ArchetypeState = (Vec<Archetype>, rustc_hash::FxHashMap<Vec<TypeId>, ArchetypeId>, Vec<(ArchetypeId, u32)>);
+
+impl ArchetypeRegistry {
+    /**
+     * Copy of the archetypes and the locations of all entities for rollback, the moves log is not included.
+     * Only consistent with the rows of the table stores if all of them replayed all moves.
+     */
+    pub fn save_state(&self) -> Box<dyn Any + Send + Sync> {
+        Box::new((self.archetypes.clone(), self.lookup.clone(), self.locations.clone()))
+    }
+
+    /**
+     * Replaces the archetypes and locations with a state returned by save_state.
+     * The moves log is dropped, so all table stores have to replay it first and restore their rows afterwards.
+     */
+    pub fn restore_state(&mut self, state: &(dyn Any + Send + Sync)) {
+        let (archetypes, lookup, locations) = state.downcast_ref::<ArchetypeState>().unwrap();
+        assert!(self.cursors.iter().flatten().all(|cursor| *cursor == self.moves_end()), "table stores have to replay all moves before the archetype registry is restored");
+        self.archetypes.clone_from(archetypes);
+        self.lookup.clone_from(lookup);
+        self.locations.clone_from(locations);
+        self.moves_offset = self.moves_end();
+        self.moves.clear();
+    }
+}
+
+impl Default for ArchetypeRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+struct Column<T> {
+    entities: Vec<EntityIndex>,
+    values: Vec<T>,
+    ticks: Vec<ComponentTicks>,
+}
+
+impl<T> Column<T> {
+    fn new() -> Self {
+        Self{
+            entities: Vec::new(),
+            values: Vec::new(),
+            ticks: Vec::new(),
+        }
+    }
+
+    fn mark_changed(&mut self, tick: Tick) {
+        for ticks in &mut self.ticks {
+            ticks.changed = tick;
+        }
+    }
+}
+
+/**
+ * The rows of a column starting at row start that fall into range.
+ */
+fn column_rows(range: &std::ops::Range<usize>, start: usize, len: usize) -> std::ops::Range<usize> {
+    let first = range.start.clamp(start, start + len) - start;
+    let end = range.end.clamp(start, start + len) - start;
+    first..end
+}
+
+/**
+ * Archetype based storage.
+ * The values of a TableStore are grouped in one column per archetype.
+ * All TableStores created by the same EntityComponentManager share an ArchetypeRegistry,
+ * so the columns of the same archetype have the same entity order in every store
+ * and joins over table components can walk the columns in lock-step (see iterate_over_entities!).
+ * Moves caused by other stores are replayed lazily on add, rem, sync and EntityComponentManager::cleanup.
+ * A store that has not replayed all moves yet is still fully usable, only lock-step joins fall back to lookups.
+ * Rollback frames save the rows of every TableStore together with the archetype registry,
+ * a saved state can only be restored after the registry was restored to the same frame (see EntityComponentManager::restore_frame).
+ * Restoring the state of a single TableStore outside of a frame leaves its rows out of sync with the other stores,
+ * there is no way to rewind the shared registry for one store only.
+ */
+pub struct TableStore<T: 'static + Default + Clone> {
+    registry: Arc<Mutex<ArchetypeRegistry>>,
+    slot: usize,
+    cursor: usize,
+    columns: Vec<Column<T>>,
+    rows: Vec<(ArchetypeId, u32)>,
+    changes: ChangeTracker,
+    lifecycle: LifecycleLog<T>,
+}
+
+impl<T: 'static + Default + Clone> TableStore<T> {
+    pub fn with_registry(registry: Arc<Mutex<ArchetypeRegistry>>) -> Self {
+        let (slot, cursor) = registry.lock().unwrap().register_store();
+        Self{
+            registry,
+            slot,
+            cursor,
+            columns: Vec::new(),
+            rows: Vec::new(),
+            changes: ChangeTracker::default(),
+            lifecycle: LifecycleLog::default(),
+        }
+    }
+
+    /**
+     * Replays all archetype moves other stores made since the last sync.
+     */
+    #[allow(unused)]
+    pub fn sync(&mut self) {
+        let registry = self.registry.clone();
+        let mut registry = registry.lock().unwrap();
+        self.catch_up(&mut registry);
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.columns.iter().flat_map(|column| column.values.iter())
+    }
+
+    #[allow(unused)]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
+        self.columns.iter_mut().flat_map(move |column| {
+            column.mark_changed(tick);
+            column.values.iter_mut()
+        })
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity(&self) -> impl Iterator<Item = (EntityIndex, &T)> {
+        self.columns.iter().flat_map(|column| column.entities.iter().map(|i| *i).zip(column.values.iter()))
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_mut(&mut self) -> impl Iterator<Item = (EntityIndex, &mut T)> {
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
+        self.columns.iter_mut().flat_map(move |column| {
+            column.mark_changed(tick);
+            column.entities.iter().map(|i| *i).zip(column.values.iter_mut())
+        })
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_batch(&self, batch_size: usize) -> impl Iterator<Item = impl Iterator<Item = (EntityIndex, &T)>> {
+        self.columns.iter()
+            .flat_map(move |column| column.entities.chunks(batch_size).zip(column.values.chunks(batch_size)))
+            .map(|(entities, values)| entities.iter().map(|i| *i).zip(values.iter()))
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_mut_batch(&mut self, batch_size: usize) -> impl Iterator<Item = impl Iterator<Item = (EntityIndex, &mut T)>> {
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
+        self.columns.iter_mut()
+            .flat_map(move |column| {
+                column.mark_changed(tick);
+                let Column{entities, values, ..} = column;
+                entities.chunks(batch_size).zip(values.chunks_mut(batch_size))
+            })
+            .map(|(entities, values)| entities.iter().map(|i| *i).zip(values.iter_mut()))
+    }
+
+    /**
+     * Components of a range of rows, the rows of all columns are counted one after another.
+     */
+    #[allow(unused)]
+    pub fn iter_entity_range(&self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &T)> {
+        self.columns.iter()
+            .scan(0, |offset, column| {
+                let start = *offset;
+                *offset += column.values.len();
+                Some((start, column))
+            })
+            .take_while(move |(start, _)| *start < range.end)
+            .flat_map(move |(start, column)| {
+                let rows = column_rows(&range, start, column.values.len());
+                column.entities[rows.clone()].iter().copied().zip(column.values[rows].iter())
+            })
+    }
+
+    /**
+     * Like iter_entity_range, counts as a rewrite unless the change log is paused for parallel batches.
+     */
+    #[allow(unused)]
+    pub fn iter_entity_range_mut(&mut self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &mut T)> {
+        self.changes.log_unpaused_rewrite();
+        let tick = self.changes.tick;
+        self.columns.iter_mut()
+            .scan(0, |offset, column| {
+                let start = *offset;
+                *offset += column.values.len();
+                Some((start, column))
+            })
+            .take_while(move |(start, _)| *start < range.end)
+            .flat_map(move |(start, column)| {
+                let rows = column_rows(&range, start, column.values.len());
+                let Column{entities, values, ticks} = column;
+                entities[rows.clone()].iter().copied().zip(values[rows.clone()].iter_mut().zip(ticks[rows].iter_mut()))
+                    .map(move |(index, (value, ticks))| {
+                        ticks.changed = tick;
+                        (index, value)
+                    })
+            })
+    }
+
+    /**
+     * Returns the entities and values of the given archetypes columns.
+     * archetypes must be sorted ascending.
+     */
+    #[allow(unused)]
+    pub fn columns_for(&self, archetypes: &[ArchetypeId]) -> Vec<(&[EntityIndex], &[T])> {
+        self.columns.iter()
+            .enumerate()
+            .filter(|(archetype, _)| archetypes.binary_search(&(*archetype as ArchetypeId)).is_ok())
+            .map(|(_, column)| (&column.entities[..], &column.values[..]))
+            .collect()
+    }
+
+    /**
+     * Returns the entities and mutable values of the given archetypes columns.
+     * archetypes must be sorted ascending.
+     * All returned values count as changed.
+     */
+    #[allow(unused)]
+    pub fn columns_for_mut(&mut self, archetypes: &[ArchetypeId]) -> Vec<(&[EntityIndex], &mut [T])> {
+        self.changes.log_rewrite();
+        let tick = self.changes.tick;
+        self.columns.iter_mut()
+            .enumerate()
+            .filter(|(archetype, _)| archetypes.binary_search(&(*archetype as ArchetypeId)).is_ok())
+            .map(|(_, column)| {
+                column.mark_changed(tick);
+                let Column{entities, values, ..} = column;
+                (&entities[..], &mut values[..])
+            })
+            .collect()
+    }
+
+    fn catch_up(&mut self, registry: &mut ArchetypeRegistry) {
+        let end = registry.moves_end();
+        if self.cursor == end {
+            return;
+        }
+        let component = TypeId::of::<T>();
+        for i in self.cursor..end {
+            let table_move = registry.moves[i - registry.moves_offset];
+            if registry.archetype_has(table_move.from.0, component) {
+                let (value, ticks) = self.take_row(table_move.from.0, table_move.from.1);
+                if registry.archetype_has(table_move.to, component) {
+                    self.push_row(table_move.to, table_move.entity, value, ticks);
+                }
+            }
+        }
+        self.cursor = end;
+        registry.advance_store(self.slot, end);
+    }
+
+    fn take_row(&mut self, archetype: ArchetypeId, row: u32) -> (T, ComponentTicks) {
+        let column = &mut self.columns[archetype as usize];
+        let entity = column.entities.swap_remove(row as usize);
+        let value = column.values.swap_remove(row as usize);
+        let ticks = column.ticks.swap_remove(row as usize);
+        if (row as usize) < column.entities.len() {
+            let moved = column.entities[row as usize];
+            self.rows[moved as usize].1 = row;
+        }
+        self.rows[entity as usize] = (NO_ARCHETYPE, 0);
+        (value, ticks)
+    }
+
+    fn push_row(&mut self, archetype: ArchetypeId, entity: EntityIndex, value: T, ticks: ComponentTicks) {
+        if self.columns.len() <= archetype as usize {
+            self.columns.resize_with(archetype as usize + 1, Column::new);
+        }
+        if self.rows.len() <= entity as usize {
+            self.rows.resize(entity as usize + 1, (NO_ARCHETYPE, 0));
+        }
+        let column = &mut self.columns[archetype as usize];
+        column.entities.push(entity);
+        column.values.push(value);
+        column.ticks.push(ticks);
+        self.rows[entity as usize] = (archetype, column.entities.len() as u32 - 1);
+    }
+
+    fn row_of(&self, index: EntityIndex) -> Option<(ArchetypeId, u32)> {
+        match self.rows.get(index as usize) {
+            Some(row) if row.0 != NO_ARCHETYPE => Some(*row),
+            _ => None,
+        }
+    }
+}
+
+impl<T: 'static + Default + Clone> Drop for TableStore<T> {
+    fn drop(&mut self) {
+        if let Ok(mut registry) = self.registry.lock() {
+            registry.unregister_store(self.slot);
+        }
+    }
+}
+
+impl<T: 'static + Default + Clone + Send + Sync> GenericComponentStore for TableStore<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn optimize(&mut self) {
+        self.sync();
+        for column in self.columns.iter_mut() {
+            column.entities.shrink_to_fit();
+            column.values.shrink_to_fit();
+            column.ticks.shrink_to_fit();
+        }
+    }
+
+    fn maintain(&mut self) {
+        self.sync();
+    }
+
+    fn table_layout(&self) -> Option<&dyn TableLayout> {
+        Some(self)
+    }
+
+    fn sync_layout(&mut self) {
+        self.sync();
+    }
+
+    fn has(&self, index: EntityIndex) -> bool {
+        self.row_of(index).is_some()
+    }
+
+    fn rem(&mut self, index: EntityIndex) {
+        assert!(self.has(index), "tried to remove non existing component of an entity");
+        let registry = self.registry.clone();
+        let mut registry = registry.lock().unwrap();
+        self.catch_up(&mut registry);
+        let table_move = registry.move_entity(index, TypeId::of::<T>(), false);
+        let (old, _) = self.take_row(table_move.from.0, table_move.from.1);
+        self.cursor = registry.moves_end();
+        registry.advance_store(self.slot, self.cursor);
+        self.changes.log_removal(index);
+        self.lifecycle.removed(index, old);
+    }
+
+    fn len(&self) -> usize {
+        self.columns.iter().map(|column| column.values.len()).sum()
+    }
+
+    fn iter_indices(&self) -> Box<dyn Iterator<Item = EntityIndex> + '_> {
+        Box::new(self.columns.iter().flat_map(|column| column.entities.iter().map(|i| *i)))
+    }
+
+    /**
+     * The archetype registry shared by all table stores is not included.
+     */
+    fn memory(&self) -> StoreMemory {
+        StoreMemory{
+            len: self.len(),
+            capacity: self.columns.iter().map(|column| column.values.capacity()).sum(),
+            bytes: self.rows.capacity() * std::mem::size_of::<(ArchetypeId, u32)>()
+                + self.columns.iter().map(|column| {
+                    column.entities.capacity() * std::mem::size_of::<EntityIndex>()
+                        + column.values.capacity() * std::mem::size_of::<T>()
+                        + column.ticks.capacity() * std::mem::size_of::<ComponentTicks>()
+                }).sum::<usize>(),
+        }
+    }
+
+    fn change_tick(&self) -> Tick {
+        self.changes.tick
+    }
+
+    fn set_change_tick(&mut self, tick: Tick) {
+        self.changes.set_tick(tick);
+    }
+
+    fn component_ticks(&self, index: EntityIndex) -> Option<ComponentTicks> {
+        let (archetype, row) = self.row_of(index)?;
+        Some(self.columns[archetype as usize].ticks[row as usize])
+    }
+
+    fn removed(&self) -> &[(EntityIndex, Tick)] {
+        self.changes.removed()
+    }
+
+    fn log_changes(&mut self) -> bool {
+        self.changes.log_changes();
+        true
+    }
+
+    fn change_cursor(&self) -> ChangeCursor {
+        self.changes.cursor()
+    }
+
+    fn changes_since(&self, cursor: ChangeCursor) -> Option<&[EntityIndex]> {
+        self.changes.changes_since(cursor)
+    }
+
+    fn pause_change_log(&mut self, paused: bool) {
+        self.changes.pause(paused);
+    }
+
+    fn record_lifecycle(&mut self) -> bool {
+        self.lifecycle.record();
+        true
+    }
+
+    fn take_lifecycle(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new(self.lifecycle.take()))
+    }
+
+    /**
+     * The rows only match the saved archetype registry if the store replayed all moves before.
+     */
+    fn save_state(&self) -> Option<Box<dyn Any + Send + Sync>> {
+        Some(Box::new((self.columns.clone(), self.rows.clone())))
+    }
+
+    fn restore_state(&mut self, state: &(dyn Any + Send + Sync)) {
+        let (columns, rows) = state.downcast_ref::<(Vec<Column<T>>, Vec<(ArchetypeId, u32)>)>().unwrap();
+        self.columns.clone_from(columns);
+        self.rows.clone_from(rows);
+        self.lifecycle.take();
+        self.changes.log_rewrite();
+        let mut registry = self.registry.lock().unwrap();
+        self.cursor = registry.moves_end();
+        registry.advance_store(self.slot, self.cursor);
+    }
+}
+
+impl<T: 'static + Default + Clone + Component> ComponentStore<T> for TableStore<T> {
+    type ComponentType = T;
+
+    fn new() -> Self {
+        Self::with_registry(Arc::new(Mutex::new(ArchetypeRegistry::new())))
+    }
+
+    fn new_in(context: &StoreContext) -> Self {
+        Self::with_registry(context.tables.clone())
+    }
+
+    fn get(&self, index: EntityIndex) -> Option<&T> {
+        let (archetype, row) = self.row_of(index)?;
+        Some(&self.columns[archetype as usize].values[row as usize])
+    }
+
+    fn get_mut(&mut self, index: EntityIndex) -> Option<&mut T> {
+        let (archetype, row) = self.row_of(index)?;
+        let column = &mut self.columns[archetype as usize];
+        column.ticks[row as usize].changed = self.changes.tick;
+        self.changes.log_access(index);
+        Some(&mut column.values[row as usize])
+    }
+
+    fn set(&mut self, index: EntityIndex, value: T) {
+        let (archetype, row) = self.row_of(index).expect("tried to set non existing component of an entity");
+        let column = &mut self.columns[archetype as usize];
+        let old = std::mem::replace(&mut column.values[row as usize], value);
+        column.ticks[row as usize].changed = self.changes.tick;
+        self.changes.log_change(index);
+        self.lifecycle.replaced(index, old, &column.values[row as usize]);
+    }
+
+    fn add(&mut self, index: EntityIndex, value: T) {
+        assert!(!self.has(index), "tried to add a component to an entity that allready has the given component");
+        let registry = self.registry.clone();
+        let mut registry = registry.lock().unwrap();
+        self.catch_up(&mut registry);
+        let table_move = registry.move_entity(index, TypeId::of::<T>(), true);
+        self.push_row(table_move.to, index, value, ComponentTicks::new(self.changes.tick));
+        self.changes.log_change(index);
+        self.cursor = registry.moves_end();
+        registry.advance_store(self.slot, self.cursor);
+        let (archetype, row) = self.rows[index as usize];
+        self.lifecycle.added(index, &self.columns[archetype as usize].values[row as usize]);
+    }
+}
+
+impl<T: 'static + Default + Clone + Send + Sync> BatchedStore<T> for TableStore<T> {
+    fn batches(&self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &T)> + Send + '_>> {
+        self.iter_entity_batch(batch_size)
+            .map(|batch| Box::new(batch) as Box<dyn Iterator<Item = (EntityIndex, &T)> + Send + '_>)
+            .collect()
+    }
+
+    fn batches_mut(&mut self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &mut T)> + Send + '_>> {
+        self.iter_entity_mut_batch(batch_size)
+            .map(|batch| Box::new(batch) as Box<dyn Iterator<Item = (EntityIndex, &mut T)> + Send + '_>)
+            .collect()
+    }
+}
+
+/**
+ * Archetype layout information of a table store, used to plan lock-step joins.
+ */
+pub trait TableLayout {
+    fn layout_registry(&self) -> *const Mutex<ArchetypeRegistry>;
+
+    fn layout_cursor(&self) -> usize;
+
+    fn occupied_archetypes(&self) -> Vec<ArchetypeId>;
+
+    /**
+     * Positions of the rows of the archetype's column, see TableStore::iter_entity_range.
+     */
+    fn column_rows(&self, archetype: ArchetypeId) -> std::ops::Range<usize>;
+}
+
+impl<T: 'static + Default + Clone> TableLayout for TableStore<T> {
+    fn layout_registry(&self) -> *const Mutex<ArchetypeRegistry> {
+        Arc::as_ptr(&self.registry)
+    }
+
+    fn layout_cursor(&self) -> usize {
+        self.cursor
+    }
+
+    fn occupied_archetypes(&self) -> Vec<ArchetypeId> {
+        self.columns.iter()
+            .enumerate()
+            .filter(|(_, column)| !column.entities.is_empty())
+            .map(|(archetype, _)| archetype as ArchetypeId)
+            .collect()
+    }
+
+    fn column_rows(&self, archetype: ArchetypeId) -> std::ops::Range<usize> {
+        let archetype = (archetype as usize).min(self.columns.len());
+        let start = self.columns[..archetype].iter().map(|column| column.values.len()).sum::<usize>();
+        start..start + self.columns.get(archetype).map_or(0, |column| column.values.len())
+    }
+}
+
+/**
+ * Returns the sorted archetypes that contain every given store, if the stores can be joined in lock-step.
+ * That is the case, when all stores share a registry and replayed the same archetype moves.
+ */
+pub fn join_archetypes(stores: &[&dyn TableLayout]) -> Option<Vec<ArchetypeId>> {
+    let first = stores.first()?;
+    if stores.iter().any(|store| store.layout_registry() != first.layout_registry() || store.layout_cursor() != first.layout_cursor()) {
+        return None;
+    }
+    let mut archetypes = first.occupied_archetypes();
+    for store in &stores[1..] {
+        let occupied = store.occupied_archetypes();
+        archetypes.retain(|archetype| occupied.binary_search(archetype).is_ok());
+    }
+    Some(archetypes)
+}
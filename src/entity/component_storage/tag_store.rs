@@ -0,0 +1,386 @@
+use super::*;
+
+const WORD_BITS: usize = 64;
+
+/**
+ * Set of entity indices, stored as three layers of bit words.
+ * A bit in a higher layer is set when the corresponding word in the layer below is not empty,
+ * so iteration and intersection skip empty regions 64 or 4096 words at a time.
+ */
+#[derive(Clone, Default)]
+pub struct BitSet {
+    layers: [Vec<u64>; 3],
+    len: usize,
+}
+
+impl BitSet {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(unused)]
+    pub fn contains(&self, index: EntityIndex) -> bool {
+        let index = index as usize;
+        self.layers[0].get(index / WORD_BITS).map_or(false, |word| word & (1 << (index % WORD_BITS)) != 0)
+    }
+
+    /**
+     * Returns false, if the index was allready part of the set.
+     */
+    #[allow(unused)]
+    pub fn insert(&mut self, index: EntityIndex) -> bool {
+        if self.contains(index) {
+            return false;
+        }
+        let mut index = index as usize;
+        for layer in self.layers.iter_mut() {
+            let word = index / WORD_BITS;
+            if layer.len() <= word {
+                layer.resize(word + 1, 0);
+            }
+            layer[word] |= 1 << (index % WORD_BITS);
+            index = word;
+        }
+        self.len += 1;
+        true
+    }
+
+    /**
+     * Returns false, if the index was not part of the set.
+     */
+    #[allow(unused)]
+    pub fn remove(&mut self, index: EntityIndex) -> bool {
+        if !self.contains(index) {
+            return false;
+        }
+        let mut index = index as usize;
+        for layer in self.layers.iter_mut() {
+            let word = index / WORD_BITS;
+            layer[word] &= !(1 << (index % WORD_BITS));
+            if layer[word] != 0 {
+                break;
+            }
+            index = word;
+        }
+        self.len -= 1;
+        true
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /**
+     * Frees the words past the highest index in the set.
+     */
+    #[allow(unused)]
+    pub fn shrink_to_fit(&mut self) {
+        for layer in self.layers.iter_mut() {
+            let used = layer.iter().rposition(|word| *word != 0).map_or(0, |word| word + 1);
+            layer.truncate(used);
+            layer.shrink_to_fit();
+        }
+    }
+
+    /**
+     * Bytes allocated for the words of all layers.
+     */
+    #[allow(unused)]
+    pub fn allocated_bytes(&self) -> usize {
+        self.layers.iter().map(|layer| layer.capacity() * std::mem::size_of::<u64>()).sum()
+    }
+
+    #[allow(unused)]
+    pub fn clear(&mut self) {
+        self.layers.iter_mut().for_each(|layer| layer.clear());
+        self.len = 0;
+    }
+
+    /**
+     * Iterates the indices in ascending order.
+     */
+    #[allow(unused)]
+    pub fn iter(&self) -> impl Iterator<Item = EntityIndex> + '_ {
+        iter_layers(self.layers[2].len(), move |layer, word| self.word(layer, word))
+    }
+
+    /**
+     * Iterates the indices in range in ascending order, only the words of the range are visited.
+     */
+    #[allow(unused)]
+    pub fn iter_range(&self, range: std::ops::Range<usize>) -> impl Iterator<Item = EntityIndex> + '_ {
+        let words = range.start / WORD_BITS..range.end.div_ceil(WORD_BITS).min(self.layers[0].len());
+        words
+            .flat_map(move |w0| iter_bits(self.word(0, w0)).map(move |bit| w0 * WORD_BITS + bit))
+            .filter(move |index| range.contains(index))
+            .map(|index| index as EntityIndex)
+    }
+
+    /**
+     * Number of indices the words of the set cover.
+     */
+    #[allow(unused)]
+    pub fn capacity(&self) -> usize {
+        self.layers[0].len() * WORD_BITS
+    }
+
+    /**
+     * Iterates the indices that are part of all sets in ascending order.
+     * Only the words that are set in every set on all layers are visited.
+     */
+    #[allow(unused)]
+    pub fn intersection<'a, I: Iterator<Item = &'a BitSet> + Clone + 'a>(sets: impl IntoIterator<IntoIter = I>) -> impl Iterator<Item = EntityIndex> + 'a {
+        let sets = sets.into_iter();
+        let top = sets.clone().map(|set| set.layers[2].len()).min().unwrap_or(0);
+        iter_layers(top, move |layer, word| sets.clone().fold(!0, |acc, set| acc & set.word(layer, word)))
+    }
+
+    fn word(&self, layer: usize, word: usize) -> u64 {
+        self.layers[layer].get(word).copied().unwrap_or(0)
+    }
+}
+
+fn iter_layers<'a>(top: usize, word: impl Fn(usize, usize) -> u64 + Clone + 'a) -> impl Iterator<Item = EntityIndex> + 'a {
+    let (word2, word1) = (word.clone(), word.clone());
+    (0..top)
+        .flat_map(move |w2| iter_bits(word2(2, w2)).map(move |bit| w2 * WORD_BITS + bit))
+        .flat_map(move |w1| iter_bits(word1(1, w1)).map(move |bit| w1 * WORD_BITS + bit))
+        .flat_map(move |w0| iter_bits(word(0, w0)).map(move |bit| (w0 * WORD_BITS + bit) as EntityIndex))
+}
+
+fn iter_bits(mut word: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if word == 0 {
+            None
+        } else {
+            let bit = word.trailing_zeros() as usize;
+            word &= word - 1;
+            Some(bit)
+        }
+    })
+}
+
+/**
+ * Store for zero sized marker components, only keeps a BitSet of the entities that have the tag.
+ * Added and changed are not tracked for tags, removals are.
+ */
+pub struct TagStore<T: Default + Clone> {
+    entities: BitSet,
+    changes: ChangeTracker,
+    lifecycle: LifecycleLog<T>,
+    _tag: std::marker::PhantomData<T>,
+}
+
+impl<T: Default + Clone> TagStore<T> {
+    /**
+     * The entities that have the tag, joins over several tags are driven by BitSet::intersection of their sets.
+     */
+    #[allow(unused)]
+    pub fn bits(&self) -> &BitSet {
+        &self.entities
+    }
+
+    #[allow(unused)]
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.entities.iter().map(|_| Self::tag())
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity(&self) -> impl Iterator<Item = (EntityIndex, &T)> {
+        self.entities.iter().map(|index| (index, Self::tag()))
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_mut(&mut self) -> impl Iterator<Item = (EntityIndex, &mut T)> {
+        self.entities.iter().map(|index| (index, Self::tag_mut()))
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_batch(&self, batch_size: usize) -> impl Iterator<Item = impl Iterator<Item = (EntityIndex, &T)>> {
+        self.index_batches(batch_size).into_iter()
+            .map(|batch| batch.into_iter().map(|index| (index, Self::tag())))
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_mut_batch(&mut self, batch_size: usize) -> impl Iterator<Item = impl Iterator<Item = (EntityIndex, &mut T)>> {
+        self.index_batches(batch_size).into_iter()
+            .map(|batch| batch.into_iter().map(|index| (index, Self::tag_mut())))
+    }
+
+    /**
+     * Entities of a range of indices, position_count covers every word of the set.
+     */
+    #[allow(unused)]
+    pub fn iter_entity_range(&self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &T)> {
+        self.entities.iter_range(range).map(|index| (index, Self::tag()))
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity_range_mut(&mut self, range: std::ops::Range<usize>) -> impl Iterator<Item = (EntityIndex, &mut T)> {
+        self.entities.iter_range(range).map(|index| (index, Self::tag_mut()))
+    }
+
+    fn index_batches(&self, batch_size: usize) -> Vec<Vec<EntityIndex>> {
+        let indices = self.entities.iter().collect::<Vec<_>>();
+        indices.chunks(batch_size.max(1)).map(|chunk| chunk.to_vec()).collect()
+    }
+
+    fn tag<'a>() -> &'a T {
+        // T is zero sized, so every well aligned pointer is a valid reference
+        unsafe{ &*std::ptr::NonNull::<T>::dangling().as_ptr() }
+    }
+
+    fn tag_mut<'a>() -> &'a mut T {
+        unsafe{ &mut *std::ptr::NonNull::<T>::dangling().as_ptr() }
+    }
+}
+
+impl<T: 'static + Default + Clone + Send + Sync> GenericComponentStore for TagStore<T> {
+    fn optimize(&mut self) {
+        self.entities.shrink_to_fit();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn has(&self, index: EntityIndex) -> bool {
+        self.entities.contains(index)
+    }
+
+    fn rem(&mut self, index: EntityIndex) {
+        assert!(self.entities.remove(index));
+        self.changes.log_removal(index);
+        self.lifecycle.removed(index, T::default());
+    }
+
+    fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    fn iter_indices(&self) -> Box<dyn Iterator<Item = EntityIndex> + '_> {
+        Box::new(self.entities.iter())
+    }
+
+    fn tag_bits(&self) -> Option<&BitSet> {
+        Some(&self.entities)
+    }
+
+    fn position_count(&self) -> usize {
+        self.entities.capacity()
+    }
+
+    fn memory(&self) -> StoreMemory {
+        StoreMemory{
+            len: self.entities.len(),
+            capacity: self.entities.layers[0].capacity() * WORD_BITS,
+            bytes: self.entities.allocated_bytes(),
+        }
+    }
+
+    fn change_tick(&self) -> Tick {
+        self.changes.tick
+    }
+
+    fn set_change_tick(&mut self, tick: Tick) {
+        self.changes.set_tick(tick);
+    }
+
+    fn removed(&self) -> &[(EntityIndex, Tick)] {
+        self.changes.removed()
+    }
+
+    fn log_changes(&mut self) -> bool {
+        self.changes.log_changes();
+        true
+    }
+
+    fn change_cursor(&self) -> ChangeCursor {
+        self.changes.cursor()
+    }
+
+    fn changes_since(&self, cursor: ChangeCursor) -> Option<&[EntityIndex]> {
+        self.changes.changes_since(cursor)
+    }
+
+    fn pause_change_log(&mut self, paused: bool) {
+        self.changes.pause(paused);
+    }
+
+    fn record_lifecycle(&mut self) -> bool {
+        self.lifecycle.record();
+        true
+    }
+
+    fn take_lifecycle(&mut self) -> Option<Box<dyn Any>> {
+        Some(Box::new(self.lifecycle.take()))
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any + Send + Sync>> {
+        Some(Box::new(self.entities.clone()))
+    }
+
+    fn restore_state(&mut self, state: &(dyn Any + Send + Sync)) {
+        self.entities.clone_from(state.downcast_ref::<BitSet>().unwrap());
+        self.lifecycle.take();
+        self.changes.log_rewrite();
+    }
+}
+
+impl<T: 'static + Default + Clone + Component> ComponentStore<T> for TagStore<T> {
+    type ComponentType = T;
+
+    fn new() -> Self {
+        assert!(std::mem::size_of::<T>() == 0, "TagStore can only store zero sized components, {} is not", std::any::type_name::<T>());
+        Self{
+            entities: BitSet::new(),
+            changes: ChangeTracker::default(),
+            lifecycle: LifecycleLog::default(),
+            _tag: std::marker::PhantomData,
+        }
+    }
+
+    fn get(&self, index: EntityIndex) -> Option<&T> {
+        if self.has(index) { Some(Self::tag()) } else { None }
+    }
+
+    fn get_mut(&mut self, index: EntityIndex) -> Option<&mut T> {
+        if self.has(index) { Some(Self::tag_mut()) } else { None }
+    }
+
+    fn set(&mut self, index: EntityIndex, value: T) {
+        assert!(self.has(index));
+        self.lifecycle.replaced(index, T::default(), &value);
+    }
+
+    fn add(&mut self, index: EntityIndex, value: T) {
+        assert!(self.entities.insert(index));
+        self.changes.log_change(index);
+        self.lifecycle.added(index, &value);
+    }
+}
+
+impl<T: 'static + Default + Clone + Send + Sync> BatchedStore<T> for TagStore<T> {
+    fn batches(&self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &T)> + Send + '_>> {
+        self.iter_entity_batch(batch_size)
+            .map(|batch| Box::new(batch) as Box<dyn Iterator<Item = (EntityIndex, &T)> + Send + '_>)
+            .collect()
+    }
+
+    fn batches_mut(&mut self, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, &mut T)> + Send + '_>> {
+        self.iter_entity_mut_batch(batch_size)
+            .map(|batch| Box::new(batch) as Box<dyn Iterator<Item = (EntityIndex, &mut T)> + Send + '_>)
+            .collect()
+    }
+}
@@ -1,6 +1,8 @@
 use crate::Vf32x2;
+use crate::Vf32x4;
 
 use super::{Component, DenseStore, LinearStore};
+use super::snapshot::{SnapshotComponent, SnapshotWriter, SnapshotReader, SnapshotError};
 
 #[derive(Clone, Copy)]
 pub struct Transform {
@@ -21,6 +23,24 @@ impl Component for Transform {
     type Storage = LinearStore<Self>;
 }
 
+crate::impl_reflect!(Transform { position: Vf32x2, orientation: Vf32x2 });
+
+impl SnapshotComponent for Transform {
+    const NAME: &'static str = "Transform";
+
+    fn save(&self, writer: &mut dyn SnapshotWriter) {
+        writer.write_vec2("position", self.position);
+        writer.write_vec2("orientation", self.orientation);
+    }
+
+    fn load(reader: &mut dyn SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self{
+            position: reader.read_vec2("position")?,
+            orientation: reader.read_vec2("orientation")?,
+        })
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct OldTransform {
     pub position: cgmath::Vector2<f32>,
@@ -40,6 +60,22 @@ impl Component for OldTransform {
     type Storage = LinearStore<Self>;
 }
 
+impl SnapshotComponent for OldTransform {
+    const NAME: &'static str = "OldTransform";
+
+    fn save(&self, writer: &mut dyn SnapshotWriter) {
+        writer.write_vec2("position", self.position);
+        writer.write_vec2("orientation", self.orientation);
+    }
+
+    fn load(reader: &mut dyn SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self{
+            position: reader.read_vec2("position")?,
+            orientation: reader.read_vec2("orientation")?,
+        })
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct RectRenderable {
     pub size: cgmath::Vector2<f32>,
@@ -57,4 +93,22 @@ impl Default for RectRenderable {
 
 impl Component for RectRenderable {
     type Storage = DenseStore<Self>;
-}
\ No newline at end of file
+}
+
+crate::impl_reflect!(RectRenderable { size: Vf32x2, color: Vf32x4 });
+
+impl SnapshotComponent for RectRenderable {
+    const NAME: &'static str = "RectRenderable";
+
+    fn save(&self, writer: &mut dyn SnapshotWriter) {
+        writer.write_vec2("size", self.size);
+        writer.write_vec4("color", self.color);
+    }
+
+    fn load(reader: &mut dyn SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self{
+            size: reader.read_vec2("size")?,
+            color: reader.read_vec4("color")?,
+        })
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+
+use crate::entity::handle::*;
+use crate::entity::snapshot::*;
+
+pub const DELTA_MAGIC: &str = "eisen_delta";
+pub const DELTA_VERSION: u32 = 1;
+
+/**
+ * State of a world as seen by replication, captured with EntityComponentManager::capture_state.
+ * Keeps the entity slots and the serialized bytes of every component registered via register_snapshot_component.
+ * The sequence number identifies the state, a server keeps the state a client acknowledged as the base of the next delta.
+ */
+#[derive(Clone, Default)]
+pub struct WorldState {
+    pub(crate) sequence: u64,
+    pub(crate) slots: Vec<(EntityVersion, bool)>,
+    pub(crate) components: BTreeMap<&'static str, BTreeMap<EntityIndex, Vec<u8>>>,
+}
+
+impl WorldState {
+    /**
+     * The state of an empty world with sequence 0, deltas against it contain the whole world.
+     */
+    #[allow(unused)]
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    #[allow(unused)]
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+/**
+ * Changes of the components of one type.
+ */
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct ComponentDelta {
+    pub name: String,
+    pub removed: Vec<EntityIndex>,
+    /**
+     * Added and changed components, in the binary snapshot format of the component.
+     */
+    pub set: Vec<(EntityIndex, Vec<u8>)>,
+}
+
+/**
+ * Difference between two world states.
+ * Slots lists the entity slots whose version or liveness changed, an entity is created or destroyed by that alone.
+ * Components of destroyed entities are removed implicitly, they are not listed in removed.
+ */
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct WorldDelta {
+    pub base_sequence: u64,
+    pub sequence: u64,
+    pub slot_count: u32,
+    pub slots: Vec<(EntityIndex, EntityVersion, bool)>,
+    pub components: Vec<ComponentDelta>,
+}
+
+impl WorldDelta {
+    /**
+     * Computes the changes that turn base into current.
+     */
+    #[allow(unused)]
+    pub fn between(base: &WorldState, current: &WorldState) -> Self {
+        assert!(current.sequence > base.sequence, "The current state must be newer than the base state.");
+        let slot = |state: &WorldState, index: usize| state.slots.get(index).copied().unwrap_or((0, false));
+        let unchanged = |index: EntityIndex| {
+            let (version, alive) = slot(current, index as usize);
+            alive && slot(base, index as usize) == (version, alive)
+        };
+
+        let slots = (0..current.slots.len())
+            .filter(|index| slot(base, *index) != slot(current, *index))
+            .map(|index| (index as EntityIndex, current.slots[index].0, current.slots[index].1))
+            .collect();
+
+        let empty = BTreeMap::new();
+        let mut names = base.components.keys().chain(current.components.keys()).copied().collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        let components = names.into_iter()
+            .map(|name| {
+                let before = base.components.get(name).unwrap_or(&empty);
+                let after = current.components.get(name).unwrap_or(&empty);
+                ComponentDelta{
+                    name: name.to_string(),
+                    removed: before.keys()
+                        .filter(|index| unchanged(**index) && !after.contains_key(index))
+                        .copied()
+                        .collect(),
+                    set: after.iter()
+                        .filter(|(index, bytes)| !unchanged(**index) || before.get(index) != Some(bytes))
+                        .map(|(index, bytes)| (*index, bytes.clone()))
+                        .collect(),
+                }
+            })
+            .filter(|delta| !delta.removed.is_empty() || !delta.set.is_empty())
+            .collect();
+
+        Self{
+            base_sequence: base.sequence,
+            sequence: current.sequence,
+            slot_count: current.slots.len() as u32,
+            slots,
+            components,
+        }
+    }
+
+    /**
+     * True, if applying the delta only advances the sequence number.
+     */
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty() && self.components.is_empty()
+    }
+
+    /**
+     * Encodes the delta into the byte format that is streamed to clients.
+     */
+    #[allow(unused)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = BinarySnapshotWriter::new();
+        writer.write_str("magic", DELTA_MAGIC);
+        writer.write_u32("version", DELTA_VERSION);
+        writer.write_u64("base_sequence", self.base_sequence);
+        writer.write_u64("sequence", self.sequence);
+        writer.write_u32("slot_count", self.slot_count);
+        writer.write_u32("changed_slots", self.slots.len() as u32);
+        for (index, version, alive) in &self.slots {
+            writer.write_u32("index", *index);
+            writer.write_u32("version", *version);
+            writer.write_bool("alive", *alive);
+        }
+        writer.write_u32("component_count", self.components.len() as u32);
+        for component in &self.components {
+            writer.write_str("name", &component.name);
+            writer.write_u32("removed_count", component.removed.len() as u32);
+            for index in &component.removed {
+                writer.write_u32("entity", *index);
+            }
+            writer.write_u32("set_count", component.set.len() as u32);
+            for (index, bytes) in &component.set {
+                writer.write_u32("entity", *index);
+                writer.write_u32("len", bytes.len() as u32);
+                writer.data.extend_from_slice(bytes);
+            }
+        }
+        writer.data
+    }
+
+    #[allow(unused)]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = BinarySnapshotReader::new(data);
+        if reader.read_string("magic").map_err(|_| SnapshotError::InvalidMagic)? != DELTA_MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        let version = reader.read_u32("version")?;
+        if version == 0 || version > DELTA_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let mut delta = Self{
+            base_sequence: reader.read_u64("base_sequence")?,
+            sequence: reader.read_u64("sequence")?,
+            slot_count: reader.read_u32("slot_count")?,
+            ..Self::default()
+        };
+        for _ in 0..reader.read_u32("changed_slots")? {
+            let index = checked_index(reader.read_u32("index")?, delta.slot_count)?;
+            delta.slots.push((index, reader.read_u32("version")?, reader.read_bool("alive")?));
+        }
+        for _ in 0..reader.read_u32("component_count")? {
+            let mut component = ComponentDelta{ name: reader.read_string("name")?, ..ComponentDelta::default() };
+            for _ in 0..reader.read_u32("removed_count")? {
+                component.removed.push(checked_index(reader.read_u32("entity")?, delta.slot_count)?);
+            }
+            for _ in 0..reader.read_u32("set_count")? {
+                let index = checked_index(reader.read_u32("entity")?, delta.slot_count)?;
+                let len = reader.read_u32("len")? as usize;
+                component.set.push((index, reader.read_bytes(len)?.to_vec()));
+            }
+            delta.components.push(component);
+        }
+        if !reader.is_at_end() {
+            return Err(SnapshotError::Malformed(String::from("trailing bytes after delta")));
+        }
+        Ok(delta)
+    }
+}
@@ -0,0 +1,57 @@
+use crate::entity::component_storage::*;
+
+/**
+ * Memory report of one component store, name is the type name of the store or the layout name of a dynamic component.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct StoreDiagnostics {
+    pub name: String,
+    pub memory: StoreMemory,
+}
+
+/**
+ * Summary of a world for memory budgets, taken with EntityComponentManager::diagnostics.
+ * Resources, events, indices and pending commands are not included.
+ */
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct WorldDiagnostics {
+    pub entities: usize,
+    pub entity_slots: usize,
+    pub free_slots: usize,
+    pub entity_bytes: usize,
+    /**
+     * Sorted by bytes, the largest store first.
+     */
+    pub stores: Vec<StoreDiagnostics>,
+}
+
+impl WorldDiagnostics {
+    #[allow(unused)]
+    pub fn components(&self) -> usize {
+        self.stores.iter().map(|store| store.memory.len).sum()
+    }
+
+    /**
+     * Bytes of the entity slots and all stores.
+     */
+    #[allow(unused)]
+    pub fn bytes(&self) -> usize {
+        self.entity_bytes + self.stores.iter().map(|store| store.memory.bytes).sum::<usize>()
+    }
+
+    #[allow(unused)]
+    pub fn store(&self, name: &str) -> Option<&StoreDiagnostics> {
+        self.stores.iter().find(|store| store.name == name)
+    }
+}
+
+impl std::fmt::Display for WorldDiagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "entities: {} alive, {} slots, {} free, {} bytes", self.entities, self.entity_slots, self.free_slots, self.entity_bytes)?;
+        for store in &self.stores {
+            writeln!(f, "{}: {} components, {} capacity, {} bytes, {:.1}% fragmented",
+                store.name, store.memory.len, store.memory.capacity, store.memory.bytes, store.memory.fragmentation() * 100.0)?;
+        }
+        write!(f, "total: {} components, {} bytes", self.components(), self.bytes())
+    }
+}
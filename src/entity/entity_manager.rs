@@ -1,3 +1,5 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
 use crate::entity::handle::*; 
 use super::component_storage::*;
 
@@ -5,6 +7,7 @@ pub struct EntityManager {
     pub(crate) entity_slots: Vec<EntitySlot>,
     pub entity_free_list: Vec<EntityIndex>,
     pub entity_destruct_queue: Vec<EntityIndex>,
+    reserved_count: AtomicU32,
 }
 
 impl EntityManager {
@@ -13,6 +16,7 @@ impl EntityManager {
             entity_slots: Vec::new(),
             entity_free_list: Vec::new(),
             entity_destruct_queue: Vec::new(),
+            reserved_count: AtomicU32::new(0),
         }
     }
 
@@ -29,12 +33,33 @@ impl EntityManager {
         }
     }
 
+    /**
+     * Copy of the entities for rollback, reserved entities are not part of it.
+     */
+    pub(crate) fn save_state(&self) -> Self {
+        Self{
+            entity_slots: self.entity_slots.clone(),
+            entity_free_list: self.entity_free_list.clone(),
+            entity_destruct_queue: self.entity_destruct_queue.clone(),
+            reserved_count: AtomicU32::new(0),
+        }
+    }
+
+    /**
+     * Handle of the index with the version of its slot, also for entities that were destroyed but not yet cleaned up.
+     */
+    pub(crate) fn slot_handle(&self, index: EntityIndex) -> EntityHandle {
+        let version = self.entity_slots.get(index as usize).map_or(0, |slot| slot.version);
+        EntityHandle{index, version}
+    }
+
     pub fn exists(&self, entity: EntityHandle) -> bool {
         (entity.index as usize) < self.entity_slots.len() && self.entity_slots[entity.index as usize].alive && self.entity_slots[entity.index as usize].version == entity.version
     }
 
     #[allow(unused)]
     pub fn create(&mut self) -> EntityHandle {
+        self.flush_reserved();
         let index = self.entity_free_list.pop().unwrap_or(
             {
                 let index = self.entity_slots.len();
@@ -47,6 +72,30 @@ impl EntityManager {
         EntityHandle{index:index, version:entity_slot.version}
     }
 
+    /**
+     * Reserves a handle for an entity without needing exclusive access, for example inside of parallel iteration.
+     * Reserved entities do not exist until the next flush_reserved, which happens in create and when Commands are applied.
+     * Reserved entities never reuse freed indices.
+     */
+    #[allow(unused)]
+    pub fn reserve(&self) -> EntityHandle {
+        let offset = self.reserved_count.fetch_add(1, Ordering::Relaxed);
+        EntityHandle{index: (self.entity_slots.len() as u32 + offset) as EntityIndex, version: 0}
+    }
+
+    /**
+     * Makes all reserved entities exist.
+     */
+    #[allow(unused)]
+    pub fn flush_reserved(&mut self) {
+        let reserved = std::mem::replace(self.reserved_count.get_mut(), 0);
+        for _ in 0..reserved {
+            let mut slot = EntitySlot::new();
+            slot.alive = true;
+            self.entity_slots.push(slot);
+        }
+    }
+
     #[allow(unused)]
     pub fn destroy(&mut self, entity: EntityHandle) {
         assert!(self.exists(entity));
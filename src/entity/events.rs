@@ -0,0 +1,157 @@
+use std::any::Any;
+use std::sync::Mutex;
+
+#[derive(Clone)]
+struct EventBuffers<E> {
+    previous: Vec<E>,
+    current: Vec<E>,
+    previous_start: u64,
+    current_start: u64,
+}
+
+/**
+ * Double buffered queue of events of one type.
+ * Events are kept for two updates, the EntityComponentManager updates all queues after every fixed step.
+ * Sending only needs a shared reference, so events can be sent from within parallel iteration.
+ */
+pub struct Events<E> {
+    buffers: Mutex<EventBuffers<E>>,
+}
+
+impl<E> Default for Events<E> {
+    fn default() -> Self {
+        Self{
+            buffers: Mutex::new(EventBuffers{
+                previous: Vec::new(),
+                current: Vec::new(),
+                previous_start: 0,
+                current_start: 0,
+            }),
+        }
+    }
+}
+
+impl<E> Events<E> {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(unused)]
+    pub fn send(&self, event: E) {
+        self.buffers.lock().unwrap().current.push(event);
+    }
+
+    /**
+     * Drops the events of the previous update, the current events become the previous ones.
+     */
+    #[allow(unused)]
+    pub fn update(&self) {
+        let mut buffers = self.buffers.lock().unwrap();
+        let buffers = &mut *buffers;
+        buffers.previous_start = buffers.current_start;
+        buffers.current_start += buffers.current.len() as u64;
+        std::mem::swap(&mut buffers.previous, &mut buffers.current);
+        buffers.current.clear();
+    }
+
+    /**
+     * Number of events that are still kept.
+     */
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        let buffers = self.buffers.lock().unwrap();
+        buffers.previous.len() + buffers.current.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /**
+     * Copy of the kept events, used by rollback frames.
+     */
+    pub(crate) fn save_state(&self) -> Box<dyn Any + Send + Sync> where E: Clone + Send + Sync + 'static {
+        Box::new(self.buffers.lock().unwrap().clone())
+    }
+
+    /**
+     * Puts back the events of save_state, readers keep their cursor and only see events sent after it.
+     */
+    pub(crate) fn restore_state(&self, state: &(dyn Any + Send + Sync)) where E: Clone + Send + Sync + 'static {
+        *self.buffers.lock().unwrap() = state.downcast_ref::<EventBuffers<E>>().unwrap().clone();
+    }
+
+    /**
+     * Creates a reader that only sees events sent after its creation.
+     */
+    #[allow(unused)]
+    pub fn reader(&self) -> EventReader<E> {
+        let buffers = self.buffers.lock().unwrap();
+        EventReader{
+            cursor: buffers.current_start + buffers.current.len() as u64,
+            _event: std::marker::PhantomData,
+        }
+    }
+}
+
+/**
+ * Cursor into an Events queue, every reader sees every event once.
+ * A reader that is not read for more than two updates misses events.
+ * Readers do not borrow the queue, so they can be kept in fixed step as well as in variable step code.
+ */
+pub struct EventReader<E> {
+    cursor: u64,
+    _event: std::marker::PhantomData<fn() -> E>,
+}
+
+impl<E> Default for EventReader<E> {
+    /**
+     * A reader that sees all events the queue still keeps.
+     */
+    fn default() -> Self {
+        Self{
+            cursor: 0,
+            _event: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<E> EventReader<E> {
+    /**
+     * Calls f for all events this reader has not seen yet, in the order they were sent.
+     */
+    #[allow(unused)]
+    pub fn for_each(&mut self, events: &Events<E>, mut f: impl FnMut(&E)) {
+        let buffers = events.buffers.lock().unwrap();
+        let skip_previous = self.cursor.saturating_sub(buffers.previous_start) as usize;
+        let skip_current = self.cursor.saturating_sub(buffers.current_start) as usize;
+        buffers.previous.iter().skip(skip_previous).for_each(&mut f);
+        buffers.current.iter().skip(skip_current).for_each(&mut f);
+        self.cursor = buffers.current_start + buffers.current.len() as u64;
+    }
+
+    /**
+     * Returns clones of all events this reader has not seen yet, in the order they were sent.
+     */
+    #[allow(unused)]
+    pub fn read(&mut self, events: &Events<E>) -> Vec<E> where E: Clone {
+        let mut read = Vec::new();
+        self.for_each(events, |event| read.push(event.clone()));
+        read
+    }
+}
+
+/**
+ * Type erased Events queue, as it is kept by the EntityComponentManager.
+ */
+pub(crate) trait EventQueue: Send + Sync {
+    fn update(&self);
+}
+
+impl<E: Send + 'static> EventQueue for Events<E> {
+    fn update(&self) {
+        Events::update(self);
+    }
+}
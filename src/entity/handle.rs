@@ -1,7 +1,7 @@
 pub type EntityIndex = u32;
 pub type EntityVersion = u32;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct EntityHandle {
     pub index: EntityIndex,
     pub version: EntityVersion,
@@ -0,0 +1,244 @@
+use std::any::TypeId;
+use std::sync::Arc;
+
+use async_std::sync::RwLock;
+use cgmath::InnerSpace;
+
+use crate::Vf32x2;
+use crate::entity::handle::*;
+use crate::entity::entity_manager::*;
+use crate::entity::component_storage::*;
+use crate::entity::default_components::Transform;
+
+/**
+ * The entity this entity is attached to.
+ * Maintained by EntityManager::set_parent and EntityManager::remove_parent, do not add it by hand.
+ */
+#[derive(Clone, Copy)]
+pub struct Parent(pub EntityHandle);
+
+impl Default for Parent {
+    fn default() -> Self {
+        Self(EntityHandle{ index: !0, version: 0 })
+    }
+}
+
+impl Component for Parent {
+    type Storage = DenseStore<Self>;
+}
+
+crate::impl_reflect!(Parent { 0: EntityHandle });
+
+/**
+ * The entities attached to this entity.
+ * Maintained by EntityManager::set_parent and EntityManager::remove_parent, do not add it by hand.
+ */
+#[derive(Clone, Default)]
+pub struct Children(pub Vec<EntityHandle>);
+
+impl Component for Children {
+    type Storage = DenseStore<Self>;
+}
+
+/**
+ * World space transform, written by propagate_transforms.
+ * For entities with a Parent, Transform is relative to the parents GlobalTransform.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub struct GlobalTransform {
+    pub position: cgmath::Vector2<f32>,
+    pub orientation: cgmath::Vector2<f32>,
+}
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        Self{
+            position: Vf32x2::new(0.0, 0.0),
+            orientation: Vf32x2::new(0.0, 0.0),
+        }
+    }
+}
+
+impl Component for GlobalTransform {
+    type Storage = LinearStore<Self>;
+}
+
+crate::impl_reflect!(GlobalTransform { position: Vf32x2, orientation: Vf32x2 });
+
+impl GlobalTransform {
+    pub fn from_local(local: &Transform) -> Self {
+        Self{
+            position: local.position,
+            orientation: local.orientation,
+        }
+    }
+
+    /**
+     * Transforms a transform local to this one into world space.
+     * Orientations are treated as direction vectors, a zero orientation means no rotation.
+     */
+    pub fn mul_transform(&self, local: &Transform) -> Self {
+        let rotation = rotation_of(self.orientation);
+        Self{
+            position: self.position + rotate(rotation, local.position),
+            orientation: rotate(rotation, rotation_of(local.orientation)),
+        }
+    }
+}
+
+fn rotation_of(orientation: Vf32x2) -> Vf32x2 {
+    if orientation.magnitude2() > 0.0 {
+        orientation.normalize()
+    } else {
+        Vf32x2::new(1.0, 0.0)
+    }
+}
+
+fn rotate(rotation: Vf32x2, v: Vf32x2) -> Vf32x2 {
+    Vf32x2::new(rotation.x * v.x - rotation.y * v.y, rotation.y * v.x + rotation.x * v.y)
+}
+
+impl EntityManager {
+    /**
+     * Attaches child to parent, detaching it from its previous parent.
+     * Panics if this would create a cycle.
+     */
+    #[allow(unused)]
+    pub fn set_parent(&self, parents: &mut DenseStore<Parent>, children: &mut DenseStore<Children>, child: EntityHandle, parent: EntityHandle) {
+        assert!(self.exists(child) && self.exists(parent));
+        let mut ancestor = Some(parent);
+        while let Some(a) = ancestor {
+            assert!(a.index != child.index, "can not attach an entity to itself or one of its descendants");
+            ancestor = parents.get(a.index).map(|p| p.0);
+        }
+
+        self.remove_parent(parents, children, child);
+
+        parents.add(child.index, Parent(parent));
+        match children.get_mut(parent.index) {
+            Some(siblings) => siblings.0.push(child),
+            None => children.add(parent.index, Children(vec![child])),
+        }
+    }
+
+    /**
+     * Detaches child from its parent, if it has one.
+     */
+    #[allow(unused)]
+    pub fn remove_parent(&self, parents: &mut DenseStore<Parent>, children: &mut DenseStore<Children>, child: EntityHandle) {
+        assert!(self.exists(child));
+        if let Some(Parent(parent)) = parents.get(child.index).copied() {
+            parents.rem(child.index);
+            detach_child(children, parent.index, child.index);
+        }
+    }
+
+    /**
+     * Calls f for every descendant of entity, parents before their children.
+     */
+    #[allow(unused)]
+    pub fn for_each_descendant(&self, children: &DenseStore<Children>, entity: EntityHandle, mut f: impl FnMut(EntityHandle)) {
+        let mut stack = Vec::new();
+        if let Some(c) = children.get(entity.index) {
+            stack.extend(c.0.iter().rev().copied());
+        }
+        while let Some(descendant) = stack.pop() {
+            f(descendant);
+            if let Some(c) = children.get(descendant.index) {
+                stack.extend(c.0.iter().rev().copied());
+            }
+        }
+    }
+}
+
+fn detach_child(children: &mut DenseStore<Children>, parent: EntityIndex, child: EntityIndex) {
+    let now_empty = match children.get_mut(parent) {
+        Some(siblings) => {
+            siblings.0.retain(|c| c.index != child);
+            siblings.0.is_empty()
+        },
+        None => false,
+    };
+    if now_empty {
+        children.rem(parent);
+    }
+}
+
+fn store_of<C: Component>(stores: &StoreMap) -> Option<Arc<RwLock<C::Storage>>> {
+    stores.get(&TypeId::of::<C>())
+        .map(|store| store.as_any_ref().downcast_ref::<Arc<RwLock<C::Storage>>>().unwrap().clone())
+}
+
+/**
+ * Destroys all descendants of the destroyed entities and detaches the destroyed entities from surviving parents.
+ * Called by EntityComponentManager::cleanup before the components of destroyed entities are removed,
+ * this is what makes EntityManager::destroy recursive.
+ */
+pub(crate) fn expand_destruction(entities: &mut EntityManager, stores: &StoreMap) {
+    let children = match store_of::<Children>(stores) {
+        Some(children) => children,
+        None => return,
+    };
+    let mut children = spin_on!(children.try_write());
+
+    let mut i = 0;
+    while i < entities.entity_destruct_queue.len() {
+        let index = entities.entity_destruct_queue[i];
+        if let Some(c) = children.get(index) {
+            for child in c.0.clone() {
+                if entities.exists(child) {
+                    entities.destroy(child);
+                }
+            }
+        }
+        i += 1;
+    }
+
+    if let Some(parents) = store_of::<Parent>(stores) {
+        let parents = spin_on!(parents.try_read());
+        for index in &entities.entity_destruct_queue {
+            if let Some(Parent(parent)) = parents.get(*index) {
+                if entities.exists(*parent) {
+                    detach_child(&mut children, parent.index, *index);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Computes the GlobalTransform of every entity with a Transform from the root of its hierarchy downwards.
+ * Only hierarchies whose root has a Transform are visited.
+ * Entities without a Transform inside a hierarchy count as not transformed relative to their parent.
+ * GlobalTransforms that stay the same are not written, so only moved entities count as changed.
+ */
+#[allow(unused)]
+pub fn propagate_transforms(
+    transforms: &LinearStore<Transform>,
+    globals: &mut LinearStore<GlobalTransform>,
+    parents: &DenseStore<Parent>,
+    children: &DenseStore<Children>,
+) {
+    let mut stack: Vec<(EntityIndex, GlobalTransform)> = Vec::new();
+    for (index, local) in transforms.iter_entity() {
+        if parents.has(index) {
+            continue;
+        }
+        stack.push((index, GlobalTransform::from_local(local)));
+        while let Some((index, global)) = stack.pop() {
+            if transforms.has(index) {
+                match globals.get(index) {
+                    Some(g) if *g == global => {},
+                    Some(_) => *globals.get_mut(index).unwrap() = global,
+                    None => globals.add(index, global),
+                }
+            }
+            if let Some(c) = children.get(index) {
+                for child in &c.0 {
+                    let local = transforms.get(child.index).copied().unwrap_or_default();
+                    stack.push((child.index, global.mul_transform(&local)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::any::Any;
+
+use crate::entity::handle::*;
+use crate::entity::entity_manager::*;
+use crate::entity::component_storage::*;
+use crate::entity::commands::*;
+
+/**
+ * What a lifecycle hook has access to while it runs.
+ * Structural changes have to go through the commands, they are applied by the next apply_commands or cleanup.
+ */
+pub struct HookContext<'a> {
+    pub commands: &'a Commands,
+    pub entities: &'a EntityManager,
+}
+
+type AddHook<C> = Box<dyn Fn(&HookContext, EntityHandle, &C) + Send + Sync>;
+
+type ReplaceHook<C> = Box<dyn Fn(&HookContext, EntityHandle, &C, &C) + Send + Sync>;
+
+type RemoveHook<C> = Box<dyn Fn(&HookContext, EntityHandle, &C) + Send + Sync>;
+
+/**
+ * Callbacks registered for the component C, see EntityComponentManager::on_add, on_replace and on_remove.
+ */
+pub struct ComponentHooks<C: Component> {
+    pub(crate) on_add: Vec<AddHook<C>>,
+    pub(crate) on_replace: Vec<ReplaceHook<C>>,
+    pub(crate) on_remove: Vec<RemoveHook<C>>,
+}
+
+impl<C: Component> Default for ComponentHooks<C> {
+    fn default() -> Self {
+        Self{
+            on_add: Vec::new(),
+            on_replace: Vec::new(),
+            on_remove: Vec::new(),
+        }
+    }
+}
+
+impl<C: Component> ComponentHooks<C> {
+    /**
+     * Calls the hooks for the events in the order they happened.
+     */
+    pub fn dispatch(&self, events: Vec<LifecycleEvent<C>>, context: &HookContext) {
+        for event in events {
+            match event {
+                LifecycleEvent::Added(index, value) => {
+                    let entity = context.entities.slot_handle(index);
+                    self.on_add.iter().for_each(|hook| hook(context, entity, &value));
+                }
+                LifecycleEvent::Replaced(index, old, new) => {
+                    let entity = context.entities.slot_handle(index);
+                    self.on_replace.iter().for_each(|hook| hook(context, entity, &old, &new));
+                }
+                LifecycleEvent::Removed(index, old) => {
+                    let entity = context.entities.slot_handle(index);
+                    self.on_remove.iter().for_each(|hook| hook(context, entity, &old));
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Type erased hooks, as they are kept by the EntityComponentManager.
+ */
+pub(crate) trait LifecycleHooks: Send + Sync {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn dispatch_erased(&self, events: Box<dyn Any>, context: &HookContext);
+}
+
+impl<C: Component> LifecycleHooks for ComponentHooks<C> {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn dispatch_erased(&self, events: Box<dyn Any>, context: &HookContext) {
+        self.dispatch(*events.downcast::<Vec<LifecycleEvent<C>>>().unwrap(), context);
+    }
+}
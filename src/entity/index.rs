@@ -0,0 +1,264 @@
+use std::any::TypeId;
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::ops::RangeBounds;
+use std::marker::PhantomData;
+use std::sync::{Mutex, MutexGuard};
+
+use rustc_hash::FxHashMap;
+
+use crate::entity::handle::*;
+use crate::entity::entity_manager::*;
+use crate::entity::component_storage::*;
+
+/**
+ * Map from keys to the entities whose component has that key.
+ */
+pub trait IndexMap<K>: Default + Send + Sync + 'static {
+    fn insert(&mut self, key: K, entity: EntityHandle);
+
+    fn remove(&mut self, key: &K, entity: EntityHandle);
+
+    fn get(&self, key: &K) -> &[EntityHandle];
+
+    fn len(&self) -> usize;
+}
+
+impl<K: Hash + Eq + Send + Sync + 'static> IndexMap<K> for FxHashMap<K, Vec<EntityHandle>> {
+    fn insert(&mut self, key: K, entity: EntityHandle) {
+        self.entry(key).or_default().push(entity);
+    }
+
+    fn remove(&mut self, key: &K, entity: EntityHandle) {
+        if let Some(entities) = self.get_mut(key) {
+            entities.retain(|e| e.index != entity.index);
+            if entities.is_empty() {
+                self.remove(key);
+            }
+        }
+    }
+
+    fn get(&self, key: &K) -> &[EntityHandle] {
+        std::collections::HashMap::get(self, key).map_or(&[], |entities| entities.as_slice())
+    }
+
+    fn len(&self) -> usize {
+        self.values().map(|entities| entities.len()).sum()
+    }
+}
+
+impl<K: Ord + Send + Sync + 'static> IndexMap<K> for BTreeMap<K, Vec<EntityHandle>> {
+    fn insert(&mut self, key: K, entity: EntityHandle) {
+        self.entry(key).or_default().push(entity);
+    }
+
+    fn remove(&mut self, key: &K, entity: EntityHandle) {
+        if let Some(entities) = self.get_mut(key) {
+            entities.retain(|e| e.index != entity.index);
+            if entities.is_empty() {
+                self.remove(key);
+            }
+        }
+    }
+
+    fn get(&self, key: &K) -> &[EntityHandle] {
+        BTreeMap::get(self, key).map_or(&[], |entities| entities.as_slice())
+    }
+
+    fn len(&self) -> usize {
+        self.values().map(|entities| entities.len()).sum()
+    }
+}
+
+struct IndexState<K, M> {
+    entries: M,
+    keys: FxHashMap<EntityIndex, (K, EntityHandle)>,
+    cursor: Option<ChangeCursor>,
+}
+
+/**
+ * Secondary index over a key extracted from the component C.
+ * Lookups go through lookup, which first brings the index up to date with the store it is given,
+ * so components added, set, changed through get_mut or removed in the current tick are found with their current key.
+ * Registering the index with the EntityComponentManager turns on the change log of the store,
+ * a lookup then only re-extracts the keys of the components changed since the last one.
+ * Writes through mutable iterators count as changes of every component, the next lookup indexes the whole store again.
+ */
+pub struct ComponentIndex<C: Component, K, M> {
+    key: Box<dyn Fn(&C) -> K + Send + Sync>,
+    state: Mutex<IndexState<K, M>>,
+}
+
+pub type HashIndex<C, K> = ComponentIndex<C, K, FxHashMap<K, Vec<EntityHandle>>>;
+
+pub type OrderedIndex<C, K> = ComponentIndex<C, K, BTreeMap<K, Vec<EntityHandle>>>;
+
+impl<C: Component, K: Clone + PartialEq + Send + Sync + 'static, M: IndexMap<K>> ComponentIndex<C, K, M> {
+    #[allow(unused)]
+    pub fn new(key: impl Fn(&C) -> K + Send + Sync + 'static) -> Self {
+        Self{
+            key: Box::new(key),
+            state: Mutex::new(IndexState{
+                entries: M::default(),
+                keys: FxHashMap::default(),
+                cursor: None,
+            }),
+        }
+    }
+
+    /**
+     * Refreshes the index against the store and gives access to its entries.
+     * The lookup borrows the store, so it can not be written while the lookup is alive and the answers stay current.
+     */
+    #[allow(unused)]
+    pub fn lookup<'a>(&'a self, store: &'a C::Storage, entities: &EntityManager) -> IndexLookup<'a, K, M> {
+        self.refresh(store, entities);
+        IndexLookup{
+            state: self.state.lock().unwrap(),
+            _store: PhantomData,
+        }
+    }
+
+    /**
+     * Brings the index up to date with the store.
+     * Only the entities in the change log of the store since the last refresh are looked at,
+     * the first refresh and refreshes after a rewrite or without a change log index every component.
+     */
+    pub fn refresh(&self, store: &C::Storage, entities: &EntityManager) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+
+        match state.cursor.and_then(|cursor| store.changes_since(cursor)) {
+            Some(changes) => {
+                for index in changes {
+                    self.reindex(state, store, entities, *index);
+                }
+            },
+            None => {
+                let removed = state.keys.keys().copied().filter(|index| !store.has(*index)).collect::<Vec<_>>();
+                for index in removed {
+                    self.reindex(state, store, entities, index);
+                }
+                for index in store.iter_indices() {
+                    self.reindex(state, store, entities, index);
+                }
+            },
+        }
+        state.cursor = Some(store.change_cursor());
+    }
+
+    fn reindex(&self, state: &mut IndexState<K, M>, store: &C::Storage, entities: &EntityManager, index: EntityIndex) {
+        let value = match store.get(index) {
+            Some(value) => value,
+            None => {
+                if let Some((key, entity)) = state.keys.remove(&index) {
+                    state.entries.remove(&key, entity);
+                }
+                return;
+            },
+        };
+        let entity = EntityHandle{ index, version: entities.version_of(index).unwrap_or(0) };
+        let key = (self.key)(value);
+        if let Some((old_key, old_entity)) = state.keys.get(&index) {
+            if *old_key == key && *old_entity == entity {
+                return;
+            }
+            state.entries.remove(old_key, *old_entity);
+        }
+        state.entries.insert(key.clone(), entity);
+        state.keys.insert(index, (key, entity));
+    }
+}
+
+/**
+ * Entries of a ComponentIndex that are up to date with the borrowed store, taken with ComponentIndex::lookup.
+ */
+pub struct IndexLookup<'a, K, M> {
+    state: MutexGuard<'a, IndexState<K, M>>,
+    _store: PhantomData<&'a ()>,
+}
+
+impl<'a, K: Clone + PartialEq, M: IndexMap<K>> IndexLookup<'a, K, M> {
+    /**
+     * All entities whose component has the key.
+     */
+    #[allow(unused)]
+    pub fn get(&self, key: &K) -> Vec<EntityHandle> {
+        self.state.entries.get(key).to_vec()
+    }
+
+    /**
+     * The first entity whose component has the key, for keys that are unique.
+     */
+    #[allow(unused)]
+    pub fn get_one(&self, key: &K) -> Option<EntityHandle> {
+        self.state.entries.get(key).first().copied()
+    }
+
+    #[allow(unused)]
+    pub fn contains(&self, key: &K) -> bool {
+        !self.state.entries.get(key).is_empty()
+    }
+
+    #[allow(unused)]
+    pub fn key_of(&self, entity: EntityHandle) -> Option<K> {
+        match self.state.keys.get(&entity.index) {
+            Some((key, indexed)) if *indexed == entity => Some(key.clone()),
+            _ => None,
+        }
+    }
+
+    /**
+     * Number of indexed entities.
+     */
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.state.keys.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a, K: Ord + Clone> IndexLookup<'a, K, BTreeMap<K, Vec<EntityHandle>>> {
+    /**
+     * All entities whose key lies in the range, ordered by key.
+     */
+    #[allow(unused)]
+    pub fn range(&self, range: impl RangeBounds<K>) -> Vec<EntityHandle> {
+        self.state.entries.range(range).flat_map(|(_, entities)| entities.iter().copied()).collect()
+    }
+}
+
+/**
+ * Type erased index, as it is kept by the EntityComponentManager.
+ */
+pub(crate) trait IndexMaintainer: Send + Sync {
+    fn component_type(&self) -> TypeId;
+
+    fn refresh_erased(&self, store: &dyn GenericComponentStore, entities: &EntityManager);
+
+    /**
+     * Forgets all entries, the next refresh indexes every component again.
+     */
+    fn invalidate(&self);
+}
+
+impl<C: Component, K: Clone + PartialEq + Send + Sync + 'static, M: IndexMap<K>> IndexMaintainer for ComponentIndex<C, K, M> {
+    fn component_type(&self) -> TypeId {
+        TypeId::of::<C>()
+    }
+
+    fn refresh_erased(&self, store: &dyn GenericComponentStore, entities: &EntityManager) {
+        self.refresh(store.as_any().downcast_ref::<C::Storage>().unwrap(), entities);
+    }
+
+    fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries = M::default();
+        state.keys.clear();
+        state.cursor = None;
+    }
+}
@@ -3,22 +3,98 @@
 #[allow(unused)]
 pub use crate::util::*;
 pub use crate::entity::component_storage::*;
+use crate::entity::handle::EntityIndex;
+use crate::sync::{Runtime, RuntimeHandle, AtomicDependency};
+use crate::sync::task::Priority;
 
 #[allow(unused)]
 #[macro_export]
-pub(crate) fn forget_lifetime_mut<'a, 'b, T>(reference: &'a mut T) -> &'b mut T {
-    unsafe{std::mem::transmute::<&'a mut T, &'b mut T>(reference)}
+pub(crate) fn forget_lifetime_mut<'a, 'b, T: ?Sized>(reference: &'a mut T) -> &'b mut T {
+    unsafe{&mut *(reference as *mut T)}
 }
 
 #[allow(unused)]
 #[macro_export]
-pub(crate) fn forget_lifetime<'a, 'b, T>(reference: &'a T) -> &'b T {
-    unsafe{std::mem::transmute::<&'a T, &'b T>(reference)}
+pub(crate) fn forget_lifetime<'a, 'b, T: ?Sized>(reference: &'a T) -> &'b T {
+    unsafe{&*(reference as *const T)}
+}
+
+/**
+ * Mut stores of a parallel iteration whose change logs are paused, see GenericComponentStore::pause_change_log.
+ * Keeps pointers instead of borrows, the batches borrow the stores until they finished.
+ */
+#[derive(Default)]
+pub struct PausedChangeLogs(Vec<*mut dyn GenericComponentStore>);
+
+// the stores of a parallel iteration are Send + Sync, like every component store
+unsafe impl Send for PausedChangeLogs {}
+
+impl PausedChangeLogs {
+    /**
+     * Prepares the store for writes from several threads, see GenericComponentStore::unshare_pages.
+     */
+    #[allow(unused)]
+    pub fn pause(&mut self, store: &mut (dyn GenericComponentStore + 'static)) {
+        store.unshare_pages();
+        store.pause_change_log(true);
+        self.0.push(store);
+    }
+
+    /**
+     * Safety: the stores must still be alive and no batch may access them anymore.
+     */
+    #[allow(unused)]
+    pub(crate) unsafe fn resume(self) {
+        for store in self.0 {
+            (*store).pause_change_log(false);
+        }
+    }
 }
 
 #[allow(unused)]
 #[macro_export]
 macro_rules! expand_iteration {
+    // a driver that is not the first store yields its component as pending, it is appended once the stores before it were looked up
+    (@pending $iter:expr; [mut $store:expr $(, $($before:tt)+)?]; [$($after:tt)*]) => {
+        eisen::expand_iteration!(@pending
+            $iter.filter_map(|(tup, pending)| {
+                let index = tup.0;
+                Some((tup.append(eisen::forget_lifetime_mut($store.get_mut(index)?)), pending))
+            });
+            [$($($before)+)?]; [$($after)*]
+        )
+    };
+
+    (@pending $iter:expr; [not $store:expr $(, $($before:tt)+)?]; [$($after:tt)*]) => {
+        eisen::expand_iteration!(@pending $iter.filter(|(tup, _)| !$store.has(tup.0)); [$($($before)+)?]; [$($after)*])
+    };
+
+    (@pending $iter:expr; [added $store:expr $(, $($before:tt)+)?]; [$($after:tt)*]) => {
+        eisen::expand_iteration!(@pending $iter.filter(|(tup, _)| $store.is_added(tup.0)); [$($($before)+)?]; [$($after)*])
+    };
+
+    (@pending $iter:expr; [changed $store:expr $(, $($before:tt)+)?]; [$($after:tt)*]) => {
+        eisen::expand_iteration!(@pending $iter.filter(|(tup, _)| $store.is_changed(tup.0)); [$($($before)+)?]; [$($after)*])
+    };
+
+    (@pending $iter:expr; [removed $store:expr $(, $($before:tt)+)?]; [$($after:tt)*]) => {
+        eisen::expand_iteration!(@pending $iter.filter(|(tup, _)| $store.was_removed(tup.0)); [$($($before)+)?]; [$($after)*])
+    };
+
+    (@pending $iter:expr; [$store:expr $(, $($before:tt)+)?]; [$($after:tt)*]) => {
+        eisen::expand_iteration!(@pending
+            $iter.filter_map(|(tup, pending)| {
+                let index = tup.0;
+                Some((tup.append($store.get(index)?), pending))
+            });
+            [$($($before)+)?]; [$($after)*]
+        )
+    };
+
+    (@pending $iter:expr; []; [$($after:tt)*]) => {
+        eisen::expand_iteration!($iter.map(|(tup, pending)| tup.append(pending)) $($after)*)
+    };
+
     ($iter:expr, mut $store:expr $(, $($rest:tt)+)?) => {
         eisen::expand_iteration!(
             $iter.filter_map(|tup| {
@@ -32,6 +108,18 @@ macro_rules! expand_iteration {
     ($iter:expr, not $store:expr $(, $($rest:tt)+)?) => {
         eisen::expand_iteration!($iter.filter(|tup| !$store.has(tup.0)) $(, $($rest)+)?)
     };
+
+    ($iter:expr, added $store:expr $(, $($rest:tt)+)?) => {
+        eisen::expand_iteration!($iter.filter(|tup| $store.is_added(tup.0)) $(, $($rest)+)?)
+    };
+
+    ($iter:expr, changed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::expand_iteration!($iter.filter(|tup| $store.is_changed(tup.0)) $(, $($rest)+)?)
+    };
+
+    ($iter:expr, removed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::expand_iteration!($iter.filter(|tup| $store.was_removed(tup.0)) $(, $($rest)+)?)
+    };
     
     ($iter:expr, $store:expr $(, $($rest:tt)+)?) => {
         eisen::expand_iteration!(
@@ -51,6 +139,12 @@ macro_rules! expand_iteration {
 #[allow(unused)]
 #[macro_export]
 macro_rules! erase_lifetime_check {
+    (, $($rest:tt)+) => {
+        eisen::erase_lifetime_check!($($rest)+)
+    };
+
+    () => {};
+
     (mut $first:ident $(, $($rest:tt)+)?) => {
         let $first = crate::entity::iteration::forget_lifetime_mut($first);
         $(eisen::erase_lifetime_check!($($rest)+))?
@@ -61,102 +155,1005 @@ macro_rules! erase_lifetime_check {
         $(eisen::erase_lifetime_check!($($rest)+))?
     };
 
+    (added $first:ident $(, $($rest:tt)+)?) => {
+        let $first = crate::entity::iteration::forget_lifetime(&*$first);
+        $(eisen::erase_lifetime_check!($($rest)+))?
+    };
+
+    (changed $first:ident $(, $($rest:tt)+)?) => {
+        let $first = crate::entity::iteration::forget_lifetime(&*$first);
+        $(eisen::erase_lifetime_check!($($rest)+))?
+    };
+
+    (removed $first:ident $(, $($rest:tt)+)?) => {
+        let $first = crate::entity::iteration::forget_lifetime(&*$first);
+        $(eisen::erase_lifetime_check!($($rest)+))?
+    };
+
     ($first:ident $(, $($rest:tt)+)?) => {
         let $first = crate::entity::iteration::forget_lifetime(&*$first);
         $(eisen::erase_lifetime_check!($($rest)+))?
     };
 }
 
+/**
+ * Position plan_driver! evaluates to, if the join is driven by the intersection of the sets of its TagStores.
+ */
+pub const TAG_INTERSECTION: usize = usize::MAX;
+
+/**
+ * Driver of a join found so far, the store with the fewest components, and the TagStores among the candidates.
+ */
+#[derive(Clone, Copy)]
+pub struct DriverPlan {
+    position: usize,
+    len: usize,
+    tags: usize,
+    smallest_tag: usize,
+}
+
+impl DriverPlan {
+    #[allow(unused)]
+    pub fn new(first: &dyn GenericComponentStore) -> Self {
+        Self{ position: 0, len: usize::MAX, tags: 0, smallest_tag: usize::MAX }.candidate(0, first)
+    }
+
+    /**
+     * Keeps the driver unless the store at position has fewer components.
+     */
+    #[allow(unused)]
+    pub fn candidate(mut self, position: usize, store: &dyn GenericComponentStore) -> Self {
+        let len = store.len();
+        if len < self.len {
+            self.position = position;
+            self.len = len;
+        }
+        if store.tag_bits().is_some() {
+            self.tags += 1;
+            self.smallest_tag = self.smallest_tag.min(len);
+        }
+        self
+    }
+
+    /**
+     * Position of the driving store, TAG_INTERSECTION if several TagStores are joined and one of them is the smallest store,
+     * their intersection is never larger than that store.
+     */
+    #[allow(unused)]
+    pub fn driver(&self) -> usize {
+        if self.tags > 1 && self.smallest_tag <= self.len {
+            TAG_INTERSECTION
+        } else {
+            self.position
+        }
+    }
+}
+
+/**
+ * Evaluates to the position of the store that drives a join in the list of stores,
+ * the plain or mut store with the fewest components, the earlier one on a tie, or TAG_INTERSECTION.
+ * Filter stores (not, added, changed, removed) never drive.
+ * plan_driver!(@intersection stores) iterates the intersection of the TagStores among the plain and mut stores in ascending order.
+ * syntax: ((mut)? first_store, (mut|not|added|changed|removed)? store_names...)
+ */
+#[allow(unused)]
+#[macro_export]
+macro_rules! plan_driver {
+    (@intersection $($stores:tt)+) => {
+        eisen::entity::BitSet::intersection(eisen::plan_driver!(@tags []; $($stores)+).into_iter().flatten())
+    };
+
+    (@tags [$($tags:expr),*]; mut $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@tags [$($tags,)* eisen::entity::GenericComponentStore::tag_bits(&*$store).map(eisen::forget_lifetime)]; $($($rest)+)?)
+    };
+    (@tags [$($tags:expr),*]; not $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@tags [$($tags),*]; $($($rest)+)?)
+    };
+    (@tags [$($tags:expr),*]; added $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@tags [$($tags),*]; $($($rest)+)?)
+    };
+    (@tags [$($tags:expr),*]; changed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@tags [$($tags),*]; $($($rest)+)?)
+    };
+    (@tags [$($tags:expr),*]; removed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@tags [$($tags),*]; $($($rest)+)?)
+    };
+    (@tags [$($tags:expr),*]; $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@tags [$($tags,)* eisen::entity::GenericComponentStore::tag_bits(&*$store).map(eisen::forget_lifetime)]; $($($rest)+)?)
+    };
+    (@tags [$($tags:expr),*];) => {
+        [$($tags),*]
+    };
+
+    (mut $first:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!($first $(, $($rest)+)?)
+    };
+
+    ($first:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@candidates eisen::entity::iteration::DriverPlan::new(&*$first); [1]; $($($rest)+)?)
+    };
+
+    (@candidates $plan:expr; [$($position:tt)+]; mut $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@candidates $plan.candidate($($position)+, &*$store); [$($position)+ + 1]; $($($rest)+)?)
+    };
+    (@candidates $plan:expr; [$($position:tt)+]; not $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@candidates $plan; [$($position)+ + 1]; $($($rest)+)?)
+    };
+    (@candidates $plan:expr; [$($position:tt)+]; added $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@candidates $plan; [$($position)+ + 1]; $($($rest)+)?)
+    };
+    (@candidates $plan:expr; [$($position:tt)+]; changed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@candidates $plan; [$($position)+ + 1]; $($($rest)+)?)
+    };
+    (@candidates $plan:expr; [$($position:tt)+]; removed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@candidates $plan; [$($position)+ + 1]; $($($rest)+)?)
+    };
+    (@candidates $plan:expr; [$($position:tt)+]; $store:expr $(, $($rest:tt)+)?) => {
+        eisen::plan_driver!(@candidates $plan.candidate($($position)+, &*$store); [$($position)+ + 1]; $($($rest)+)?)
+    };
+    (@candidates $plan:expr; [$($position:tt)+];) => {
+        $plan.driver()
+    };
+}
+
+/**
+ * Iterator of a planned join, Driven walks the store that drives and looks the others up,
+ * First holds the joins driven by the stores behind it, down to the first store.
+ * Nesting one enum per candidate store instead of boxing the iterator keeps every join statically dispatched.
+ */
+pub enum PlannedIter<A, B> {
+    First(A),
+    Driven(B),
+}
+
+impl<T, A: Iterator<Item = T>, B: Iterator<Item = T>> Iterator for PlannedIter<A, B> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            PlannedIter::First(iter) => iter.next(),
+            PlannedIter::Driven(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            PlannedIter::First(iter) => iter.size_hint(),
+            PlannedIter::Driven(iter) => iter.size_hint(),
+        }
+    }
+}
+
+/**
+ * Columns of the archetypes shared by the plain and mut stores of a join, if all of them are TableStores that can be walked in lock-step.
+ * Positions count the rows of the joined columns one after another, stores are numbered in the order they were passed.
+ */
+pub struct TableJoin {
+    offsets: Vec<usize>,
+    rows: Vec<Vec<std::ops::Range<usize>>>,
+}
+
+impl TableJoin {
+    /**
+     * None, if fewer than two stores are joined, one of them is not a TableStore
+     * or the stores replayed different archetype moves (see join_archetypes).
+     */
+    #[allow(unused)]
+    pub fn new(stores: &[&dyn GenericComponentStore]) -> Option<Self> {
+        if stores.len() < 2 {
+            return None;
+        }
+        let layouts = stores.iter().map(|store| store.table_layout()).collect::<Option<Vec<_>>>()?;
+        let archetypes = join_archetypes(&layouts)?;
+        let rows = layouts.iter()
+            .map(|layout| archetypes.iter().map(|archetype| layout.column_rows(*archetype)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let offsets = std::iter::once(0)
+            .chain(rows[0].iter().scan(0, |offset, column| {
+                *offset += column.len();
+                Some(*offset)
+            }))
+            .collect();
+        Some(Self{ offsets, rows })
+    }
+
+    #[allow(unused)]
+    pub fn position_count(&self) -> usize {
+        *self.offsets.last().unwrap()
+    }
+
+    /**
+     * The rows of the joined columns in the store, one range per column.
+     */
+    #[allow(unused)]
+    pub fn rows(&self, store: usize) -> &[std::ops::Range<usize>] {
+        &self.rows[store]
+    }
+
+    /**
+     * The rows of the store that hold the positions, split at the column boundaries.
+     */
+    #[allow(unused)]
+    pub fn ranges(&self, store: usize, positions: std::ops::Range<usize>) -> impl Iterator<Item = std::ops::Range<usize>> + '_ {
+        let column = self.offsets.partition_point(|offset| *offset <= positions.start).saturating_sub(1);
+        self.rows[store][column..].iter()
+            .zip(&self.offsets[column..])
+            .take_while(move |(_, start)| **start < positions.end)
+            .map(move |(rows, start)| {
+                let first = positions.start.max(*start) - start;
+                let end = positions.end.min(start + rows.len()) - start;
+                rows.start + first..rows.start + end
+            })
+            .filter(|rows| !rows.is_empty())
+    }
+}
+
+/**
+ * Chains the iterators over several ranges of a store, see iter_entity_range.
+ */
+#[allow(unused)]
+pub fn iter_ranges<'a, S: ?Sized, I: Iterator + 'a>(store: &'a S, ranges: impl IntoIterator<Item = std::ops::Range<usize>> + 'a, iter: impl Fn(&'a S, std::ops::Range<usize>) -> I + 'a) -> impl Iterator<Item = I::Item> + 'a {
+    ranges.into_iter().flat_map(move |range| iter(store, range))
+}
+
+/**
+ * Like iter_ranges for iter_entity_range_mut.
+ * Safety: the ranges must not overlap, every component may only be yielded once.
+ */
+#[allow(unused)]
+pub(crate) unsafe fn iter_ranges_mut<'a, S: ?Sized, I: Iterator + 'a>(store: &'a mut S, ranges: impl IntoIterator<Item = std::ops::Range<usize>> + 'a, iter: impl Fn(&'a mut S, std::ops::Range<usize>) -> I + 'a) -> impl Iterator<Item = I::Item> + 'a {
+    let store = store as *mut S;
+    ranges.into_iter().flat_map(move |range| iter(unsafe{ &mut *store }, range))
+}
+
+/**
+ * A batch that runs longer than this hands half of its remaining entities to a new task,
+ * the runtime expects closures to finish within about 200 microseconds.
+ */
+pub const SPLIT_BUDGET: std::time::Duration = std::time::Duration::from_micros(200);
+
+const SPLIT_CHECK_INTERVAL: usize = 16;
+
+const MIN_SPLIT_LEN: usize = 2 * SPLIT_CHECK_INTERVAL;
+
+const BATCHES_PER_WORKER: usize = 4;
+
+const MIN_BATCH_SIZE: usize = 64;
+
+/**
+ * Batch size that gives every worker a few batches to balance the load, but no batches smaller than MIN_BATCH_SIZE.
+ */
+#[allow(unused)]
+pub fn auto_batch_size(len: usize, worker_count: usize) -> usize {
+    let batches = worker_count.max(1) * BATCHES_PER_WORKER;
+    len.div_ceil(batches).max(MIN_BATCH_SIZE)
+}
+
+/**
+ * Splits sorted entity indices into ranges of entity indices that hold at least batch_size of them
+ * and only end at LinearStore page boundaries, so no two batches write to the same page.
+ */
+#[allow(unused)]
+pub fn page_aligned_batches(indices: &[EntityIndex], batch_size: usize) -> Vec<std::ops::Range<usize>> {
+    let page_of = |index: EntityIndex| index as usize / LINEAR_STORE_PAGE_SIZE;
+    let mut batches = Vec::new();
+    let mut start = 0;
+    for end in 1..=indices.len() {
+        if end == indices.len() || (end - start >= batch_size && page_of(indices[end]) != page_of(indices[end - 1])) {
+            batches.push(indices[start] as usize..indices[end - 1] as usize + 1);
+            start = end;
+        }
+    }
+    batches
+}
+
+/**
+ * Returns the sorted indices of the driving store, if its batches are not page aligned and the iteration writes other stores.
+ * Driving the batches with these indices keeps them page aligned, so no two batches write the same page of another store.
+ */
+#[allow(unused)]
+pub fn align_driver(driver: &dyn GenericComponentStore, writes_other_stores: bool) -> Option<Vec<EntityIndex>> {
+    if !writes_other_stores || driver.page_aligned_batches() {
+        return None;
+    }
+    let mut indices = driver.iter_indices().collect::<Vec<_>>();
+    indices.sort_unstable();
+    Some(indices)
+}
+
+/**
+ * Batches and splits of a store's positions end at multiples of this, whole pages for page aligned stores.
+ */
+#[allow(unused)]
+pub fn batch_alignment(store: &dyn GenericComponentStore) -> usize {
+    if store.page_aligned_batches() {
+        LINEAR_STORE_PAGE_SIZE
+    } else {
+        1
+    }
+}
+
+/**
+ * Ranges of at least batch_size positions of the store, see GenericComponentStore::position_count.
+ */
+#[allow(unused)]
+pub fn batch_ranges(store: &dyn GenericComponentStore, batch_size: usize) -> impl Iterator<Item = std::ops::Range<usize>> {
+    position_batches(store.position_count(), batch_size, batch_alignment(store))
+}
+
+/**
+ * Ranges of batch_size positions, rounded up to a multiple of alignment.
+ */
+#[allow(unused)]
+pub fn position_batches(positions: usize, batch_size: usize, alignment: usize) -> impl Iterator<Item = std::ops::Range<usize>> {
+    let step = batch_size.max(1).next_multiple_of(alignment);
+    (0..positions).step_by(step).map(move |start| start..(start + step).min(positions))
+}
+
+/**
+ * Mut store of a parallel iteration, shared by the batches of one WorkSplitter.
+ * The batches write disjoint positions, so they may hold mutable references to the store at the same time.
+ */
+pub struct SharedStore<S: ?Sized>(*mut S);
+
+impl<S: ?Sized> Clone for SharedStore<S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: ?Sized> Copy for SharedStore<S> {}
+
+// component stores are Send + Sync, the batches never write the same component
+unsafe impl<S: ?Sized + Send + Sync> Send for SharedStore<S> {}
+unsafe impl<S: ?Sized + Send + Sync> Sync for SharedStore<S> {}
+
+impl<S: ?Sized> SharedStore<S> {
+    #[allow(unused)]
+    pub fn new(store: &mut S) -> Self {
+        Self(store)
+    }
+
+    /**
+     * Safety: the store must outlive the batches and no two batches may access the same component.
+     */
+    #[allow(unused)]
+    pub(crate) unsafe fn get<'a>(&self) -> &'a mut S {
+        &mut *self.0
+    }
+}
+
+/**
+ * The work of a parallel iteration, it is shared by every task of a WorkSplitter
+ * and runs the iteration over the position ranges it is given.
+ */
+pub type SplitWork = std::sync::Arc<dyn Fn(&mut SplitRanges) + Send + Sync>;
+
+/**
+ * Positions of one task of a WorkSplitter, handed out in small ranges.
+ * Once the task ran longer than SPLIT_BUDGET, the second half of the positions left goes to a new task.
+ */
+pub struct SplitRanges<'a> {
+    splitter: &'a WorkSplitter,
+    work: &'a SplitWork,
+    positions: std::ops::Range<usize>,
+    alignment: usize,
+    start: std::time::Instant,
+}
+
+impl SplitRanges<'_> {
+    fn split(&mut self) {
+        if self.positions.len() < MIN_SPLIT_LEN {
+            return;
+        }
+        let middle = (self.positions.start + self.positions.len() / 2).next_multiple_of(self.alignment);
+        if middle < self.positions.end {
+            self.splitter.spawn(middle..self.positions.end, self.alignment, self.work.clone());
+            self.positions.end = middle;
+        }
+    }
+}
+
+impl Iterator for SplitRanges<'_> {
+    type Item = std::ops::Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.positions.is_empty() {
+            return None;
+        }
+        if self.start.elapsed() > SPLIT_BUDGET {
+            self.split();
+            self.start = std::time::Instant::now();
+        }
+        let end = (self.positions.start + SPLIT_CHECK_INTERVAL).min(self.positions.end);
+        let range = self.positions.start..end;
+        self.positions.start = end;
+        Some(range)
+    }
+}
+
+/**
+ * Runs the batches of parallel_over_entities on a runtime.
+ * Every task keeps the waiter of the iteration pending, a task that exceeds SPLIT_BUDGET splits its remaining positions
+ * and hands one half to a new task, recursively. All tasks share the same work.
+ */
+#[derive(Clone)]
+pub struct WorkSplitter {
+    runtime: RuntimeHandle,
+    priority: Priority,
+    _dependency: AtomicDependency,
+}
+
+impl WorkSplitter {
+    #[allow(unused)]
+    pub fn new(runtime: &Runtime, priority: Priority, dependency: AtomicDependency) -> Self {
+        Self{ runtime: runtime.handle(), priority, _dependency: dependency }
+    }
+
+    #[allow(unused)]
+    pub fn worker_count(&self) -> usize {
+        self.runtime.worker_count()
+    }
+
+    /**
+     * Runs the function on the runtime, it gets its own splitter to run work with.
+     */
+    #[allow(unused)]
+    pub fn exec(&self, f: impl FnOnce(WorkSplitter) + Send + 'static) {
+        let splitter = self.clone();
+        self.runtime.exec_prioritised(move || f(splitter), self.priority);
+    }
+
+    /**
+     * Runs the work over the positions in a new task.
+     * Splits only end at multiples of alignment.
+     */
+    #[allow(unused)]
+    pub fn spawn(&self, positions: std::ops::Range<usize>, alignment: usize, work: SplitWork) {
+        self.exec(move |splitter| splitter.run(positions, alignment, &work));
+    }
+
+    /**
+     * Runs the work over the positions in the current task, the work is shared with the tasks it splits off.
+     */
+    #[allow(unused)]
+    pub fn run(&self, positions: std::ops::Range<usize>, alignment: usize, work: &SplitWork) {
+        work(&mut SplitRanges{ splitter: self, work, positions, alignment: alignment.max(1), start: std::time::Instant::now() });
+    }
+}
+
+/**
+ * Runs the closure over the entities that have all plain and mut stores in parallel batches.
+ * The smallest of these stores drives the join, the intersection of their TagStores if one of several tags is the smallest,
+ * the components are passed in the order of the stores.
+ * Without batch_size the batch size is chosen by auto_batch_size, priority defaults to Normal.
+ * Like iterate_over_entities, TableStores that replayed the same archetype moves are zipped over their joined columns.
+ * The closure is created once and shared by all batches through an Arc, so it has to be Fn + Send + Sync,
+ * a batch that runs too long hands a range of its remaining positions to a new task (see WorkSplitter).
+ * Pages of mut stores that are shared with saved rollback frames are copied before the batches start,
+ * their change logs count the iteration as a write to every component.
+ * syntax: (note: "profiling note"; runtime: runtime; priority: priority; batch_size: size; closure: closure; entities: entity_manager; commands: commands; stores: (mut|not|added|changed|removed)? store_names...)
+ */
 #[allow(unused)]
 #[macro_export]
 macro_rules! parallel_over_entities {
-    ($(note: $note:literal;)? runtime: $runtime:expr; batch_size: $batch_size:expr; closure: $closure:expr; entities: $entities:ident; stores: $first_store:ident $(,$($rest:tt)+)?) => {
+    ($(note: $note:literal;)? runtime: $runtime:expr; $(priority: $priority:expr;)? $(batch_size: $batch_size:expr;)? closure: $closure:expr; entities: $entities:ident; $(commands: $commands:ident;)? stores: $first_store:ident $(,$($rest:tt)+)?) => {
         async { 
             let waiter = crate::sync::AtomicWaiter::new();
+            let splitter = eisen::entity::iteration::WorkSplitter::new(&$runtime, None$(.or(Some($priority)))?.unwrap_or(crate::sync::task::Priority::Normal), waiter.make_dependency());
+            // the batches may outlive the returned waiter, the change logs resume on the next add, set, removal or change tick
+            #[allow(unused_mut)]
+            let mut paused = eisen::entity::iteration::PausedChangeLogs::default();
+            $(eisen::iterate_over_entities!(@sync $($rest)+);)?
+            eisen::parallel_over_entities!(@unshare paused; $($($rest)+)?);
             eisen::erase_lifetime_check!($first_store);
-
-            $first_store.iter_entity_batch($batch_size)
-            .for_each(|batch_iter|{
-                $(eisen::erase_lifetime_check!($($rest)+);)?
-                eisen::erase_lifetime_check!($entities);
-                let dep = waiter.make_dependency();
-                let func = || { 
-                    profiling::scope!("parallel_over_entities" $(,$note)?);
-                    let _d = dep;
-                    eisen::expand_iteration!(batch_iter $(,$($rest)+)?)
-                        .map(|tup| {
-                            let index = tup.0;
-                            tup.replace_first(EntityHandle{index: index, version: $entities.version_of(index).unwrap()})
-                        })
-                        .for_each($closure);
-                };
-    
-                $runtime.exec(func);
-            });
+            eisen::parallel_over_entities!(@plan [splitter; [$($note)?]; $closure; $entities; [$($commands)?]]; [$($batch_size)?]; $first_store $(, $($rest)+)?);
+            drop(splitter);
 
             waiter
         }
     };
 
-    ($(note: $note:literal;)? runtime: $runtime:expr; batch_size: $batch_size:expr; closure: $closure:expr; entities: $entities:ident; stores: mut $first_store:ident $(,$($rest:tt)+)?) => {
+    ($(note: $note:literal;)? runtime: $runtime:expr; $(priority: $priority:expr;)? $(batch_size: $batch_size:expr;)? closure: $closure:expr; entities: $entities:ident; $(commands: $commands:ident;)? stores: mut $first_store:ident $(,$($rest:tt)+)?) => {
         async {
             let waiter = crate::sync::AtomicWaiter::new();
+            let splitter = eisen::entity::iteration::WorkSplitter::new(&$runtime, None$(.or(Some($priority)))?.unwrap_or(crate::sync::task::Priority::Normal), waiter.make_dependency());
+            let mut paused = eisen::entity::iteration::PausedChangeLogs::default();
+            eisen::iterate_over_entities!(@sync mut $first_store $(, $($rest)+)?);
+            eisen::parallel_over_entities!(@unshare paused; mut $first_store $(, $($rest)+)?);
             eisen::erase_lifetime_check!(mut $first_store);
+            eisen::parallel_over_entities!(@plan [splitter; [$($note)?]; $closure; $entities; [$($commands)?]]; [$($batch_size)?]; mut $first_store $(, $($rest)+)?);
+            drop(splitter);
+
+            waiter.await;
+            unsafe{ paused.resume() };
+        }
+    };
+
+    (@plan [$splitter:ident; [$($note:literal)?]; $closure:expr; $entities:ident; [$($commands:ident)?]]; [$($batch_size:expr)?]; $($stores:tt)+) => {
+        eisen::erase_lifetime_check!($entities);
+        $(eisen::erase_lifetime_check!($commands);)?
+        let closure = std::sync::Arc::new($closure);
+        let join = eisen::entity::iteration::TableJoin::new(&eisen::iterate_over_entities!(@joined []; $($stores)+));
+        if let Some(join) = join {
+            // the positions are the rows of the joined columns, every store writes only its own rows
+            let batch_size = None$(.or(Some($batch_size)))?.unwrap_or_else(|| eisen::entity::iteration::auto_batch_size(join.position_count(), $splitter.worker_count()));
+            eisen::parallel_over_entities!(@work [$splitter; [$($note)?]; closure; $entities]; [$($stores)+];
+                eisen::entity::iteration::position_batches(join.position_count(), batch_size, 1); 1;
+                range => {
+                    let ranges = |store: usize| join.ranges(store, range.clone());
+                    eisen::iterate_over_entities!(@table ranges; $($stores)+)
+                }
+            );
+        } else {
+            let driver = eisen::plan_driver!($($stores)+);
+            let (driver_store, driver_writes) = eisen::parallel_over_entities!(@driver driver; [$($stores)+]);
+            let writes_other_stores = eisen::parallel_over_entities!(@writes $($stores)+) > driver_writes as usize;
+            let sorted = if driver == eisen::entity::iteration::TAG_INTERSECTION {
+                Some(eisen::plan_driver!(@intersection $($stores)+).collect::<Vec<_>>())
+            } else {
+                eisen::entity::iteration::align_driver(driver_store, writes_other_stores)
+            };
+            let len = sorted.as_ref().map_or(eisen::entity::GenericComponentStore::len(driver_store), |indices| indices.len());
+            let batch_size = None$(.or(Some($batch_size)))?.unwrap_or_else(|| eisen::entity::iteration::auto_batch_size(len, $splitter.worker_count()));
+            match sorted {
+                // the positions are entity indices, a range runs the sorted indices that fall into it
+                Some(indices) => {
+                    eisen::parallel_over_entities!(@work [$splitter; [$($note)?]; closure; $entities]; [$($stores)+];
+                        eisen::entity::iteration::page_aligned_batches(&indices, batch_size); eisen::entity::LINEAR_STORE_PAGE_SIZE;
+                        range => {
+                            let start = indices.partition_point(|index| (*index as usize) < range.start);
+                            let end = indices.partition_point(|index| (*index as usize) < range.end);
+                            eisen::expand_iteration!(indices[start..end].iter().map(|index| (*index,)), $($stores)+)
+                        }
+                    );
+                },
+                None => eisen::parallel_over_entities!(@branches [$splitter; [$($note)?]; closure; $entities]; batch_size; driver; [1]; $($stores)+),
+            }
+        }
+    };
 
-            $first_store.iter_entity_mut_batch($batch_size)
-                .for_each(|batch_iter|{
-                    $(eisen::erase_lifetime_check!($($rest)+);)?
-                    eisen::erase_lifetime_check!($entities);
-                    let dep = waiter.make_dependency();
-                    let func = move || { 
-                        profiling::scope!("parallel_over_entities" $(,$note)?);
-                        let _d = dep;
-                        eisen::expand_iteration!(batch_iter $(,$($rest)+)?)
-                            .map(|tup| {
-                                let index = tup.0;
-                                tup.replace_first(EntityHandle{index: index, version: $entities.version_of(index).unwrap()})
-                            })
-                            .for_each($closure);
-                    };
-        
-                    $runtime.exec(func);
-                });
+    (@driver $driver:ident; [mut $first_store:ident $(, $($rest:tt)+)?]) => {
+        eisen::parallel_over_entities!(@driver $driver; [1]; [mut $first_store $(, $($rest)+)?]; $($($rest)+)?)
+    };
+    (@driver $driver:ident; [$first_store:ident $(, $($rest:tt)+)?]) => {
+        eisen::parallel_over_entities!(@driver $driver; [1]; [$first_store $(, $($rest)+)?]; $($($rest)+)?)
+    };
+    (@driver $driver:ident; [$($position:tt)+]; [$($stores:tt)+]; mut $store:ident $(, $($rest:tt)+)?) => {
+        if $driver == $($position)+ {
+            (&*$store as &dyn eisen::entity::GenericComponentStore, true)
+        } else {
+            eisen::parallel_over_entities!(@driver $driver; [$($position)+ + 1]; [$($stores)+]; $($($rest)+)?)
+        }
+    };
+    (@driver $driver:ident; [$($position:tt)+]; [$($stores:tt)+]; $filter:ident $store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@driver $driver; [$($position)+ + 1]; [$($stores)+]; $($($rest)+)?)
+    };
+    (@driver $driver:ident; [$($position:tt)+]; [$($stores:tt)+]; $store:ident $(, $($rest:tt)+)?) => {
+        if $driver == $($position)+ {
+            (&*$store as &dyn eisen::entity::GenericComponentStore, false)
+        } else {
+            eisen::parallel_over_entities!(@driver $driver; [$($position)+ + 1]; [$($stores)+]; $($($rest)+)?)
+        }
+    };
+    (@driver $driver:ident; [$($position:tt)+]; [mut $first_store:ident $(, $($others:tt)+)?];) => {
+        (&*$first_store as &dyn eisen::entity::GenericComponentStore, true)
+    };
+    (@driver $driver:ident; [$($position:tt)+]; [$first_store:ident $(, $($others:tt)+)?];) => {
+        (&*$first_store as &dyn eisen::entity::GenericComponentStore, false)
+    };
 
-            waiter.await
+    (@branches $spawn:tt; $batch_size:ident; $driver:ident; [$($position:tt)+]; mut $first_store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@branches $spawn; $batch_size; $driver; [$($position)+]; [mut $first_store]; $($($rest)+)?)
+    };
+    (@branches $spawn:tt; $batch_size:ident; $driver:ident; [$($position:tt)+]; $first_store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@branches $spawn; $batch_size; $driver; [$($position)+]; [$first_store]; $($($rest)+)?)
+    };
+    (@branches $spawn:tt; $batch_size:ident; $driver:ident; [$($position:tt)+]; [$($before:tt)+]; mut $store:ident $(, $($rest:tt)+)?) => {
+        if $driver == $($position)+ {
+            eisen::parallel_over_entities!(@work $spawn; [$($before)+, mut $store $(, $($rest)+)?];
+                eisen::entity::iteration::batch_ranges(&*$store, $batch_size); eisen::entity::iteration::batch_alignment(&*$store);
+                range => eisen::expand_iteration!(@pending $store.iter_entity_range_mut(range).map(|(index, value)| ((index,), value)); [$($before)+]; [$(, $($rest)+)?])
+            );
+        } else {
+            eisen::parallel_over_entities!(@branches $spawn; $batch_size; $driver; [$($position)+ + 1]; [$($before)+, mut $store]; $($($rest)+)?);
         }
     };
+    (@branches $spawn:tt; $batch_size:ident; $driver:ident; [$($position:tt)+]; [$($before:tt)+]; $filter:ident $store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@branches $spawn; $batch_size; $driver; [$($position)+ + 1]; [$($before)+, $filter $store]; $($($rest)+)?)
+    };
+    (@branches $spawn:tt; $batch_size:ident; $driver:ident; [$($position:tt)+]; [$($before:tt)+]; $store:ident $(, $($rest:tt)+)?) => {
+        if $driver == $($position)+ {
+            eisen::parallel_over_entities!(@work $spawn; [$($before)+, $store $(, $($rest)+)?];
+                eisen::entity::iteration::batch_ranges(&*$store, $batch_size); eisen::entity::iteration::batch_alignment(&*$store);
+                range => eisen::expand_iteration!(@pending $store.iter_entity_range(range).map(|(index, value)| ((index,), value)); [$($before)+]; [$(, $($rest)+)?])
+            );
+        } else {
+            eisen::parallel_over_entities!(@branches $spawn; $batch_size; $driver; [$($position)+ + 1]; [$($before)+, $store]; $($($rest)+)?);
+        }
+    };
+    (@branches $spawn:tt; $batch_size:ident; $driver:ident; [$($position:tt)+]; [mut $first_store:ident $(, $($others:tt)+)?];) => {
+        eisen::parallel_over_entities!(@work $spawn; [mut $first_store $(, $($others)+)?];
+            eisen::entity::iteration::batch_ranges(&*$first_store, $batch_size); eisen::entity::iteration::batch_alignment(&*$first_store);
+            range => eisen::expand_iteration!($first_store.iter_entity_range_mut(range) $(, $($others)+)?)
+        )
+    };
+    (@branches $spawn:tt; $batch_size:ident; $driver:ident; [$($position:tt)+]; [$first_store:ident $(, $($others:tt)+)?];) => {
+        eisen::parallel_over_entities!(@work $spawn; [$first_store $(, $($others)+)?];
+            eisen::entity::iteration::batch_ranges(&*$first_store, $batch_size); eisen::entity::iteration::batch_alignment(&*$first_store);
+            range => eisen::expand_iteration!($first_store.iter_entity_range(range) $(, $($others)+)?)
+        )
+    };
+
+    (@unshare $paused:ident; mut $store:ident $(, $($rest:tt)+)?) => {
+        $paused.pause(&mut *$store);
+        eisen::parallel_over_entities!(@unshare $paused; $($($rest)+)?);
+    };
+    (@unshare $paused:ident; $filter:ident $store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@unshare $paused; $($($rest)+)?);
+    };
+    (@unshare $paused:ident; $store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@unshare $paused; $($($rest)+)?);
+    };
+    (@unshare $paused:ident;) => {};
+
+    (@writes mut $store:ident $(, $($rest:tt)+)?) => {
+        1 + eisen::parallel_over_entities!(@writes $($($rest)+)?)
+    };
+    (@writes $filter:ident $store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@writes $($($rest)+)?)
+    };
+    (@writes $store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@writes $($($rest)+)?)
+    };
+    (@writes) => {
+        0
+    };
+
+    (@work [$splitter:ident; [$($note:literal)?]; $closure:ident; $entities:ident]; [$($stores:tt)+]; $batches:expr; $alignment:expr; $range:ident => $iter:expr) => {
+        {
+            let batches = $batches;
+            let alignment = $alignment;
+            eisen::parallel_over_entities!(@share $($stores)+);
+            let work: eisen::entity::iteration::SplitWork = std::sync::Arc::new(move |ranges: &mut eisen::entity::iteration::SplitRanges| {
+                profiling::scope!("parallel_over_entities" $(,$note)?);
+                eisen::parallel_over_entities!(@access $($stores)+);
+                for $range in ranges {
+                    $iter
+                        .map(|tup| {
+                            let index = tup.0;
+                            tup.replace_first(EntityHandle{index, version: $entities.version_of(index).unwrap()})
+                        })
+                        .for_each(&*$closure);
+                }
+            });
+            for batch in batches {
+                $splitter.spawn(batch, alignment, work.clone());
+            }
+        }
+    };
+
+    (@share mut $store:ident $(, $($rest:tt)+)?) => {
+        let $store = eisen::entity::iteration::SharedStore::new(&mut *$store);
+        eisen::parallel_over_entities!(@share $($($rest)+)?);
+    };
+    (@share $filter:ident $store:ident $(, $($rest:tt)+)?) => {
+        eisen::erase_lifetime_check!($filter $store);
+        eisen::parallel_over_entities!(@share $($($rest)+)?);
+    };
+    (@share $store:ident $(, $($rest:tt)+)?) => {
+        eisen::erase_lifetime_check!($store);
+        eisen::parallel_over_entities!(@share $($($rest)+)?);
+    };
+    (@share) => {};
+
+    (@access mut $store:ident $(, $($rest:tt)+)?) => {
+        let $store = unsafe{ $store.get() };
+        eisen::parallel_over_entities!(@access $($($rest)+)?);
+    };
+    (@access $filter:ident $store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@access $($($rest)+)?);
+    };
+    (@access $store:ident $(, $($rest:tt)+)?) => {
+        eisen::parallel_over_entities!(@access $($($rest)+)?);
+    };
+    (@access) => {};
 }
 
+/**
+ * Iterates the entities that have all plain and mut stores, the smallest of these stores drives the join,
+ * the intersection of their TagStores if one of several tags is the smallest.
+ * If all of them are TableStores that replayed the same archetype moves, their joined columns are walked in lock-step instead,
+ * mut stores are synced first (see TableJoin).
+ * The components are returned in the order of the stores.
+ * syntax: (entities: entity_manager; stores: (mut|not|added|changed|removed)? store_names...)
+ */
 #[allow(unused)]
 #[macro_export]
 macro_rules! iterate_over_entities {
-    (entities: $entities:expr; stores: mut $first_store:expr $(,$($rest:tt)+)?) => { 
-        eisen::expand_iteration!($first_store.iter_entity_mut() $(, $($rest)+)?)
-            .map(|tup|{
+    (entities: $entities:expr; stores: $($stores:tt)+) => {
+        eisen::iterate_over_entities!(@plan $($stores)+)
+            .map(|tup| {
                 let index = tup.0;
-                tup.replace_first(eisen::entity::EntityHandle{index: index, version: $entities.version_of(index).unwrap()})
+                tup.replace_first(eisen::entity::EntityHandle{index, version: $entities.version_of(index).unwrap()})
             })
     };
 
-    (entities: $entities:expr; stores: $first_store:expr $(,$($rest:tt)+)?) => {
-        eisen::expand_iteration!($first_store.iter_entity() $(, $($rest)+)?)
+    (stores: $($stores:tt)+) => {
+        eisen::iterate_over_entities!(@plan $($stores)+)
             .map(|tup| {
-                let index = tup.0;
-                tup.replace_first(eisen::entity::EntityHandle{index: index, version: $entities.version_of(index).unwrap()})
+                tup.pop_front()
             })
     };
-    
-    (stores: mut $first_store:expr $(,$($rest:tt)+)?) => { 
-        eisen::expand_iteration!($first_store.iter_entity_mut() $(, $($rest)+)?)
+
+    (@plan mut $first_store:expr) => {
+        $first_store.iter_entity_mut()
+    };
+
+    (@plan $first_store:expr) => {
+        $first_store.iter_entity()
+    };
+
+    (@plan mut $first_store:expr, $($rest:tt)+) => {{
+        eisen::iterate_over_entities!(@sync mut $first_store, $($rest)+);
+        let join = eisen::entity::iteration::TableJoin::new(&eisen::iterate_over_entities!(@joined []; mut $first_store, $($rest)+));
+        let driver = eisen::plan_driver!($first_store, $($rest)+);
+        if let Some(join) = join {
+            let ranges = |store: usize| join.rows(store).to_vec();
+            eisen::entity::iteration::PlannedIter::Driven(eisen::entity::iteration::PlannedIter::First(eisen::iterate_over_entities!(@table ranges; mut $first_store, $($rest)+)))
+        } else if driver == eisen::entity::iteration::TAG_INTERSECTION {
+            eisen::entity::iteration::PlannedIter::Driven(eisen::entity::iteration::PlannedIter::Driven(eisen::expand_iteration!(
+                eisen::plan_driver!(@intersection mut $first_store, $($rest)+).map(|index| (index,)), mut $first_store, $($rest)+
+            )))
+        } else {
+            eisen::entity::iteration::PlannedIter::First(eisen::iterate_over_entities!(@branches driver; [1]; [mut $first_store]; $($rest)+))
+        }
+    }};
+
+    (@plan $first_store:expr, $($rest:tt)+) => {{
+        eisen::iterate_over_entities!(@sync $first_store, $($rest)+);
+        let join = eisen::entity::iteration::TableJoin::new(&eisen::iterate_over_entities!(@joined []; $first_store, $($rest)+));
+        let driver = eisen::plan_driver!($first_store, $($rest)+);
+        if let Some(join) = join {
+            let ranges = |store: usize| join.rows(store).to_vec();
+            eisen::entity::iteration::PlannedIter::Driven(eisen::entity::iteration::PlannedIter::First(eisen::iterate_over_entities!(@table ranges; $first_store, $($rest)+)))
+        } else if driver == eisen::entity::iteration::TAG_INTERSECTION {
+            eisen::entity::iteration::PlannedIter::Driven(eisen::entity::iteration::PlannedIter::Driven(eisen::expand_iteration!(
+                eisen::plan_driver!(@intersection $first_store, $($rest)+).map(|index| (index,)), $first_store, $($rest)+
+            )))
+        } else {
+            eisen::entity::iteration::PlannedIter::First(eisen::iterate_over_entities!(@branches driver; [1]; [$first_store]; $($rest)+))
+        }
+    }};
+
+    // all plain and mut stores are TableStores, their joined columns are zipped in lock-step
+    (@table $ranges:ident; mut $first_store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@zip $ranges; [1];
+            unsafe{ eisen::entity::iteration::iter_ranges_mut(&mut *$first_store, $ranges(0), |store, rows| store.iter_entity_range_mut(rows)) }
+                .map(|(index, value)| (index,).append(value))
+            $(; $($rest)+)?)
+    };
+    (@table $ranges:ident; $first_store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@zip $ranges; [1];
+            eisen::entity::iteration::iter_ranges(&*$first_store, $ranges(0), |store, rows| store.iter_entity_range(rows))
+                .map(|(index, value)| (index,).append(value))
+            $(; $($rest)+)?)
+    };
+
+    (@zip $ranges:ident; [$($position:tt)+]; $iter:expr; mut $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@zip $ranges; [$($position)+ + 1];
+            $iter.zip(unsafe{ eisen::entity::iteration::iter_ranges_mut(&mut *$store, $ranges($($position)+), |store, rows| store.iter_entity_range_mut(rows)) })
+                .map(|(tup, (_, value))| tup.append(value))
+            $(; $($rest)+)?)
+    };
+    (@zip $ranges:ident; [$($position:tt)+]; $iter:expr; not $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@zip $ranges; [$($position)+]; { let store = &*$store; $iter.filter(move |tup| !store.has(tup.0)) } $(; $($rest)+)?)
+    };
+    (@zip $ranges:ident; [$($position:tt)+]; $iter:expr; added $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@zip $ranges; [$($position)+]; { let store = &*$store; $iter.filter(move |tup| store.is_added(tup.0)) } $(; $($rest)+)?)
+    };
+    (@zip $ranges:ident; [$($position:tt)+]; $iter:expr; changed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@zip $ranges; [$($position)+]; { let store = &*$store; $iter.filter(move |tup| store.is_changed(tup.0)) } $(; $($rest)+)?)
+    };
+    (@zip $ranges:ident; [$($position:tt)+]; $iter:expr; removed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@zip $ranges; [$($position)+]; { let store = &*$store; $iter.filter(move |tup| store.was_removed(tup.0)) } $(; $($rest)+)?)
+    };
+    (@zip $ranges:ident; [$($position:tt)+]; $iter:expr; $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@zip $ranges; [$($position)+ + 1];
+            $iter.zip(eisen::entity::iteration::iter_ranges(&*$store, $ranges($($position)+), |store, rows| store.iter_entity_range(rows)))
+                .map(|(tup, (_, value))| tup.append(value))
+            $(; $($rest)+)?)
+    };
+    (@zip $ranges:ident; [$($position:tt)+]; $iter:expr) => {
+        $iter
+    };
+
+    (@sync mut $store:expr $(, $($rest:tt)+)?) => {
+        eisen::entity::GenericComponentStore::sync_layout(&mut *$store);
+        $(eisen::iterate_over_entities!(@sync $($rest)+);)?
+    };
+    (@sync not $store:expr $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_entities!(@sync $($rest)+);)?
+    };
+    (@sync added $store:expr $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_entities!(@sync $($rest)+);)?
+    };
+    (@sync changed $store:expr $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_entities!(@sync $($rest)+);)?
+    };
+    (@sync removed $store:expr $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_entities!(@sync $($rest)+);)?
+    };
+    (@sync $store:expr $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_entities!(@sync $($rest)+);)?
+    };
+
+    (@joined [$($joined:tt)*]; mut $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@joined [$($joined)* &*$store as &dyn eisen::entity::GenericComponentStore,]; $($($rest)+)?)
+    };
+    (@joined [$($joined:tt)*]; not $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@joined [$($joined)*]; $($($rest)+)?)
+    };
+    (@joined [$($joined:tt)*]; added $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@joined [$($joined)*]; $($($rest)+)?)
+    };
+    (@joined [$($joined:tt)*]; changed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@joined [$($joined)*]; $($($rest)+)?)
+    };
+    (@joined [$($joined:tt)*]; removed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@joined [$($joined)*]; $($($rest)+)?)
+    };
+    (@joined [$($joined:tt)*]; $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@joined [$($joined)* &*$store as &dyn eisen::entity::GenericComponentStore,]; $($($rest)+)?)
+    };
+    (@joined [$($joined:tt)*];) => {
+        [$($joined)*]
+    };
+
+    (@branches $driver:ident; [$($position:tt)+]; [$($before:tt)+]; mut $store:expr $(, $($rest:tt)+)?) => {
+        if $driver == $($position)+ {
+            eisen::entity::iteration::PlannedIter::Driven(eisen::expand_iteration!(
+                @pending $store.iter_entity_mut().map(|(index, value)| ((index,), value)); [$($before)+]; [$(, $($rest)+)?]
+            ))
+        } else {
+            eisen::entity::iteration::PlannedIter::First(eisen::iterate_over_entities!(@branches $driver; [$($position)+ + 1]; [$($before)+, mut $store]; $($($rest)+)?))
+        }
+    };
+    (@branches $driver:ident; [$($position:tt)+]; [$($before:tt)+]; not $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@branches $driver; [$($position)+ + 1]; [$($before)+, not $store]; $($($rest)+)?)
+    };
+    (@branches $driver:ident; [$($position:tt)+]; [$($before:tt)+]; added $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@branches $driver; [$($position)+ + 1]; [$($before)+, added $store]; $($($rest)+)?)
+    };
+    (@branches $driver:ident; [$($position:tt)+]; [$($before:tt)+]; changed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@branches $driver; [$($position)+ + 1]; [$($before)+, changed $store]; $($($rest)+)?)
+    };
+    (@branches $driver:ident; [$($position:tt)+]; [$($before:tt)+]; removed $store:expr $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_entities!(@branches $driver; [$($position)+ + 1]; [$($before)+, removed $store]; $($($rest)+)?)
+    };
+    (@branches $driver:ident; [$($position:tt)+]; [$($before:tt)+]; $store:expr $(, $($rest:tt)+)?) => {
+        if $driver == $($position)+ {
+            eisen::entity::iteration::PlannedIter::Driven(eisen::expand_iteration!(
+                @pending $store.iter_entity().map(|(index, value)| ((index,), value)); [$($before)+]; [$(, $($rest)+)?]
+            ))
+        } else {
+            eisen::entity::iteration::PlannedIter::First(eisen::iterate_over_entities!(@branches $driver; [$($position)+ + 1]; [$($before)+, $store]; $($($rest)+)?))
+        }
+    };
+    (@branches $driver:ident; [$($position:tt)+]; [mut $first_store:expr $(, $($others:tt)+)?];) => {
+        eisen::expand_iteration!($first_store.iter_entity_mut() $(, $($others)+)?)
+    };
+    (@branches $driver:ident; [$($position:tt)+]; [$first_store:expr $(, $($others:tt)+)?];) => {
+        eisen::expand_iteration!($first_store.iter_entity() $(, $($others)+)?)
+    };
+}
+
+/**
+ * Joins the DenseStores of an arranged DenseGroup, walking their leading ranges in lock-step.
+ * All stores that are not filters have to be members of the group.
+ * Mutable stores replay the pending moves of the group before the join.
+ * If a plain store did not replay them yet, the join falls back to iterating the first store and looking up the others until the next cleanup.
+ * syntax: (group: dense_group; entities: entity_manager; stores: (mut|not|added|changed|removed)? dense_store_names...)
+ */
+#[allow(unused)]
+#[macro_export]
+macro_rules! iterate_over_group {
+    (group: $group:expr; entities: $entities:expr; stores: $($stores:tt)+) => {
+        eisen::iterate_over_group!(@start $group; $($stores)+)
             .map(|tup| {
-                tup.pop_front()
+                let index = tup.0;
+                tup.replace_first(eisen::entity::EntityHandle{index: index, version: $entities.version_of(index).unwrap()})
             })
     };
 
-    (stores: $first_store:expr $(,$($rest:tt)+)?) => { 
-        eisen::expand_iteration!($first_store.iter_entity() $(, $($rest)+)?)
+    (group: $group:expr; stores: $($stores:tt)+) => {
+        eisen::iterate_over_group!(@start $group; $($stores)+)
             .map(|tup| {
                 tup.pop_front()
             })
     };
-}
\ No newline at end of file
+
+    (@start $group:expr; mut $first:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@join $group; [mut $first $(, $($rest)+)?]; $first.iter_entity_mut() $(, $($rest)+)?)
+    };
+
+    (@start $group:expr; $first:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@join $group; [$first $(, $($rest)+)?]; $first.iter_entity() $(, $($rest)+)?)
+    };
+
+    (@join $group:expr; [$($stores:tt)+]; $($fallback:tt)+) => {{
+        eisen::iterate_over_group!(@sync $($stores)+);
+        let mut members: Vec<&dyn eisen::entity::GroupedStore> = Vec::new();
+        eisen::iterate_over_group!(@members members; $($stores)+);
+        let arranged = $group.is_arranged(&members);
+        drop(members);
+        let iter: Box<dyn Iterator<Item = _> + '_> = if arranged {
+            let len = $group.len();
+            Box::new(eisen::iterate_over_group!(@first len; $($stores)+))
+        } else {
+            Box::new(eisen::expand_iteration!($($fallback)+))
+        };
+        iter
+    }};
+
+    (@sync mut $store:ident $(, $($rest:tt)+)?) => {
+        eisen::entity::GroupedStore::sync_group(&mut *$store);
+        $(eisen::iterate_over_group!(@sync $($rest)+);)?
+    };
+    (@sync $filter:ident $store:ident $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_group!(@sync $($rest)+);)?
+    };
+    (@sync $store:ident $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_group!(@sync $($rest)+);)?
+    };
+
+    (@members $members:ident; mut $store:ident $(, $($rest:tt)+)?) => {
+        $members.push(&*$store);
+        $(eisen::iterate_over_group!(@members $members; $($rest)+);)?
+    };
+    (@members $members:ident; not $store:ident $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_group!(@members $members; $($rest)+);)?
+    };
+    (@members $members:ident; added $store:ident $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_group!(@members $members; $($rest)+);)?
+    };
+    (@members $members:ident; changed $store:ident $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_group!(@members $members; $($rest)+);)?
+    };
+    (@members $members:ident; removed $store:ident $(, $($rest:tt)+)?) => {
+        $(eisen::iterate_over_group!(@members $members; $($rest)+);)?
+    };
+    (@members $members:ident; $store:ident $(, $($rest:tt)+)?) => {
+        $members.push(&*$store);
+        $(eisen::iterate_over_group!(@members $members; $($rest)+);)?
+    };
+
+    (@first $len:ident; mut $first:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@zip $len; $first.iter_leading_mut($len) $(; $($rest)+)?)
+    };
+    (@first $len:ident; $first:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@zip $len; $first.iter_leading($len) $(; $($rest)+)?)
+    };
+
+    (@zip $len:ident; $iter:expr; mut $store:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@zip $len; $iter.zip($store.iter_leading_mut($len)).map(|(tup, (_, value))| tup.append(value)) $(; $($rest)+)?)
+    };
+    (@zip $len:ident; $iter:expr; not $store:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@zip $len; $iter.filter({ let $store = &*$store; move |tup| !$store.has(tup.0) }) $(; $($rest)+)?)
+    };
+    (@zip $len:ident; $iter:expr; added $store:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@zip $len; $iter.filter({ let $store = &*$store; move |tup| $store.is_added(tup.0) }) $(; $($rest)+)?)
+    };
+    (@zip $len:ident; $iter:expr; changed $store:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@zip $len; $iter.filter({ let $store = &*$store; move |tup| $store.is_changed(tup.0) }) $(; $($rest)+)?)
+    };
+    (@zip $len:ident; $iter:expr; removed $store:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@zip $len; $iter.filter({ let $store = &*$store; move |tup| $store.was_removed(tup.0) }) $(; $($rest)+)?)
+    };
+    (@zip $len:ident; $iter:expr; $store:ident $(, $($rest:tt)+)?) => {
+        eisen::iterate_over_group!(@zip $len; $iter.zip($store.iter_leading($len)).map(|(tup, (_, value))| tup.append(value)) $(; $($rest)+)?)
+    };
+    (@zip $len:ident; $iter:expr) => {
+        $iter
+    };
+}
@@ -0,0 +1,178 @@
+use std::any::TypeId;
+
+use crate::entity::handle::*;
+use crate::entity::entity_manager::*;
+use crate::entity::component_storage::*;
+use crate::entity::commands::*;
+use crate::entity::hierarchy::*;
+
+/**
+ * Reusable template of an entity, a set of type erased component values and child prefabs.
+ * Instantiating a prefab creates one entity with clones of its components,
+ * children are instantiated as well and attached with Parent and Children.
+ */
+#[derive(Default)]
+pub struct Prefab {
+    components: Vec<Box<dyn AnyComponent>>,
+    children: Vec<Prefab>,
+}
+
+impl Clone for Prefab {
+    fn clone(&self) -> Self {
+        Self{
+            components: self.components.iter().map(|c| c.clone_box()).collect(),
+            children: self.children.clone(),
+        }
+    }
+}
+
+impl Prefab {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Adds the component to the template, replacing the value of the same type.
+     */
+    #[allow(unused)]
+    pub fn with<C: Component>(mut self, value: C) -> Self {
+        self.set(value);
+        self
+    }
+
+    #[allow(unused)]
+    pub fn with_child(mut self, child: Prefab) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /**
+     * Adds the component to the template, replacing the value of the same type.
+     */
+    #[allow(unused)]
+    pub fn set<C: Component>(&mut self, value: C) {
+        set_boxed(&mut self.components, Box::new(value));
+    }
+
+    #[allow(unused)]
+    pub fn get<C: Component>(&self) -> Option<&C> {
+        self.components.iter()
+            .find(|c| c.component_type() == TypeId::of::<C>())
+            .and_then(|c| c.as_any().downcast_ref::<C>())
+    }
+
+    #[allow(unused)]
+    pub fn get_mut<C: Component>(&mut self) -> Option<&mut C> {
+        self.components.iter_mut()
+            .find(|c| c.component_type() == TypeId::of::<C>())
+            .and_then(|c| c.as_any_mut().downcast_mut::<C>())
+    }
+
+    #[allow(unused)]
+    pub fn children(&self) -> &[Prefab] {
+        &self.children
+    }
+
+    #[allow(unused)]
+    pub fn children_mut(&mut self) -> &mut Vec<Prefab> {
+        &mut self.children
+    }
+
+    /**
+     * Records the creation of an instance into the commands.
+     * The instance exists after the commands are applied.
+     */
+    #[allow(unused)]
+    pub fn spawn(&self, commands: &Commands, entities: &EntityManager) -> EntityHandle {
+        self.spawn_with(commands, entities, Overrides::new())
+    }
+
+    /**
+     * Records the creation of an instance into the commands.
+     * The overrides replace or extend the components of the root entity of the instance.
+     */
+    #[allow(unused)]
+    pub fn spawn_with(&self, commands: &Commands, entities: &EntityManager, overrides: Overrides) -> EntityHandle {
+        self.build(
+            &mut || commands.create(entities),
+            &mut |entity, value| commands.add_boxed(entity, value),
+            overrides,
+        )
+    }
+
+    pub(crate) fn build(
+        &self,
+        create: &mut dyn FnMut() -> EntityHandle,
+        add: &mut dyn FnMut(EntityHandle, Box<dyn AnyComponent>),
+        overrides: Overrides,
+    ) -> EntityHandle {
+        let entity = create();
+        for component in &self.components {
+            if !overrides.contains(component.component_type()) {
+                add(entity, component.clone_box());
+            }
+        }
+        for component in overrides.components {
+            add(entity, component);
+        }
+        if !self.children.is_empty() {
+            // the instances are new, so setting both sides directly matches what EntityManager::set_parent does
+            let children = self.children.iter()
+                .map(|child| {
+                    let child = child.build(create, add, Overrides::new());
+                    add(child, Box::new(Parent(entity)));
+                    child
+                })
+                .collect();
+            add(entity, Box::new(Children(children)));
+        }
+        entity
+    }
+}
+
+/**
+ * Per instance component values, that replace or extend the components of a prefab.
+ */
+#[derive(Default)]
+pub struct Overrides {
+    components: Vec<Box<dyn AnyComponent>>,
+}
+
+impl Overrides {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(unused)]
+    pub fn with<C: Component>(mut self, value: C) -> Self {
+        self.set(value);
+        self
+    }
+
+    #[allow(unused)]
+    pub fn set<C: Component>(&mut self, value: C) {
+        set_boxed(&mut self.components, Box::new(value));
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    fn contains(&self, type_id: TypeId) -> bool {
+        self.components.iter().any(|c| c.component_type() == type_id)
+    }
+
+    pub(crate) fn take(&mut self) -> Overrides {
+        Overrides{ components: std::mem::take(&mut self.components) }
+    }
+}
+
+fn set_boxed(components: &mut Vec<Box<dyn AnyComponent>>, value: Box<dyn AnyComponent>) {
+    match components.iter_mut().find(|c| c.component_type() == value.component_type()) {
+        Some(existing) => *existing = value,
+        None => components.push(value),
+    }
+}
@@ -0,0 +1,478 @@
+use std::marker::PhantomData;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sync::Runtime;
+use crate::entity::handle::*;
+use crate::entity::entity_manager::*;
+use crate::entity::component_storage::*;
+
+/**
+ * One element of a Query, implemented for &C, &mut C, Option<&C> and Option<&mut C>.
+ * Terms work on raw store pointers, the Query they belong to holds the borrow of the stores.
+ */
+pub trait QueryTerm {
+    type Store<'s>;
+    type Ptr: Copy + 'static;
+    type Item<'q>;
+
+    fn into_ptr(store: Self::Store<'_>) -> Self::Ptr;
+
+    /**
+     * False, if the entity is missing the component and the term is not optional.
+     * Safety: ptr must point to a store that is borrowed by the calling Query.
+     */
+    unsafe fn matches(ptr: Self::Ptr, index: EntityIndex) -> bool;
+
+    /**
+     * Safety: ptr must point to a store that is borrowed by the calling Query,
+     * mutable items must only be fetched once per entity while they are alive.
+     */
+    unsafe fn fetch<'q>(ptr: Self::Ptr, index: EntityIndex) -> Option<Self::Item<'q>>;
+}
+
+/**
+ * Terms that can drive the iteration of a Query, the first term of every Query must be one.
+ */
+pub trait DriverTerm: QueryTerm {
+    /**
+     * Safety: same as QueryTerm::fetch.
+     */
+    unsafe fn batches<'q>(ptr: Self::Ptr, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, Self::Item<'q>)> + Send + 'q>>;
+
+    /**
+     * Called before the batches are handed to other threads.
+     * Safety: ptr must point to a store that is borrowed by the calling Query, no items may be alive.
+     */
+    unsafe fn prepare_parallel(_ptr: Self::Ptr) {}
+}
+
+/**
+ * Terms that never hand out mutable references.
+ */
+pub trait ReadOnlyTerm: QueryTerm {}
+
+impl<'x, C: Component> QueryTerm for &'x C {
+    type Store<'s> = &'s C::Storage;
+    type Ptr = *const C::Storage;
+    type Item<'q> = &'q C;
+
+    fn into_ptr(store: Self::Store<'_>) -> Self::Ptr {
+        store
+    }
+
+    unsafe fn matches(ptr: Self::Ptr, index: EntityIndex) -> bool {
+        (*ptr).has(index)
+    }
+
+    unsafe fn fetch<'q>(ptr: Self::Ptr, index: EntityIndex) -> Option<Self::Item<'q>> {
+        (*ptr).get(index)
+    }
+}
+
+impl<'x, C: Component> DriverTerm for &'x C where C::Storage: BatchedStore<C> {
+    unsafe fn batches<'q>(ptr: Self::Ptr, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, Self::Item<'q>)> + Send + 'q>> {
+        (*ptr).batches(batch_size)
+    }
+}
+
+impl<'x, C: Component> ReadOnlyTerm for &'x C {}
+
+impl<'x, C: Component> QueryTerm for &'x mut C {
+    type Store<'s> = &'s mut C::Storage;
+    type Ptr = *mut C::Storage;
+    type Item<'q> = &'q mut C;
+
+    fn into_ptr(store: Self::Store<'_>) -> Self::Ptr {
+        store
+    }
+
+    unsafe fn matches(ptr: Self::Ptr, index: EntityIndex) -> bool {
+        (*ptr).has(index)
+    }
+
+    unsafe fn fetch<'q>(ptr: Self::Ptr, index: EntityIndex) -> Option<Self::Item<'q>> {
+        (*ptr).get_mut(index)
+    }
+}
+
+impl<'x, C: Component> DriverTerm for &'x mut C where C::Storage: BatchedStore<C> {
+    unsafe fn batches<'q>(ptr: Self::Ptr, batch_size: usize) -> Vec<Box<dyn Iterator<Item = (EntityIndex, Self::Item<'q>)> + Send + 'q>> {
+        (*ptr).batches_mut(batch_size)
+    }
+
+    unsafe fn prepare_parallel(ptr: Self::Ptr) {
+        (*ptr).unshare_pages();
+    }
+}
+
+impl<'x, C: Component> QueryTerm for Option<&'x C> {
+    type Store<'s> = &'s C::Storage;
+    type Ptr = *const C::Storage;
+    type Item<'q> = Option<&'q C>;
+
+    fn into_ptr(store: Self::Store<'_>) -> Self::Ptr {
+        store
+    }
+
+    unsafe fn matches(_ptr: Self::Ptr, _index: EntityIndex) -> bool {
+        true
+    }
+
+    unsafe fn fetch<'q>(ptr: Self::Ptr, index: EntityIndex) -> Option<Self::Item<'q>> {
+        Some((*ptr).get(index))
+    }
+}
+
+impl<'x, C: Component> ReadOnlyTerm for Option<&'x C> {}
+
+impl<'x, C: Component> QueryTerm for Option<&'x mut C> {
+    type Store<'s> = &'s mut C::Storage;
+    type Ptr = *mut C::Storage;
+    type Item<'q> = Option<&'q mut C>;
+
+    fn into_ptr(store: Self::Store<'_>) -> Self::Ptr {
+        store
+    }
+
+    unsafe fn matches(_ptr: Self::Ptr, _index: EntityIndex) -> bool {
+        true
+    }
+
+    unsafe fn fetch<'q>(ptr: Self::Ptr, index: EntityIndex) -> Option<Self::Item<'q>> {
+        Some((*ptr).get_mut(index))
+    }
+}
+
+/**
+ * Entity filter of a Query, implemented for With, Without, Added, Changed, () and tuples of filters.
+ */
+pub trait QueryFilter {
+    type Stores<'s>;
+    type Ptrs: Copy + 'static;
+
+    fn into_ptrs(stores: Self::Stores<'_>) -> Self::Ptrs;
+
+    /**
+     * Safety: ptrs must point to stores that are borrowed by the calling Query.
+     */
+    unsafe fn matches(ptrs: Self::Ptrs, index: EntityIndex) -> bool;
+}
+
+impl QueryFilter for () {
+    type Stores<'s> = ();
+    type Ptrs = ();
+
+    fn into_ptrs(_stores: Self::Stores<'_>) -> Self::Ptrs {}
+
+    unsafe fn matches(_ptrs: Self::Ptrs, _index: EntityIndex) -> bool {
+        true
+    }
+}
+
+/**
+ * Only entities that have C.
+ */
+pub struct With<C>(PhantomData<C>);
+
+/**
+ * Only entities that do not have C.
+ */
+pub struct Without<C>(PhantomData<C>);
+
+/**
+ * Only entities whose C was added during the last or the current change tick.
+ */
+pub struct Added<C>(PhantomData<C>);
+
+/**
+ * Only entities whose C was added or changed during the last or the current change tick.
+ */
+pub struct Changed<C>(PhantomData<C>);
+
+macro_rules! impl_store_filter {
+    ($filter:ident, $ptr:ident, $index:ident => $matches:expr) => {
+        impl<C: Component> QueryFilter for $filter<C> {
+            type Stores<'s> = &'s C::Storage;
+            type Ptrs = *const C::Storage;
+
+            fn into_ptrs(stores: Self::Stores<'_>) -> Self::Ptrs {
+                stores
+            }
+
+            unsafe fn matches($ptr: Self::Ptrs, $index: EntityIndex) -> bool {
+                $matches
+            }
+        }
+    };
+}
+
+impl_store_filter!(With, ptr, index => (*ptr).has(index));
+impl_store_filter!(Without, ptr, index => !(*ptr).has(index));
+impl_store_filter!(Added, ptr, index => (*ptr).is_added(index));
+impl_store_filter!(Changed, ptr, index => (*ptr).is_changed(index));
+
+macro_rules! impl_filter_tuple {
+    ($($filters:ident),+) => {
+        #[allow(non_snake_case)]
+        impl<$($filters: QueryFilter),+> QueryFilter for ($($filters,)+) {
+            type Stores<'s> = ($($filters::Stores<'s>,)+);
+            type Ptrs = ($($filters::Ptrs,)+);
+
+            fn into_ptrs(stores: Self::Stores<'_>) -> Self::Ptrs {
+                let ($($filters,)+) = stores;
+                ($($filters::into_ptrs($filters),)+)
+            }
+
+            unsafe fn matches(ptrs: Self::Ptrs, index: EntityIndex) -> bool {
+                let ($($filters,)+) = ptrs;
+                $($filters::matches($filters, index))&&+
+            }
+        }
+    };
+}
+
+impl_filter_tuple!(F0);
+impl_filter_tuple!(F0, F1);
+impl_filter_tuple!(F0, F1, F2);
+impl_filter_tuple!(F0, F1, F2, F3);
+
+/**
+ * Raw store pointers that are kept by a Query and moved into its batch iterators.
+ * Only the Query that owns the borrow of the stores creates them,
+ * whether the Query may be sent to or shared with other threads is decided by the borrowed stores.
+ */
+#[derive(Clone, Copy)]
+struct SharedPtrs<P: Copy>(P);
+
+unsafe impl<P: Copy> Send for SharedPtrs<P> {}
+unsafe impl<P: Copy> Sync for SharedPtrs<P> {}
+
+impl<P: Copy> SharedPtrs<P> {
+    fn get(&self) -> P {
+        self.0
+    }
+}
+
+pub type QueryBatch<'q, Item> = Box<dyn Iterator<Item = (EntityIndex, Item)> + Send + 'q>;
+
+/**
+ * The terms of a Query, implemented for tuples of up to 8 terms whose first term is a DriverTerm.
+ */
+pub trait QueryData {
+    type Stores<'s>;
+    type Ptrs: Copy + 'static;
+    type Item<'q> where Self: 'q;
+
+    fn into_ptrs(stores: Self::Stores<'_>) -> Self::Ptrs;
+
+    /**
+     * Safety: ptrs and filter must point to stores that are borrowed by the calling Query,
+     * no items of an earlier call may be alive.
+     */
+    unsafe fn batches<'q, F: QueryFilter + 'q>(ptrs: Self::Ptrs, filter: F::Ptrs, batch_size: usize) -> Vec<QueryBatch<'q, Self::Item<'q>>> where Self: 'q;
+
+    /**
+     * Safety: same as batches.
+     */
+    unsafe fn prepare_parallel(ptrs: Self::Ptrs);
+}
+
+/**
+ * Queries that can be iterated in parallel, all terms except the driving first one must be read only.
+ */
+pub trait ParallelQueryData: QueryData {}
+
+macro_rules! impl_query_data {
+    ($first:ident $(, $rest:ident)*) => {
+        #[allow(non_snake_case)]
+        impl<$first: DriverTerm, $($rest: QueryTerm),*> QueryData for ($first, $($rest,)*) {
+            type Stores<'s> = ($first::Store<'s>, $($rest::Store<'s>,)*);
+            type Ptrs = ($first::Ptr, $($rest::Ptr,)*);
+            type Item<'q> = ($first::Item<'q>, $($rest::Item<'q>,)*) where Self: 'q;
+
+            fn into_ptrs(stores: Self::Stores<'_>) -> Self::Ptrs {
+                let ($first, $($rest,)*) = stores;
+                ($first::into_ptr($first), $($rest::into_ptr($rest),)*)
+            }
+
+            unsafe fn batches<'q, F: QueryFilter + 'q>(ptrs: Self::Ptrs, filter: F::Ptrs, batch_size: usize) -> Vec<QueryBatch<'q, Self::Item<'q>>> where Self: 'q {
+                let ($first, $($rest,)*) = ptrs;
+                let shared = SharedPtrs((($($rest,)*), filter));
+                $first::batches($first, batch_size)
+                    .into_iter()
+                    .map(move |batch| {
+                        let batch = batch.filter_map(move |(index, first)| {
+                            let (($($rest,)*), filter) = shared.get();
+                            unsafe {
+                                if !F::matches(filter, index) $(|| !$rest::matches($rest, index))* {
+                                    return None;
+                                }
+                                Some((index, (first, $($rest::fetch($rest, index)?,)*)))
+                            }
+                        });
+                        Box::new(batch) as QueryBatch<'q, Self::Item<'q>>
+                    })
+                    .collect()
+            }
+
+            unsafe fn prepare_parallel(ptrs: Self::Ptrs) {
+                $first::prepare_parallel(ptrs.0);
+            }
+        }
+
+        impl<$first: DriverTerm, $($rest: ReadOnlyTerm),*> ParallelQueryData for ($first, $($rest,)*) {}
+    };
+}
+
+impl_query_data!(Q0);
+impl_query_data!(Q0, Q1);
+impl_query_data!(Q0, Q1, Q2);
+impl_query_data!(Q0, Q1, Q2, Q3);
+impl_query_data!(Q0, Q1, Q2, Q3, Q4);
+impl_query_data!(Q0, Q1, Q2, Q3, Q4, Q5);
+impl_query_data!(Q0, Q1, Q2, Q3, Q4, Q5, Q6);
+impl_query_data!(Q0, Q1, Q2, Q3, Q4, Q5, Q6, Q7);
+
+/**
+ * Typed iteration over the entities that match all terms and the filter.
+ * The Query borrows the stores it is created from, so the borrow checker rejects borrowing a store mutably twice.
+ * Items borrow the Query, so they can not outlive it or alias the items of another iteration.
+ * The first term drives the iteration and must be &C or &mut C.
+ * syntax: Query::<(&mut Health, &Name, Option<&Pos>), Without<Tag>>::new((healths, names, positions), tags)
+ */
+pub struct Query<'s, Q: QueryData, F: QueryFilter = ()> {
+    ptrs: SharedPtrs<(Q::Ptrs, F::Ptrs)>,
+    _stores: PhantomData<(Q::Stores<'s>, F::Stores<'s>)>,
+}
+
+/**
+ * Batches of a parallel iteration, shared by the calling thread and the jobs helping it.
+ * Jobs that start after the calling thread returned find no batches left and never touch the borrowed stores or f.
+ */
+struct ParallelBatches<'q, Item, Func> {
+    batches: Mutex<std::vec::IntoIter<QueryBatch<'q, Item>>>,
+    active: AtomicUsize,
+    f: &'q Func,
+}
+
+impl<'q, Item, Func: Fn(EntityIndex, Item)> ParallelBatches<'q, Item, Func> {
+    /**
+     * Processes batches until none are left.
+     */
+    fn work(&self) {
+        loop {
+            let batch = {
+                let mut batches = self.batches.lock().unwrap();
+                let batch = batches.next();
+                if batch.is_some() {
+                    self.active.fetch_add(1, Ordering::AcqRel);
+                }
+                batch
+            };
+            let Some(batch) = batch else {
+                return;
+            };
+            let _active = ActiveBatch(&self.active);
+            profiling::scope!("Query::par_for_each");
+            batch.for_each(|(index, item)| (self.f)(index, item));
+        }
+    }
+}
+
+struct ActiveBatch<'a>(&'a AtomicUsize);
+
+impl Drop for ActiveBatch<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/**
+ * Held by the calling thread of a parallel iteration, also while it unwinds.
+ * Drops the batches nobody started and blocks until the batches other threads are working on are done.
+ */
+struct CompletionGuard<'a, 'q, Item, Func>(&'a ParallelBatches<'q, Item, Func>);
+
+impl<Item, Func> Drop for CompletionGuard<'_, '_, Item, Func> {
+    fn drop(&mut self) {
+        let rest = std::mem::take(&mut *self.0.batches.lock().unwrap_or_else(|e| e.into_inner()));
+        drop(rest);
+        while self.0.active.load(Ordering::Acquire) > 0 {
+            std::thread::yield_now();
+        }
+    }
+}
+
+unsafe fn erase_job_lifetime<'a>(job: Box<dyn FnOnce() + Send + 'a>) -> Box<dyn FnOnce() + Send + 'static> {
+    std::mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Box<dyn FnOnce() + Send + 'static>>(job)
+}
+
+impl<'s, Q: QueryData, F: QueryFilter> Query<'s, Q, F> {
+    #[allow(unused)]
+    pub fn new(stores: Q::Stores<'s>, filter: F::Stores<'s>) -> Self {
+        Self{
+            ptrs: SharedPtrs((Q::into_ptrs(stores), F::into_ptrs(filter))),
+            _stores: PhantomData,
+        }
+    }
+
+    #[allow(unused)]
+    pub fn iter(&mut self) -> impl Iterator<Item = Q::Item<'_>> + '_ {
+        self.iter_entity().map(|(_, item)| item)
+    }
+
+    #[allow(unused)]
+    pub fn iter_entity(&mut self) -> impl Iterator<Item = (EntityIndex, Q::Item<'_>)> + '_ {
+        unsafe{ Q::batches::<F>(self.ptrs.get().0, self.ptrs.get().1, usize::MAX) }.into_iter().flatten()
+    }
+
+    #[allow(unused)]
+    pub fn iter_handles<'q>(&'q mut self, entities: &'q EntityManager) -> impl Iterator<Item = (EntityHandle, Q::Item<'q>)> + 'q {
+        self.iter_entity().map(move |(index, item)| (EntityHandle{index: index, version: entities.version_of(index).unwrap()}, item))
+    }
+
+    #[allow(unused)]
+    pub fn iter_batch(&mut self, batch_size: usize) -> impl Iterator<Item = QueryBatch<'_, Q::Item<'_>>> + '_ {
+        unsafe{ Q::batches::<F>(self.ptrs.get().0, self.ptrs.get().1, batch_size) }.into_iter()
+    }
+
+    #[allow(unused)]
+    pub fn for_each<'q>(&'q mut self, f: impl FnMut(Q::Item<'q>)) {
+        self.iter().for_each(f);
+    }
+
+    #[allow(unused)]
+    pub fn count(&mut self) -> usize {
+        self.iter_entity().count()
+    }
+
+    /**
+     * Calls f for every matching entity, batches are executed on the runtime and on the calling thread.
+     * Blocks until all batches are processed, the calling thread works on the batches no other thread started,
+     * so it also finishes when every worker of the runtime is busy.
+     */
+    #[allow(unused)]
+    pub fn par_for_each<'q, Func>(&'q mut self, runtime: &Runtime, batch_size: usize, f: Func)
+    where
+        Q: ParallelQueryData,
+        Func: Fn(EntityIndex, Q::Item<'q>) + Send + Sync + 'q,
+    {
+        let batches = unsafe{
+            Q::prepare_parallel(self.ptrs.get().0);
+            Q::batches::<F>(self.ptrs.get().0, self.ptrs.get().1, batch_size)
+        };
+        let helpers = batches.len().saturating_sub(1);
+        let shared = Arc::new(ParallelBatches{ batches: Mutex::new(batches.into_iter()), active: AtomicUsize::new(0), f: &f });
+        let guard = CompletionGuard(&shared);
+        for _ in 0..helpers {
+            let shared = shared.clone();
+            let job = Box::new(move || shared.work());
+            // the job only touches the borrows while it holds a batch, the guard waits for those before they end
+            runtime.exec(unsafe{ erase_job_lifetime(job) });
+        }
+        shared.work();
+        drop(guard);
+    }
+}
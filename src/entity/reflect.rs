@@ -0,0 +1,312 @@
+use std::any::{Any, TypeId};
+
+use rustc_hash::FxHashMap;
+
+use crate::Vf32x2;
+use crate::Vf32x4;
+use crate::entity::handle::*;
+use crate::entity::component_storage::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReflectError {
+    NoEntity,
+    UnknownComponent(String),
+    MissingComponent(String),
+    UnknownField(String),
+    NotAValue(String),
+    TypeMismatch{ path: String, expected: &'static str },
+}
+
+impl std::fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReflectError::NoEntity => write!(f, "entity does not exist"),
+            ReflectError::UnknownComponent(name) => write!(f, "component \"{}\" is not registered for reflection", name),
+            ReflectError::MissingComponent(name) => write!(f, "entity has no component \"{}\"", name),
+            ReflectError::UnknownField(path) => write!(f, "no field \"{}\"", path),
+            ReflectError::NotAValue(path) => write!(f, "\"{}\" has fields and is not a single value", path),
+            ReflectError::TypeMismatch{ path, expected } => write!(f, "\"{}\" expects a value of type {}", path, expected),
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+/**
+ * Value of a leaf field, integers and floats of all widths are passed as the widest type.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReflectValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl ReflectValue {
+    /**
+     * Reads a value typed into a console or a text file: true/false, integers, floats and otherwise strings,
+     * surrounding quotes are stripped from strings.
+     */
+    #[allow(unused)]
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        if let Ok(value) = text.parse::<bool>() {
+            ReflectValue::Bool(value)
+        } else if let Ok(value) = text.parse::<i64>() {
+            ReflectValue::Int(value)
+        } else if let Ok(value) = text.parse::<f64>() {
+            ReflectValue::Float(value)
+        } else {
+            ReflectValue::String(text.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(text).to_string())
+        }
+    }
+}
+
+impl std::fmt::Display for ReflectValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReflectValue::Bool(value) => write!(f, "{}", value),
+            ReflectValue::Int(value) => write!(f, "{}", value),
+            ReflectValue::Float(value) => write!(f, "{:?}", value),
+            ReflectValue::String(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub type_name: &'static str,
+}
+
+/**
+ * Name and fields of a reflected type, values have no fields.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeInfo {
+    pub name: &'static str,
+    pub fields: Vec<FieldInfo>,
+}
+
+/**
+ * Dynamic access to the fields of a type.
+ * Structs implement it with impl_reflect!, they have fields, the primitive types are values that can be read and written.
+ */
+pub trait Reflect: Any + Send + Sync {
+    fn type_info(&self) -> TypeInfo;
+
+    fn field(&self, _name: &str) -> Option<&dyn Reflect> {
+        None
+    }
+
+    fn field_mut(&mut self, _name: &str) -> Option<&mut dyn Reflect> {
+        None
+    }
+
+    fn value(&self) -> Option<ReflectValue> {
+        None
+    }
+
+    /**
+     * Returns false, if the type is not a value or can not hold the value.
+     */
+    fn set_value(&mut self, _value: ReflectValue) -> bool {
+        false
+    }
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl dyn Reflect {
+    /**
+     * Follows a path of field names separated by dots, like "position.x". The empty path is the value itself.
+     */
+    #[allow(unused)]
+    pub fn path(&self, path: &str) -> Result<&dyn Reflect, ReflectError> {
+        let mut current = self;
+        for (end, name) in path_segments(path) {
+            current = current.field(name).ok_or_else(|| ReflectError::UnknownField(path[..end].to_string()))?;
+        }
+        Ok(current)
+    }
+
+    #[allow(unused)]
+    pub fn path_mut(&mut self, path: &str) -> Result<&mut dyn Reflect, ReflectError> {
+        let mut current = self;
+        for (end, name) in path_segments(path) {
+            current = current.field_mut(name).ok_or_else(|| ReflectError::UnknownField(path[..end].to_string()))?;
+        }
+        Ok(current)
+    }
+
+    #[allow(unused)]
+    pub fn get_path(&self, path: &str) -> Result<ReflectValue, ReflectError> {
+        self.path(path)?.value().ok_or_else(|| ReflectError::NotAValue(path.to_string()))
+    }
+
+    #[allow(unused)]
+    pub fn set_path(&mut self, path: &str, value: ReflectValue) -> Result<(), ReflectError> {
+        let field = self.path_mut(path)?;
+        if field.value().is_none() {
+            return Err(ReflectError::NotAValue(path.to_string()));
+        }
+        let expected = field.type_info().name;
+        if field.set_value(value) {
+            Ok(())
+        } else {
+            Err(ReflectError::TypeMismatch{ path: path.to_string(), expected })
+        }
+    }
+}
+
+/**
+ * The names of a path together with the end of the path up to and including them.
+ */
+fn path_segments(path: &str) -> impl Iterator<Item = (usize, &str)> {
+    path.split('.')
+        .filter(|name| !name.is_empty())
+        .map(move |name| (name.as_ptr() as usize - path.as_ptr() as usize + name.len(), name))
+}
+
+macro_rules! impl_reflect_value {
+    ($T:ty, $value:ident, $to:expr, $from:expr) => {
+        impl Reflect for $T {
+            fn type_info(&self) -> TypeInfo {
+                TypeInfo{ name: stringify!($T), fields: Vec::new() }
+            }
+
+            fn value(&self) -> Option<ReflectValue> {
+                let $value = self;
+                Some($to)
+            }
+
+            fn set_value(&mut self, value: ReflectValue) -> bool {
+                let $value = value;
+                match $from {
+                    Some(value) => {
+                        *self = value;
+                        true
+                    },
+                    None => false,
+                }
+            }
+
+            fn as_any(&self) -> &dyn Any {
+                self
+            }
+
+            fn as_any_mut(&mut self) -> &mut dyn Any {
+                self
+            }
+        }
+    };
+}
+
+macro_rules! impl_reflect_int {
+    ($($T:ty),+) => {
+        $(impl_reflect_value!($T, value, ReflectValue::Int(*value as i64), match value {
+            ReflectValue::Int(value) => <$T>::try_from(value).ok(),
+            _ => None,
+        });)+
+    };
+}
+
+macro_rules! impl_reflect_float {
+    ($($T:ty),+) => {
+        $(impl_reflect_value!($T, value, ReflectValue::Float(*value as f64), match value {
+            ReflectValue::Int(value) => Some(value as $T),
+            ReflectValue::Float(value) => Some(value as $T),
+            _ => None,
+        });)+
+    };
+}
+
+impl_reflect_int!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+impl_reflect_float!(f32, f64);
+impl_reflect_value!(bool, value, ReflectValue::Bool(*value), match value {
+    ReflectValue::Bool(value) => Some(value),
+    _ => None,
+});
+impl_reflect_value!(String, value, ReflectValue::String(value.clone()), match value {
+    ReflectValue::String(value) => Some(value),
+    _ => None,
+});
+
+/**
+ * Implements Reflect for a struct by listing its fields, the field types have to implement Reflect as well.
+ * Fields of tuple structs are named by their number.
+ * syntax: impl_reflect!(StructName { field_name: FieldType, ... });
+ */
+#[allow(unused)]
+#[macro_export]
+macro_rules! impl_reflect {
+    ($T:ty { $($field:tt : $F:ty),* $(,)? }) => {
+        impl $crate::entity::reflect::Reflect for $T {
+            fn type_info(&self) -> $crate::entity::reflect::TypeInfo {
+                $crate::entity::reflect::TypeInfo{
+                    name: stringify!($T),
+                    fields: vec![$($crate::entity::reflect::FieldInfo{ name: stringify!($field), type_name: std::any::type_name::<$F>() }),*],
+                }
+            }
+
+            fn field(&self, name: &str) -> Option<&dyn $crate::entity::reflect::Reflect> {
+                match name {
+                    $(stringify!($field) => Some(&self.$field as &$F),)*
+                    _ => None,
+                }
+            }
+
+            fn field_mut(&mut self, name: &str) -> Option<&mut dyn $crate::entity::reflect::Reflect> {
+                match name {
+                    $(stringify!($field) => Some(&mut self.$field as &mut $F),)*
+                    _ => None,
+                }
+            }
+
+            fn as_any(&self) -> &dyn std::any::Any {
+                self
+            }
+
+            fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+                self
+            }
+        }
+    };
+}
+
+impl_reflect!(Vf32x2 { x: f32, y: f32 });
+impl_reflect!(Vf32x4 { x: f32, y: f32, z: f32, w: f32 });
+impl_reflect!(EntityHandle { index: EntityIndex, version: EntityVersion });
+
+/**
+ * Type erased access to one reflected component type, kept by the EntityComponentManager.
+ */
+#[derive(Clone)]
+pub(crate) struct ReflectComponent {
+    pub(crate) type_id: TypeId,
+    pub(crate) info: TypeInfo,
+    pub(crate) get: fn(&dyn GenericComponentStore, EntityIndex) -> Option<&dyn Reflect>,
+    pub(crate) get_mut: fn(&mut dyn GenericComponentStore, EntityIndex) -> Option<&mut dyn Reflect>,
+}
+
+impl ReflectComponent {
+    pub(crate) fn of<C: Component + Reflect>() -> Self {
+        Self{
+            type_id: TypeId::of::<C>(),
+            info: C::default().type_info(),
+            get: |store, index| store.as_any().downcast_ref::<C::Storage>().unwrap().get(index).map(|c| c as &dyn Reflect),
+            get_mut: |store, index| store.as_any_mut().downcast_mut::<C::Storage>().unwrap().get_mut(index).map(|c| c as &mut dyn Reflect),
+        }
+    }
+}
+
+/**
+ * The reflected components of an EntityComponentManager by name.
+ */
+#[derive(Default)]
+pub(crate) struct TypeRegistry {
+    pub(crate) components: FxHashMap<&'static str, ReflectComponent>,
+}
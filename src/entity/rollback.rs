@@ -0,0 +1,157 @@
+use std::any::{Any, TypeId};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::entity::entity_manager::*;
+use crate::entity::component_manager::*;
+
+pub(crate) type RollbackSaveFuture<'a> = Pin<Box<dyn Future<Output = Option<Box<dyn Any + Send + Sync>>> + Send + 'a>>;
+pub(crate) type RollbackRestoreFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/**
+ * Saves and restores a resource or an event queue that was registered for rollback.
+ * save returns None for resources that are not inserted, restore removes them again.
+ */
+#[derive(Clone, Copy)]
+pub(crate) struct RollbackCloner {
+    pub(crate) type_id: TypeId,
+    pub(crate) save: for<'a> fn(&'a EntityComponentManager) -> RollbackSaveFuture<'a>,
+    pub(crate) restore: for<'a> fn(&'a EntityComponentManager, Option<&'a (dyn Any + Send + Sync)>) -> RollbackRestoreFuture<'a>,
+}
+
+/**
+ * Saved state of a world, taken by EntityComponentManager::save_frame.
+ * LinearStores share their pages with the world until they are written, so frames of slowly changing worlds are cheap.
+ */
+pub struct WorldFrame {
+    pub(crate) entities: EntityManager,
+    /**
+     * The state of every store that existed when the frame was saved, None for stores that can not be rolled back.
+     */
+    pub(crate) stores: Vec<(TypeId, Option<Box<dyn Any + Send + Sync>>)>,
+    /**
+     * The state of every dynamic store, ordered by DynamicComponentId.
+     */
+    pub(crate) dynamic_stores: Vec<Box<dyn Any + Send + Sync>>,
+    /**
+     * The archetype registry the TableStores share, their rows are only valid together with it.
+     */
+    pub(crate) tables: Box<dyn Any + Send + Sync>,
+    /**
+     * The resources registered for rollback, None for resources that were not inserted.
+     */
+    pub(crate) resources: Vec<(TypeId, Option<Box<dyn Any + Send + Sync>>)>,
+    /**
+     * The event queues registered for rollback.
+     */
+    pub(crate) events: Vec<(TypeId, Option<Box<dyn Any + Send + Sync>>)>,
+}
+
+/**
+ * A saved world together with the input that drove the step starting from it.
+ */
+pub struct RollbackFrame<I> {
+    pub tick: u64,
+    pub world: WorldFrame,
+    pub input: I,
+}
+
+/**
+ * Ring buffer of the frames of the last capacity fixed steps.
+ */
+pub struct RollbackBuffer<I> {
+    capacity: usize,
+    frames: VecDeque<RollbackFrame<I>>,
+}
+
+impl<I> RollbackBuffer<I> {
+    #[allow(unused)]
+    pub fn new(capacity: usize) -> Self {
+        Self{
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    #[allow(unused)]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /**
+     * Changes the number of kept frames, the oldest frames are dropped when shrinking.
+     */
+    #[allow(unused)]
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.frames.len() > capacity {
+            self.frames.pop_front();
+        }
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /**
+     * Appends the frame, frames at or after its tick are replaced, as they belong to a timeline that was rolled back.
+     */
+    #[allow(unused)]
+    pub fn push(&mut self, frame: RollbackFrame<I>) {
+        self.truncate(frame.tick);
+        if self.capacity == 0 {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /**
+     * Drops all frames at or after the tick.
+     */
+    #[allow(unused)]
+    pub fn truncate(&mut self, tick: u64) {
+        while self.frames.back().is_some_and(|frame| frame.tick >= tick) {
+            self.frames.pop_back();
+        }
+    }
+
+    #[allow(unused)]
+    pub fn get(&self, tick: u64) -> Option<&RollbackFrame<I>> {
+        let first = self.frames.front()?.tick;
+        self.frames.get(tick.checked_sub(first)? as usize).filter(|frame| frame.tick == tick)
+    }
+
+    #[allow(unused)]
+    pub fn get_mut(&mut self, tick: u64) -> Option<&mut RollbackFrame<I>> {
+        let first = self.frames.front()?.tick;
+        self.frames.get_mut(tick.checked_sub(first)? as usize).filter(|frame| frame.tick == tick)
+    }
+
+    #[allow(unused)]
+    pub fn oldest_tick(&self) -> Option<u64> {
+        self.frames.front().map(|frame| frame.tick)
+    }
+
+    #[allow(unused)]
+    pub fn newest_tick(&self) -> Option<u64> {
+        self.frames.back().map(|frame| frame.tick)
+    }
+
+    /**
+     * The frames from the tick on, oldest first.
+     */
+    #[allow(unused)]
+    pub fn iter_from(&self, tick: u64) -> impl Iterator<Item = &RollbackFrame<I>> {
+        self.frames.iter().filter(move |frame| frame.tick >= tick)
+    }
+}
@@ -0,0 +1,427 @@
+use std::any::TypeId;
+
+use crate::Vf32x2;
+use crate::Vf32x4;
+use crate::entity::handle::*;
+use crate::entity::entity_manager::*;
+use crate::entity::component_storage::*;
+
+pub const SNAPSHOT_MAGIC: &str = "eisen_snapshot";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    UnexpectedEnd,
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    UnknownComponent(String),
+    Malformed(String),
+    SequenceMismatch{ expected: u64, found: u64 },
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::UnexpectedEnd => write!(f, "snapshot ended unexpectedly"),
+            SnapshotError::InvalidMagic => write!(f, "data is not an eisen snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "snapshot version {} is not supported (newest is {})", version, SNAPSHOT_VERSION),
+            SnapshotError::UnknownComponent(name) => write!(f, "snapshot contains unregistered component \"{}\"", name),
+            SnapshotError::Malformed(msg) => write!(f, "malformed snapshot: {}", msg),
+            SnapshotError::SequenceMismatch{ expected, found } => write!(f, "delta is based on state {}, but the world is at state {}", found, expected),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/**
+ * Sink for snapshot data.
+ * Labels are only written by human readable formats, binary formats ignore them.
+ */
+pub trait SnapshotWriter {
+    fn begin(&mut self, label: &str);
+    fn end(&mut self);
+    fn write_u32(&mut self, label: &str, value: u32);
+    fn write_u64(&mut self, label: &str, value: u64);
+    fn write_i32(&mut self, label: &str, value: i32);
+    fn write_i64(&mut self, label: &str, value: i64);
+    fn write_f32(&mut self, label: &str, value: f32);
+    fn write_f64(&mut self, label: &str, value: f64);
+    fn write_bool(&mut self, label: &str, value: bool);
+    fn write_str(&mut self, label: &str, value: &str);
+
+    fn write_vec2(&mut self, label: &str, value: Vf32x2) {
+        self.begin(label);
+        self.write_f32("x", value.x);
+        self.write_f32("y", value.y);
+        self.end();
+    }
+
+    fn write_vec4(&mut self, label: &str, value: Vf32x4) {
+        self.begin(label);
+        self.write_f32("x", value.x);
+        self.write_f32("y", value.y);
+        self.write_f32("z", value.z);
+        self.write_f32("w", value.w);
+        self.end();
+    }
+}
+
+/**
+ * Source of snapshot data, the reads must mirror the writes that produced it.
+ */
+pub trait SnapshotReader {
+    fn begin(&mut self, label: &str) -> Result<(), SnapshotError>;
+    fn end(&mut self) -> Result<(), SnapshotError>;
+    fn read_u32(&mut self, label: &str) -> Result<u32, SnapshotError>;
+    fn read_u64(&mut self, label: &str) -> Result<u64, SnapshotError>;
+    fn read_i32(&mut self, label: &str) -> Result<i32, SnapshotError>;
+    fn read_i64(&mut self, label: &str) -> Result<i64, SnapshotError>;
+    fn read_f32(&mut self, label: &str) -> Result<f32, SnapshotError>;
+    fn read_f64(&mut self, label: &str) -> Result<f64, SnapshotError>;
+    fn read_bool(&mut self, label: &str) -> Result<bool, SnapshotError>;
+    fn read_string(&mut self, label: &str) -> Result<String, SnapshotError>;
+
+    fn read_vec2(&mut self, label: &str) -> Result<Vf32x2, SnapshotError> {
+        self.begin(label)?;
+        let value = Vf32x2::new(self.read_f32("x")?, self.read_f32("y")?);
+        self.end()?;
+        Ok(value)
+    }
+
+    fn read_vec4(&mut self, label: &str) -> Result<Vf32x4, SnapshotError> {
+        self.begin(label)?;
+        let value = Vf32x4::new(self.read_f32("x")?, self.read_f32("y")?, self.read_f32("z")?, self.read_f32("w")?);
+        self.end()?;
+        Ok(value)
+    }
+}
+
+/**
+ * Opt in of a component type into world snapshots.
+ * NAME identifies the component in the snapshot and must be unique and stable across versions of the game.
+ */
+pub trait SnapshotComponent: Component {
+    const NAME: &'static str;
+
+    fn save(&self, writer: &mut dyn SnapshotWriter);
+
+    fn load(reader: &mut dyn SnapshotReader) -> Result<Self, SnapshotError>;
+}
+
+//o------------ binary format ---------------o
+
+#[derive(Default)]
+pub struct BinarySnapshotWriter {
+    pub data: Vec<u8>,
+}
+
+impl BinarySnapshotWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SnapshotWriter for BinarySnapshotWriter {
+    fn begin(&mut self, _label: &str) {}
+    fn end(&mut self) {}
+    fn write_u32(&mut self, _label: &str, value: u32) { self.data.extend_from_slice(&value.to_le_bytes()); }
+    fn write_u64(&mut self, _label: &str, value: u64) { self.data.extend_from_slice(&value.to_le_bytes()); }
+    fn write_i32(&mut self, _label: &str, value: i32) { self.data.extend_from_slice(&value.to_le_bytes()); }
+    fn write_i64(&mut self, _label: &str, value: i64) { self.data.extend_from_slice(&value.to_le_bytes()); }
+    fn write_f32(&mut self, _label: &str, value: f32) { self.data.extend_from_slice(&value.to_le_bytes()); }
+    fn write_f64(&mut self, _label: &str, value: f64) { self.data.extend_from_slice(&value.to_le_bytes()); }
+    fn write_bool(&mut self, _label: &str, value: bool) { self.data.push(value as u8); }
+    fn write_str(&mut self, label: &str, value: &str) {
+        self.write_u32(label, value.len() as u32);
+        self.data.extend_from_slice(value.as_bytes());
+    }
+}
+
+pub struct BinarySnapshotReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BinarySnapshotReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self{ data, position: 0 }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let bytes = self.data.get(self.position..self.position + len).ok_or(SnapshotError::UnexpectedEnd)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        let bytes = self.data.get(self.position..self.position + N).ok_or(SnapshotError::UnexpectedEnd)?;
+        self.position += N;
+        Ok(bytes.try_into().unwrap())
+    }
+}
+
+impl<'a> SnapshotReader for BinarySnapshotReader<'a> {
+    fn begin(&mut self, _label: &str) -> Result<(), SnapshotError> { Ok(()) }
+    fn end(&mut self) -> Result<(), SnapshotError> { Ok(()) }
+    fn read_u32(&mut self, _label: &str) -> Result<u32, SnapshotError> { Ok(u32::from_le_bytes(self.take()?)) }
+    fn read_u64(&mut self, _label: &str) -> Result<u64, SnapshotError> { Ok(u64::from_le_bytes(self.take()?)) }
+    fn read_i32(&mut self, _label: &str) -> Result<i32, SnapshotError> { Ok(i32::from_le_bytes(self.take()?)) }
+    fn read_i64(&mut self, _label: &str) -> Result<i64, SnapshotError> { Ok(i64::from_le_bytes(self.take()?)) }
+    fn read_f32(&mut self, _label: &str) -> Result<f32, SnapshotError> { Ok(f32::from_le_bytes(self.take()?)) }
+    fn read_f64(&mut self, _label: &str) -> Result<f64, SnapshotError> { Ok(f64::from_le_bytes(self.take()?)) }
+    fn read_bool(&mut self, _label: &str) -> Result<bool, SnapshotError> {
+        match self.take::<1>()?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(SnapshotError::Malformed(format!("{} is not a bool", other))),
+        }
+    }
+    fn read_string(&mut self, label: &str) -> Result<String, SnapshotError> {
+        let len = self.read_u32(label)? as usize;
+        let bytes = self.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| SnapshotError::Malformed(format!("{} is not valid utf8", label)))
+    }
+}
+
+//o------------ text format ---------------o
+
+/**
+ * Human readable format, one "label value" pair per line, nested blocks are indented.
+ */
+#[derive(Default)]
+pub struct TextSnapshotWriter {
+    pub text: String,
+    depth: usize,
+}
+
+impl TextSnapshotWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn line(&mut self, label: &str, value: std::fmt::Arguments) {
+        for _ in 0..self.depth {
+            self.text.push_str("    ");
+        }
+        self.text.push_str(label);
+        self.text.push(' ');
+        self.text.push_str(&value.to_string());
+        self.text.push('\n');
+    }
+}
+
+impl SnapshotWriter for TextSnapshotWriter {
+    fn begin(&mut self, label: &str) {
+        self.line(label, format_args!("{{"));
+        self.depth += 1;
+    }
+    fn end(&mut self) {
+        self.depth -= 1;
+        for _ in 0..self.depth {
+            self.text.push_str("    ");
+        }
+        self.text.push_str("}\n");
+    }
+    fn write_u32(&mut self, label: &str, value: u32) { self.line(label, format_args!("{}", value)); }
+    fn write_u64(&mut self, label: &str, value: u64) { self.line(label, format_args!("{}", value)); }
+    fn write_i32(&mut self, label: &str, value: i32) { self.line(label, format_args!("{}", value)); }
+    fn write_i64(&mut self, label: &str, value: i64) { self.line(label, format_args!("{}", value)); }
+    fn write_f32(&mut self, label: &str, value: f32) { self.line(label, format_args!("{:?}", value)); }
+    fn write_f64(&mut self, label: &str, value: f64) { self.line(label, format_args!("{:?}", value)); }
+    fn write_bool(&mut self, label: &str, value: bool) { self.line(label, format_args!("{}", value)); }
+    fn write_str(&mut self, label: &str, value: &str) { self.line(label, format_args!("{:?}", value)); }
+}
+
+pub struct TextSnapshotReader<'a> {
+    lines: std::iter::Peekable<std::str::Lines<'a>>,
+}
+
+impl<'a> TextSnapshotReader<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self{ lines: text.lines().peekable() }
+    }
+
+    fn next_value(&mut self, label: &str) -> Result<&'a str, SnapshotError> {
+        let line = loop {
+            let line = self.lines.next().ok_or(SnapshotError::UnexpectedEnd)?.trim();
+            if !line.is_empty() {
+                break line;
+            }
+        };
+        match line.split_once(' ') {
+            Some((found, value)) if found == label => Ok(value),
+            _ => Err(SnapshotError::Malformed(format!("expected \"{}\", found \"{}\"", label, line))),
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, label: &str) -> Result<T, SnapshotError> {
+        let value = self.next_value(label)?;
+        value.parse().map_err(|_| SnapshotError::Malformed(format!("can not parse \"{}\" of {}", value, label)))
+    }
+}
+
+impl<'a> SnapshotReader for TextSnapshotReader<'a> {
+    fn begin(&mut self, label: &str) -> Result<(), SnapshotError> {
+        match self.next_value(label)? {
+            "{" => Ok(()),
+            other => Err(SnapshotError::Malformed(format!("expected block {}, found \"{}\"", label, other))),
+        }
+    }
+    fn end(&mut self) -> Result<(), SnapshotError> {
+        match self.lines.next().map(|line| line.trim()) {
+            Some("}") => Ok(()),
+            Some(other) => Err(SnapshotError::Malformed(format!("expected end of block, found \"{}\"", other))),
+            None => Err(SnapshotError::UnexpectedEnd),
+        }
+    }
+    fn read_u32(&mut self, label: &str) -> Result<u32, SnapshotError> { self.parse(label) }
+    fn read_u64(&mut self, label: &str) -> Result<u64, SnapshotError> { self.parse(label) }
+    fn read_i32(&mut self, label: &str) -> Result<i32, SnapshotError> { self.parse(label) }
+    fn read_i64(&mut self, label: &str) -> Result<i64, SnapshotError> { self.parse(label) }
+    fn read_f32(&mut self, label: &str) -> Result<f32, SnapshotError> { self.parse(label) }
+    fn read_f64(&mut self, label: &str) -> Result<f64, SnapshotError> { self.parse(label) }
+    fn read_bool(&mut self, label: &str) -> Result<bool, SnapshotError> { self.parse(label) }
+    fn read_string(&mut self, label: &str) -> Result<String, SnapshotError> {
+        let quoted = self.next_value(label)?;
+        unescape_str(quoted).ok_or_else(|| SnapshotError::Malformed(format!("can not parse string {} of {}", quoted, label)))
+    }
+}
+
+fn unescape_str(quoted: &str) -> Option<String> {
+    let inner = quoted.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            '0' => out.push('\0'),
+            '\\' => out.push('\\'),
+            '"' => out.push('"'),
+            '\'' => out.push('\''),
+            'u' => {
+                let code: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                out.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+            },
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+//o------------ world snapshots ---------------o
+
+/**
+ * Type erased save and load functions of one SnapshotComponent, kept by the EntityComponentManager.
+ */
+#[derive(Clone, Copy)]
+pub struct ComponentSerializer {
+    pub name: &'static str,
+    pub type_id: TypeId,
+    pub save: fn(&dyn GenericComponentStore, EntityIndex, &mut dyn SnapshotWriter),
+    pub load: fn(&mut dyn GenericComponentStore, EntityIndex, &mut dyn SnapshotReader) -> Result<(), SnapshotError>,
+}
+
+impl ComponentSerializer {
+    pub fn of<C: SnapshotComponent>() -> Self {
+        Self{
+            name: C::NAME,
+            type_id: TypeId::of::<C>(),
+            save: save_component::<C>,
+            load: load_component::<C>,
+        }
+    }
+}
+
+fn save_component<C: SnapshotComponent>(store: &dyn GenericComponentStore, index: EntityIndex, writer: &mut dyn SnapshotWriter) {
+    let store = store.as_any().downcast_ref::<C::Storage>().unwrap();
+    store.get(index).unwrap().save(writer);
+}
+
+fn load_component<C: SnapshotComponent>(store: &mut dyn GenericComponentStore, index: EntityIndex, reader: &mut dyn SnapshotReader) -> Result<(), SnapshotError> {
+    let store = store.as_any_mut().downcast_mut::<C::Storage>().unwrap();
+    let value = C::load(reader)?;
+    if store.has(index) {
+        store.set(index, value);
+    } else {
+        store.add(index, value);
+    }
+    Ok(())
+}
+
+pub(crate) fn save_entities(entities: &EntityManager, writer: &mut dyn SnapshotWriter) {
+    writer.begin("entities");
+    writer.write_u32("slot_count", entities.entity_slots.len() as u32);
+    for slot in &entities.entity_slots {
+        writer.begin("slot");
+        writer.write_u32("version", slot.version);
+        writer.write_bool("alive", slot.alive);
+        writer.end();
+    }
+    writer.write_u32("free_count", entities.entity_free_list.len() as u32);
+    for index in &entities.entity_free_list {
+        writer.write_u32("free", *index);
+    }
+    writer.write_u32("destruct_count", entities.entity_destruct_queue.len() as u32);
+    for index in &entities.entity_destruct_queue {
+        writer.write_u32("destruct", *index);
+    }
+    writer.end();
+}
+
+pub(crate) fn load_entities(reader: &mut dyn SnapshotReader) -> Result<EntityManager, SnapshotError> {
+    let mut entities = EntityManager::new();
+    reader.begin("entities")?;
+    let slot_count = reader.read_u32("slot_count")?;
+    for _ in 0..slot_count {
+        reader.begin("slot")?;
+        let version = reader.read_u32("version")?;
+        let alive = reader.read_bool("alive")?;
+        reader.end()?;
+        entities.entity_slots.push(EntitySlot{ version, alive });
+    }
+    let free_count = reader.read_u32("free_count")?;
+    for _ in 0..free_count {
+        entities.entity_free_list.push(checked_index(reader.read_u32("free")?, slot_count)?);
+    }
+    let destruct_count = reader.read_u32("destruct_count")?;
+    for _ in 0..destruct_count {
+        entities.entity_destruct_queue.push(checked_index(reader.read_u32("destruct")?, slot_count)?);
+    }
+    reader.end()?;
+    Ok(entities)
+}
+
+pub(crate) fn checked_index(index: EntityIndex, slot_count: u32) -> Result<EntityIndex, SnapshotError> {
+    if index < slot_count {
+        Ok(index)
+    } else {
+        Err(SnapshotError::Malformed(format!("entity index {} out of range", index)))
+    }
+}
+
+pub(crate) fn write_header(writer: &mut dyn SnapshotWriter) {
+    writer.write_str("magic", SNAPSHOT_MAGIC);
+    writer.write_u32("version", SNAPSHOT_VERSION);
+}
+
+pub(crate) fn read_header(reader: &mut dyn SnapshotReader) -> Result<u32, SnapshotError> {
+    if reader.read_string("magic").map_err(|_| SnapshotError::InvalidMagic)? != SNAPSHOT_MAGIC {
+        return Err(SnapshotError::InvalidMagic);
+    }
+    let version = reader.read_u32("version")?;
+    if version == 0 || version > SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    Ok(version)
+}
@@ -0,0 +1,480 @@
+use std::collections::BTreeMap;
+
+use cgmath::InnerSpace;
+use rustc_hash::FxHashMap;
+
+use crate::Vf32x2;
+use crate::entity::handle::*;
+use crate::entity::entity_manager::*;
+use crate::entity::component_storage::*;
+use crate::entity::default_components::{Transform, RectRenderable};
+use crate::entity::hierarchy::{GlobalTransform, Parent};
+
+/**
+ * Component that gives an entity an extent in the SpatialGrid.
+ */
+pub trait SpatialExtent: Component {
+    /**
+     * Half of the width and height of the entity, before it is rotated by its world orientation.
+     */
+    fn half_extent(&self) -> Vf32x2;
+}
+
+impl SpatialExtent for RectRenderable {
+    fn half_extent(&self) -> Vf32x2 {
+        self.size * 0.5
+    }
+}
+
+type Cell = (i32, i32);
+
+#[derive(Clone, Copy)]
+struct SpatialEntry {
+    entity: EntityHandle,
+    min: Vf32x2,
+    max: Vf32x2,
+    min_cell: Cell,
+    max_cell: Cell,
+}
+
+/**
+ * Uniform grid over the axis aligned bounds of entities with a Transform and a SpatialExtent.
+ * Entities are listed in every cell their bounds overlap, cells are only allocated where entities are.
+ * Entities are placed by their GlobalTransform, root entities without one by their Transform.
+ * Entities with a Parent are left out until propagate_transforms gave them a GlobalTransform.
+ * update only moves the entities in the change logs of the stores since the previous update,
+ * so it has to run at least once every change tick, after propagate_transforms, for example right before cleanup.
+ * The change logs are turned on by log_changes, without them every update looks at every entity.
+ */
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: FxHashMap<Cell, Vec<EntityIndex>>,
+    entries: FxHashMap<EntityIndex, SpatialEntry>,
+    // number of occupied cells per column and row, their first and last keys are the occupied bounds
+    columns: BTreeMap<i32, usize>,
+    rows: BTreeMap<i32, usize>,
+    cursors: Option<[ChangeCursor; 4]>,
+}
+
+impl SpatialGrid {
+    #[allow(unused)]
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "cell size must be positive, was {}", cell_size);
+        Self{
+            cell_size,
+            cells: FxHashMap::default(),
+            entries: FxHashMap::default(),
+            columns: BTreeMap::new(),
+            rows: BTreeMap::new(),
+            cursors: None,
+        }
+    }
+
+    /**
+     * Turns on the change logs of the stores, so update only has to look at the changed entities.
+     */
+    #[allow(unused)]
+    pub fn log_changes<E: SpatialExtent>(
+        transforms: &mut LinearStore<Transform>,
+        globals: &mut LinearStore<GlobalTransform>,
+        parents: &mut DenseStore<Parent>,
+        extents: &mut E::Storage,
+    ) {
+        transforms.log_changes();
+        globals.log_changes();
+        parents.log_changes();
+        extents.log_changes();
+    }
+
+    #[allow(unused)]
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    /**
+     * Number of entities in the grid.
+     */
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[allow(unused)]
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.entries.clear();
+        self.columns.clear();
+        self.rows.clear();
+        self.cursors = None;
+    }
+
+    /**
+     * Brings the grid up to date with the stores.
+     * The first update and updates without change logs place every entity, later ones only the logged ones.
+     */
+    #[allow(unused)]
+    pub fn update<E: SpatialExtent>(
+        &mut self,
+        entities: &EntityManager,
+        transforms: &LinearStore<Transform>,
+        globals: &LinearStore<GlobalTransform>,
+        parents: &DenseStore<Parent>,
+        extents: &E::Storage,
+    ) {
+        let changes = self.cursors.and_then(|[transform, global, parent, extent]| {
+            Some([
+                transforms.changes_since(transform)?,
+                globals.changes_since(global)?,
+                parents.changes_since(parent)?,
+                extents.changes_since(extent)?,
+            ])
+        });
+        match changes {
+            // an entity that gained or lost a GlobalTransform or Parent is placed by a different transform now, so every log counts
+            Some(changes) => {
+                for index in changes.into_iter().flatten() {
+                    self.place::<E>(*index, entities, transforms, globals, parents, extents);
+                }
+            },
+            None => {
+                let placed = self.entries.keys().copied().collect::<Vec<_>>();
+                for index in placed.into_iter().chain(extents.iter_indices()) {
+                    self.place::<E>(index, entities, transforms, globals, parents, extents);
+                }
+            },
+        }
+        self.cursors = Some([transforms.change_cursor(), globals.change_cursor(), parents.change_cursor(), extents.change_cursor()]);
+    }
+
+    fn place<E: SpatialExtent>(
+        &mut self,
+        index: EntityIndex,
+        entities: &EntityManager,
+        transforms: &LinearStore<Transform>,
+        globals: &LinearStore<GlobalTransform>,
+        parents: &DenseStore<Parent>,
+        extents: &E::Storage,
+    ) {
+        match (extents.get(index), world_transform(index, transforms, globals, parents)) {
+            (Some(extent), Some(world)) => {
+                let entity = EntityHandle{ index, version: entities.version_of(index).unwrap_or(0) };
+                let (min, max) = bounds_of(&world, extent.half_extent());
+                self.insert(entity, min, max);
+            },
+            _ => self.remove(index),
+        }
+    }
+
+    /**
+     * Inserts or moves the entity, for entities that are not maintained by update.
+     */
+    #[allow(unused)]
+    pub fn insert(&mut self, entity: EntityHandle, min: Vf32x2, max: Vf32x2) {
+        let min_cell = self.cell_of(min);
+        let max_cell = self.cell_of(max);
+        if let Some(old) = self.entries.get(&entity.index) {
+            if old.min_cell == min_cell && old.max_cell == max_cell {
+                self.entries.insert(entity.index, SpatialEntry{ entity, min, max, min_cell, max_cell });
+                return;
+            }
+            self.remove(entity.index);
+        }
+        for_each_cell(min_cell, max_cell, |cell| {
+            let list = self.cells.entry(cell).or_default();
+            if list.is_empty() {
+                *self.columns.entry(cell.0).or_default() += 1;
+                *self.rows.entry(cell.1).or_default() += 1;
+            }
+            list.push(entity.index);
+        });
+        self.entries.insert(entity.index, SpatialEntry{ entity, min, max, min_cell, max_cell });
+    }
+
+    #[allow(unused)]
+    pub fn remove(&mut self, index: EntityIndex) {
+        if let Some(entry) = self.entries.remove(&index) {
+            for_each_cell(entry.min_cell, entry.max_cell, |cell| {
+                if let Some(list) = self.cells.get_mut(&cell) {
+                    list.retain(|i| *i != index);
+                    if list.is_empty() {
+                        self.cells.remove(&cell);
+                        release(&mut self.columns, cell.0);
+                        release(&mut self.rows, cell.1);
+                    }
+                }
+            });
+        }
+    }
+
+    /**
+     * The first and the last occupied cell in both directions, shrinks when entities are removed.
+     */
+    fn bounds(&self) -> Option<(Cell, Cell)> {
+        let (x0, x1) = (*self.columns.first_key_value()?.0, *self.columns.last_key_value()?.0);
+        let (y0, y1) = (*self.rows.first_key_value()?.0, *self.rows.last_key_value()?.0);
+        Some(((x0, y0), (x1, y1)))
+    }
+
+    /**
+     * All entities whose bounds overlap the box.
+     */
+    #[allow(unused)]
+    pub fn query_aabb(&self, min: Vf32x2, max: Vf32x2) -> Vec<EntityHandle> {
+        let mut found = Vec::new();
+        self.for_each_in_aabb(min, max, |entry| found.push(entry.entity));
+        found
+    }
+
+    /**
+     * All entities whose bounds are at most radius away from center.
+     */
+    #[allow(unused)]
+    pub fn query_radius(&self, center: Vf32x2, radius: f32) -> Vec<EntityHandle> {
+        let mut found = Vec::new();
+        let offset = Vf32x2::new(radius, radius);
+        self.for_each_in_aabb(center - offset, center + offset, |entry| {
+            if distance_to(entry, center) <= radius {
+                found.push(entry.entity);
+            }
+        });
+        found
+    }
+
+    /**
+     * The entity whose bounds are closest to the point, together with the distance.
+     * Entities that contain the point have distance zero.
+     */
+    #[allow(unused)]
+    pub fn nearest(&self, point: Vf32x2, max_distance: f32) -> Option<(EntityHandle, f32)> {
+        self.nearest_where(point, max_distance, |_| true)
+    }
+
+    /**
+     * The closest entity for which filter returns true, for example to exclude the entity searching.
+     */
+    #[allow(unused)]
+    pub fn nearest_where(&self, point: Vf32x2, max_distance: f32, filter: impl Fn(EntityHandle) -> bool) -> Option<(EntityHandle, f32)> {
+        let (lo, hi) = self.bounds()?;
+        let center = self.cell_of(point);
+        // ring math is done in i64, cells of points far outside the grid saturate at the ends of i32
+        let (center, lo, hi) = (widen(center), widen(lo), widen(hi));
+        let gap = |c: i64, lo: i64, hi: i64| (lo - c).max(c - hi).max(0);
+        // rings closer than the occupied bounds and rings beyond them are empty
+        let first_ring = gap(center.0, lo.0, hi.0).max(gap(center.1, lo.1, hi.1));
+        let last_ring = [lo.0 - center.0, hi.0 - center.0, lo.1 - center.1, hi.1 - center.1].iter()
+            .map(|d| d.abs())
+            .max()
+            .unwrap();
+        let mut best: Option<(EntityHandle, f32)> = None;
+        for ring in first_ring..=last_ring {
+            // every cell of this ring is at least ring - 1 cells away from the point
+            let ring_distance = (ring - 1).max(0) as f32 * self.cell_size;
+            if ring_distance > max_distance || best.map_or(false, |(_, d)| ring_distance > d) {
+                break;
+            }
+            for_each_ring_cell(center, ring, lo, hi, |cell| {
+                for index in self.cells.get(&cell).into_iter().flatten() {
+                    let entry = &self.entries[index];
+                    let distance = distance_to(entry, point);
+                    if distance <= max_distance && best.map_or(true, |(_, d)| distance < d) && filter(entry.entity) {
+                        best = Some((entry.entity, distance));
+                    }
+                }
+            });
+        }
+        best
+    }
+
+    /**
+     * The first entity hit by the ray, together with the distance along the ray.
+     * A ray starting inside an entity hits it at distance zero.
+     */
+    #[allow(unused)]
+    pub fn raycast(&self, origin: Vf32x2, direction: Vf32x2, max_distance: f32) -> Option<(EntityHandle, f32)> {
+        let (lo, hi) = self.bounds()?;
+        if direction.magnitude2() == 0.0 {
+            return None;
+        }
+        let direction = direction.normalize();
+        let grid_min = Vf32x2::new(lo.0 as f32, lo.1 as f32) * self.cell_size;
+        let grid_max = Vf32x2::new(hi.0 as f32 + 1.0, hi.1 as f32 + 1.0) * self.cell_size;
+        let (enter, exit) = ray_box(origin, direction, grid_min, grid_max)?;
+        let end = exit.min(max_distance);
+        if enter > end {
+            return None;
+        }
+
+        // walk the cells along the ray, starting where it enters the occupied part of the grid
+        let start = origin + direction * enter;
+        let mut cell = self.cell_of(start);
+        let step = (direction.x.signum() as i32, direction.y.signum() as i32);
+        let boundary = |c: i32, s: i32| if s > 0 { (c as f32 + 1.0) * self.cell_size } else { c as f32 * self.cell_size };
+        let mut t_max = Vf32x2::new(
+            if direction.x != 0.0 { (boundary(cell.0, step.0) - origin.x) / direction.x } else { f32::INFINITY },
+            if direction.y != 0.0 { (boundary(cell.1, step.1) - origin.y) / direction.y } else { f32::INFINITY },
+        );
+        let t_delta = Vf32x2::new(self.cell_size / direction.x.abs(), self.cell_size / direction.y.abs());
+
+        let mut best: Option<(EntityHandle, f32)> = None;
+        let mut t = enter;
+        while t <= end {
+            for index in self.cells.get(&cell).into_iter().flatten() {
+                let entry = &self.entries[index];
+                if let Some((hit, _)) = ray_box(origin, direction, entry.min, entry.max) {
+                    let hit = hit.max(0.0);
+                    if hit <= max_distance && best.map_or(true, |(_, d)| hit < d) {
+                        best = Some((entry.entity, hit));
+                    }
+                }
+            }
+            let cell_exit = t_max.x.min(t_max.y);
+            if best.map_or(false, |(_, d)| d <= cell_exit) {
+                break;
+            }
+            if t_max.x < t_max.y {
+                cell.0 = cell.0.saturating_add(step.0);
+                t = t_max.x;
+                t_max.x += t_delta.x;
+            } else {
+                cell.1 = cell.1.saturating_add(step.1);
+                t = t_max.y;
+                t_max.y += t_delta.y;
+            }
+        }
+        best
+    }
+
+    fn for_each_in_aabb(&self, min: Vf32x2, max: Vf32x2, mut f: impl FnMut(&SpatialEntry)) {
+        let (lo, hi) = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        // only the occupied part of the box is walked
+        let min_cell = self.cell_of(min);
+        let max_cell = self.cell_of(max);
+        let min_cell = (min_cell.0.max(lo.0), min_cell.1.max(lo.1));
+        let max_cell = (max_cell.0.min(hi.0), max_cell.1.min(hi.1));
+        for_each_cell(min_cell, max_cell, |cell| {
+            for index in self.cells.get(&cell).into_iter().flatten() {
+                let entry = &self.entries[index];
+                // entries spanning several cells are only reported in the first cell both ranges share
+                let first = (entry.min_cell.0.max(min_cell.0), entry.min_cell.1.max(min_cell.1));
+                if first == cell && entry.min.x <= max.x && entry.max.x >= min.x && entry.min.y <= max.y && entry.max.y >= min.y {
+                    f(entry);
+                }
+            }
+        });
+    }
+
+    fn cell_of(&self, point: Vf32x2) -> Cell {
+        ((point.x / self.cell_size).floor() as i32, (point.y / self.cell_size).floor() as i32)
+    }
+}
+
+/**
+ * The transform the entity is placed by.
+ * None for entities without a Transform and for children that were not propagated yet.
+ */
+fn world_transform(index: EntityIndex, transforms: &LinearStore<Transform>, globals: &LinearStore<GlobalTransform>, parents: &DenseStore<Parent>) -> Option<GlobalTransform> {
+    let local = transforms.get(index)?;
+    match globals.get(index) {
+        Some(global) => Some(*global),
+        None if !parents.has(index) => Some(GlobalTransform::from_local(local)),
+        None => None,
+    }
+}
+
+/**
+ * Axis aligned bounds of a rectangle rotated by the orientation of the transform.
+ */
+fn bounds_of(transform: &GlobalTransform, half: Vf32x2) -> (Vf32x2, Vf32x2) {
+    let rotation = if transform.orientation.magnitude2() > 0.0 {
+        transform.orientation.normalize()
+    } else {
+        Vf32x2::new(1.0, 0.0)
+    };
+    let extent = Vf32x2::new(
+        rotation.x.abs() * half.x + rotation.y.abs() * half.y,
+        rotation.y.abs() * half.x + rotation.x.abs() * half.y,
+    );
+    (transform.position - extent, transform.position + extent)
+}
+
+fn distance_to(entry: &SpatialEntry, point: Vf32x2) -> f32 {
+    let dx = (entry.min.x - point.x).max(point.x - entry.max.x).max(0.0);
+    let dy = (entry.min.y - point.y).max(point.y - entry.max.y).max(0.0);
+    (dx * dx + dy * dy).sqrt()
+}
+
+/**
+ * Distances along the ray at which it enters and leaves the box, None if it misses the box.
+ */
+fn ray_box(origin: Vf32x2, direction: Vf32x2, min: Vf32x2, max: Vf32x2) -> Option<(f32, f32)> {
+    let mut enter = 0.0f32;
+    let mut exit = f32::INFINITY;
+    for (o, d, lo, hi) in [(origin.x, direction.x, min.x, max.x), (origin.y, direction.y, min.y, max.y)] {
+        if d == 0.0 {
+            if o < lo || o > hi {
+                return None;
+            }
+        } else {
+            let (a, b) = ((lo - o) / d, (hi - o) / d);
+            enter = enter.max(a.min(b));
+            exit = exit.min(a.max(b));
+        }
+    }
+    if enter <= exit { Some((enter, exit)) } else { None }
+}
+
+fn for_each_cell(min: Cell, max: Cell, mut f: impl FnMut(Cell)) {
+    for x in min.0..=max.0 {
+        for y in min.1..=max.1 {
+            f((x, y));
+        }
+    }
+}
+
+fn release(counts: &mut BTreeMap<i32, usize>, key: i32) {
+    if let Some(count) = counts.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+fn widen(cell: Cell) -> (i64, i64) {
+    (cell.0 as i64, cell.1 as i64)
+}
+
+/**
+ * Visits the cells of the ring around center that lie within lo and hi, those fit into a Cell.
+ */
+fn for_each_ring_cell(center: (i64, i64), ring: i64, lo: (i64, i64), hi: (i64, i64), mut f: impl FnMut(Cell)) {
+    let inside = |x: i64, y: i64| lo.0 <= x && x <= hi.0 && lo.1 <= y && y <= hi.1;
+    if ring == 0 {
+        if inside(center.0, center.1) {
+            f((center.0 as i32, center.1 as i32));
+        }
+        return;
+    }
+    for y in [center.1 - ring, center.1 + ring] {
+        if lo.1 <= y && y <= hi.1 {
+            for x in (center.0 - ring).max(lo.0)..=(center.0 + ring).min(hi.0) {
+                f((x as i32, y as i32));
+            }
+        }
+    }
+    for x in [center.0 - ring, center.0 + ring] {
+        if lo.0 <= x && x <= hi.0 {
+            for y in (center.1 - ring + 1).max(lo.1)..=(center.1 + ring - 1).min(hi.1) {
+                f((x as i32, y as i32));
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use async_std::sync::RwLock;
+use rustc_hash::FxHashMap;
+
+use crate::entity::handle::*;
+use crate::entity::component_manager::*;
+
+/**
+ * Maps the handles of migrated entities in the source world to their handles in the target world.
+ */
+#[derive(Clone, Default)]
+pub struct EntityMapping {
+    map: FxHashMap<EntityHandle, EntityHandle>,
+}
+
+impl EntityMapping {
+    pub(crate) fn insert(&mut self, from: EntityHandle, to: EntityHandle) {
+        self.map.insert(from, to);
+    }
+
+    /**
+     * The handle in the target world of an entity from the source world.
+     */
+    #[allow(unused)]
+    pub fn get(&self, from: EntityHandle) -> Option<EntityHandle> {
+        self.map.get(&from).copied()
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[allow(unused)]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /**
+     * Pairs of source and target handles, in no particular order.
+     */
+    #[allow(unused)]
+    pub fn iter(&self) -> impl Iterator<Item = (EntityHandle, EntityHandle)> + '_ {
+        self.map.iter().map(|(from, to)| (*from, *to))
+    }
+}
+
+/**
+ * Named worlds next to the main world of the app, for example for loading levels in the background or for previews.
+ * Every world is an independent EntityComponentManager, the app only cleans up its main world,
+ * the owner of an additional world calls cleanup on it.
+ */
+#[derive(Default)]
+pub struct Worlds {
+    worlds: RwLock<FxHashMap<String, Arc<EntityComponentManager>>>,
+}
+
+impl Worlds {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Creates an empty world, the name must not be taken yet.
+     */
+    #[allow(unused)]
+    pub async fn create(&self, name: &str) -> Arc<EntityComponentManager> {
+        let mut worlds = self.worlds.write().await;
+        assert!(!worlds.contains_key(name), "World {} allready exists.", name);
+        let world = Arc::new(EntityComponentManager::new());
+        worlds.insert(name.to_string(), world.clone());
+        world
+    }
+
+    #[allow(unused)]
+    pub async fn get(&self, name: &str) -> Option<Arc<EntityComponentManager>> {
+        self.worlds.read().await.get(name).cloned()
+    }
+
+    /**
+     * Removes the world, it is dropped once all other references to it are gone.
+     */
+    #[allow(unused)]
+    pub async fn remove(&self, name: &str) -> Option<Arc<EntityComponentManager>> {
+        self.worlds.write().await.remove(name)
+    }
+
+    #[allow(unused)]
+    pub async fn names(&self) -> Vec<String> {
+        self.worlds.read().await.keys().cloned().collect()
+    }
+}
@@ -24,6 +24,8 @@ mod tests {
     use crate::entity::{EntityComponentManager};
 
     use super::*;
+    #[allow(unused)]
+    use cgmath::InnerSpace;
 
     #[test]
     fn ecm_par_iter_works() {
@@ -108,6 +110,2182 @@ mod tests {
         block_on(waiter);
     }
 
+    #[test]
+    fn custom_store_registration_works() {
+        use std::any::Any;
+        use entity::handle::EntityIndex;
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Flag(u8);
+
+        struct VecStore<T: Default + Clone> {
+            values: Vec<Option<T>>,
+        }
+
+        impl<T: 'static + Default + Clone> GenericComponentStore for VecStore<T> {
+            fn optimize(&mut self) {}
+            fn as_any(&self) -> &dyn Any { self }
+            fn as_any_mut(&mut self) -> &mut dyn Any { self }
+            fn has(&self, index: EntityIndex) -> bool {
+                matches!(self.values.get(index as usize), Some(Some(_)))
+            }
+            fn rem(&mut self, index: EntityIndex) {
+                self.values[index as usize] = None;
+            }
+            fn len(&self) -> usize {
+                self.values.iter().filter(|v| v.is_some()).count()
+            }
+        }
+
+        impl<T: 'static + Default + Clone + entity::Component> ComponentStore<T> for VecStore<T> {
+            type ComponentType = T;
+            fn new() -> Self { Self{ values: Vec::new() } }
+            fn get(&self, index: EntityIndex) -> Option<&T> {
+                self.values.get(index as usize)?.as_ref()
+            }
+            fn get_mut(&mut self, index: EntityIndex) -> Option<&mut T> {
+                self.values.get_mut(index as usize)?.as_mut()
+            }
+            fn set(&mut self, index: EntityIndex, value: T) {
+                self.values[index as usize] = Some(value);
+            }
+            fn add(&mut self, index: EntityIndex, value: T) {
+                if self.values.len() <= index as usize {
+                    self.values.resize(index as usize + 1, None);
+                }
+                self.values[index as usize] = Some(value);
+            }
+        }
+
+        impl entity::Component for Flag {
+            type Storage = VecStore<Self>;
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let (a, b) = {
+                get_components_mut!(ecm; Flag => flags);
+                get_entities_mut!(ecm; entities);
+                let a = entities.create();
+                let b = entities.create();
+                entities.add(flags, Flag(1), a);
+                entities.add(flags, Flag(2), b);
+                entities.destroy(a);
+                (a, b)
+            };
+            ecm.cleanup().await;
+
+            get_components!(ecm; Flag => flags);
+            assert!(!flags.has(a.index));
+            assert_eq!(flags.get(b.index), Some(&Flag(2)));
+            assert_eq!(flags.len(), 1);
+        });
+    }
+
+    #[test]
+    fn table_store_join_works() {
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Speed(f32);
+
+        impl entity::Component for Speed {
+            type Storage = entity::TableStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Distance(f32);
+
+        impl entity::Component for Distance {
+            type Storage = entity::TableStore<Self>;
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let mut handles = Vec::new();
+            {
+                get_components_mut!(ecm; Speed, Distance => speeds, distances);
+                get_entities_mut!(ecm; entities);
+                for i in 0..100 {
+                    let entity = entities.create();
+                    entities.add(speeds, Speed(i as f32), entity);
+                    if i % 3 == 0 {
+                        entities.add(distances, Distance(0.0), entity);
+                    }
+                    handles.push(entity);
+                }
+                for i in (0..100).step_by(9) {
+                    entities.rem(speeds, handles[i]);
+                }
+                for i in (0..100).step_by(9) {
+                    entities.destroy(handles[i]);
+                }
+            }
+            ecm.cleanup().await;
+
+            get_components_mut!(ecm; Speed, Distance => speeds, distances);
+            get_entities!(ecm; entities);
+            let mut count = 0;
+            iterate_over_entities!(entities: entities; stores: mut distances, speeds)
+                .for_each(|(entity, distance, speed): (EntityHandle, &mut Distance, &Speed)| {
+                    assert_eq!(speed.0, entity.index as f32);
+                    distance.0 += speed.0;
+                    count += 1;
+                });
+            assert_eq!(count, (0..100).filter(|i| i % 3 == 0 && i % 9 != 0).count());
+            for i in 0..100 {
+                if i % 9 == 0 {
+                    continue;
+                }
+                let expected = if i % 3 == 0 { Some(&Distance(i as f32)) } else { None };
+                assert_eq!(distances.get(handles[i].index), expected);
+                assert_eq!(speeds.get(handles[i].index), Some(&Speed(i as f32)));
+            }
+            assert_eq!(iterate_over_entities!(stores: speeds, not distances).count(), (0..100).filter(|i| i % 3 != 0).count());
+
+            // synced table stores are zipped over their joined columns, sequentially and in parallel
+            let stores: [&dyn entity::GenericComponentStore; 2] = [&*distances, &*speeds];
+            let join = entity::iteration::TableJoin::new(&stores).unwrap();
+            assert_eq!(join.position_count(), count);
+            assert_eq!(join.ranges(0, 0..join.position_count()).map(|rows| rows.len()).sum::<usize>(), count);
+            let runtime = Runtime::new();
+            parallel_over_entities!(
+                runtime: runtime;
+                batch_size: 4;
+                closure: |(_, distance, speed): (EntityHandle, &mut Distance, &Speed)| {
+                    distance.0 += speed.0;
+                };
+                entities: entities;
+                stores: mut distances, speeds
+            ).await;
+            assert!(iterate_over_entities!(entities: entities; stores: distances, speeds).all(|(entity, distance, speed)| {
+                distance.0 == 2.0 * speed.0 && speed.0 == entity.index as f32
+            }));
+        });
+    }
+
+    #[test]
+    fn snapshot_round_trip_works() {
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Name(String);
+
+        impl entity::Component for Name {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        impl entity::SnapshotComponent for Name {
+            const NAME: &'static str = "Name";
+
+            fn save(&self, writer: &mut dyn entity::SnapshotWriter) {
+                writer.write_str("name", &self.0);
+            }
+
+            fn load(reader: &mut dyn entity::SnapshotReader) -> Result<Self, entity::SnapshotError> {
+                Ok(Name(reader.read_string("name")?))
+            }
+        }
+
+        use entity::Transform;
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            ecm.register_snapshot_component::<Name>().await;
+            ecm.register_snapshot_component::<Transform>().await;
+
+            let mut handles = Vec::new();
+            {
+                get_components_mut!(ecm; Name, Transform => names, transforms);
+                get_entities_mut!(ecm; entities);
+                for i in 0..300 {
+                    let entity = entities.create();
+                    entities.add(names, Name(format!("entity \"{}\"\n", i)), entity);
+                    if i % 2 == 0 {
+                        entities.add(transforms, Transform{ position: Vf32x2::new(i as f32, 0.5), orientation: Vf32x2::new(1.0, 0.0) }, entity);
+                    }
+                    handles.push(entity);
+                }
+                for i in (0..300).step_by(7) {
+                    entities.destroy(handles[i]);
+                }
+            }
+            ecm.cleanup().await;
+            {
+                get_entities_mut!(ecm; entities);
+                handles.push(entities.create());
+            }
+
+            let binary = ecm.save_snapshot_binary().await;
+            let text = ecm.save_snapshot_text().await;
+
+            for load in 0..2 {
+                let loaded = EntityComponentManager::new();
+                loaded.register_snapshot_component::<Name>().await;
+                loaded.register_snapshot_component::<Transform>().await;
+                if load == 0 {
+                    loaded.load_snapshot_binary(&binary).await.unwrap();
+                } else {
+                    loaded.load_snapshot_text(&text).await.unwrap();
+                }
+                assert_eq!(loaded.save_snapshot_text().await, text);
+
+                get_components!(loaded; Name, Transform => names, transforms);
+                get_entities!(loaded; entities);
+                for (i, handle) in handles.iter().enumerate().take(300) {
+                    assert_eq!(entities.exists(*handle), i % 7 != 0);
+                    if i % 7 != 0 {
+                        assert_eq!(entities.get(names, *handle), Some(&Name(format!("entity \"{}\"\n", i))));
+                        assert_eq!(entities.get(transforms, *handle).map(|t| t.position.x), if i % 2 == 0 { Some(i as f32) } else { None });
+                    }
+                }
+                assert!(entities.exists(handles[300]));
+            }
+
+            assert_eq!(EntityComponentManager::new().load_snapshot_text(&text).await, Err(entity::SnapshotError::UnknownComponent(String::from("Name"))));
+            assert_eq!(ecm.load_snapshot_binary(&binary[..binary.len() - 1]).await, Err(entity::SnapshotError::UnexpectedEnd));
+        });
+    }
+
+    #[test]
+    fn hierarchy_works() {
+        use entity::{Transform, GlobalTransform, Parent, Children};
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let (tank, turret, barrel, other) = {
+                get_components_mut!(ecm; Transform, GlobalTransform, Parent, Children => transforms, globals, parents, children);
+                get_entities_mut!(ecm; entities);
+                let tank = entities.create();
+                let turret = entities.create();
+                let barrel = entities.create();
+                let other = entities.create();
+                entities.add(transforms, Transform{ position: Vf32x2::new(10.0, 0.0), orientation: Vf32x2::new(0.0, 1.0) }, tank);
+                entities.add(transforms, Transform{ position: Vf32x2::new(1.0, 0.0), orientation: Vf32x2::new(0.0, 0.0) }, turret);
+                entities.add(transforms, Transform{ position: Vf32x2::new(2.0, 0.0), orientation: Vf32x2::new(0.0, 0.0) }, barrel);
+                entities.set_parent(parents, children, turret, tank);
+                entities.set_parent(parents, children, barrel, turret);
+                entities.set_parent(parents, children, other, turret);
+                entities.set_parent(parents, children, other, tank);
+
+                entity::propagate_transforms(transforms, globals, parents, children);
+                let turret_global = entities.get(globals, turret).unwrap();
+                assert!((turret_global.position - Vf32x2::new(10.0, 1.0)).magnitude() < 1e-5);
+                let barrel_global = entities.get(globals, barrel).unwrap();
+                assert!((barrel_global.position - Vf32x2::new(10.0, 3.0)).magnitude() < 1e-5);
+                assert_eq!(children.get(turret.index).unwrap().0.len(), 1);
+
+                entities.destroy(turret);
+                (tank, turret, barrel, other)
+            };
+            ecm.cleanup().await;
+
+            get_components!(ecm; Parent, Children => parents, children);
+            get_entities!(ecm; entities);
+            assert!(!entities.exists(turret) && !entities.exists(barrel));
+            assert!(entities.exists(tank) && entities.exists(other));
+            let tank_children = &children.get(tank.index).unwrap().0;
+            assert_eq!(tank_children.len(), 1);
+            assert_eq!(tank_children[0].index, other.index);
+            assert_eq!(parents.get(other.index).unwrap().0.index, tank.index);
+        });
+    }
+
+    #[test]
+    fn change_detection_works() {
+        use entity::Transform;
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Score(u32);
+
+        impl entity::Component for Score {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let mut handles = Vec::new();
+            {
+                get_components_mut!(ecm; Score, Transform => scores, transforms);
+                get_entities_mut!(ecm; entities);
+                for i in 0..10 {
+                    let entity = entities.create();
+                    entities.add(scores, Score(i), entity);
+                    entities.add(transforms, Transform::default(), entity);
+                    handles.push(entity);
+                }
+                assert_eq!(iterate_over_entities!(stores: transforms, added scores).count(), 10);
+            }
+            ecm.advance_tick().await;
+            ecm.advance_tick().await;
+            {
+                get_components_mut!(ecm; Score, Transform => scores, transforms);
+                get_entities_mut!(ecm; entities);
+                assert_eq!(iterate_over_entities!(stores: transforms, added scores).count(), 0);
+                assert_eq!(iterate_over_entities!(stores: scores, changed transforms).count(), 0);
+
+                entities.get_mut(scores, handles[3]).unwrap().0 += 1;
+                transforms.set(handles[5].index, Transform::default());
+                entities.rem(scores, handles[7]);
+
+                let changed = iterate_over_entities!(entities: entities; stores: transforms, changed scores)
+                    .map(|(entity, _)| entity.index)
+                    .collect::<Vec<_>>();
+                assert_eq!(changed, vec![handles[3].index]);
+                assert_eq!(iterate_over_entities!(stores: scores, changed transforms).count(), 1);
+                assert_eq!(iterate_over_entities!(stores: transforms, removed scores).count(), 1);
+                assert!(scores.was_removed(handles[7].index));
+            }
+            ecm.advance_tick().await;
+            {
+                get_components!(ecm; Score => scores);
+                assert!(scores.is_changed(handles[3].index));
+                assert!(scores.was_removed(handles[7].index));
+            }
+            ecm.advance_tick().await;
+            get_components!(ecm; Score => scores);
+            assert!(!scores.is_changed(handles[3].index));
+            assert!(!scores.was_removed(handles[7].index));
+        });
+    }
+
+    #[test]
+    fn commands_work() {
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Marker;
+
+        impl entity::Component for Marker {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        let runtime = Arc::new(Runtime::new());
+        let waiter = sync::AtomicWaiter::new();
+        let dep = waiter.make_dependency();
+
+        let rt_clone = runtime.clone();
+        let task = async {
+            let _d = dep;
+            let runtime = rt_clone;
+            let ecm = EntityComponentManager::new();
+            {
+                get_components_mut!(ecm; Health => healths);
+                get_entities_mut!(ecm; entities);
+                for i in 0..100 {
+                    let entity = entities.create();
+                    entities.add(healths, Health(i), entity);
+                }
+                let commands = &ecm.commands;
+                parallel_over_entities!(
+                    runtime: runtime;
+                    batch_size: 16;
+                    closure: |(entity, health): (EntityHandle, &mut Health)| {
+                        if health.0 < 50 {
+                            commands.destroy(entity);
+                        } else if health.0 % 2 == 0 {
+                            commands.add(entity, Marker);
+                        }
+                        if health.0 % 10 == 0 {
+                            let spawned = commands.create(entities);
+                            commands.add(spawned, Health(1000 + health.0));
+                            commands.rem::<Health>(entity);
+                        }
+                    };
+                    entities: entities;
+                    commands: commands;
+                    stores: mut healths
+                ).await;
+                assert_eq!(ecm.commands.len(), 50 + 25 + 2 * 10);
+            }
+            ecm.cleanup().await;
+
+            get_components!(ecm; Health, Marker => healths, markers);
+            get_entities!(ecm; entities);
+            assert!(ecm.commands.is_empty());
+            assert_eq!(healths.iter().filter(|h| h.0 < 1000).count(), 50 - 5);
+            assert_eq!(healths.iter().filter(|h| h.0 >= 1000).count(), 10);
+            assert_eq!(markers.iter().count(), 25);
+            for (index, health) in healths.iter_entity() {
+                assert!(entities.version_of(index).is_some());
+                assert!(health.0 >= 50);
+            }
+        };
+
+        runtime.spawn_prioritised(task, sync::task::Priority::VeryHigh);
+        block_on(waiter);
+    }
+
+    #[test]
+    fn query_works() {
+        use entity::{Query, Without, Changed};
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Pos(f32);
+
+        impl entity::Component for Pos {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Tag;
+
+        impl entity::Component for Tag {
+            type Storage = entity::TableStore<Self>;
+        }
+
+        let runtime = Arc::new(Runtime::new());
+        let waiter = sync::AtomicWaiter::new();
+        let dep = waiter.make_dependency();
+
+        let rt_clone = runtime.clone();
+        let task = async {
+            let _d = dep;
+            let runtime = rt_clone;
+            let ecm = EntityComponentManager::new();
+            get_components_mut!(ecm; Health, Pos, Tag => healths, positions, tags);
+            get_entities_mut!(ecm; entities);
+            for i in 0..1000 {
+                let entity = entities.create();
+                entities.add(healths, Health(i), entity);
+                if i % 2 == 0 {
+                    entities.add(positions, Pos(i as f32), entity);
+                }
+                if i % 5 == 0 {
+                    entities.add(tags, Tag, entity);
+                }
+            }
+
+            {
+                let mut query = Query::<(&mut Health, Option<&Pos>), Without<Tag>>::new((&mut *healths, &*positions), &*tags);
+                assert_eq!(query.count(), 800);
+                query.for_each(|(health, pos)| {
+                    health.0 += pos.map_or(0, |pos| pos.0 as u32);
+                });
+                let mut batches = 0;
+                for batch in query.iter_batch(100) {
+                    batches += 1;
+                    for (index, (health, pos)) in batch {
+                        assert!(index % 5 != 0);
+                        assert_eq!(health.0, if pos.is_some() { 2 * index } else { index });
+                    }
+                }
+                assert!(batches >= 8);
+                for (entity, _) in query.iter_handles(entities) {
+                    assert!(entities.exists(entity));
+                }
+            }
+
+            {
+                let mut query = Query::<(&mut Health, &Pos)>::new((&mut *healths, &*positions), ());
+                query.par_for_each(&runtime, 64, |index, (health, pos)| {
+                    assert_eq!(index as f32, pos.0);
+                    health.0 += 1;
+                });
+            }
+
+            let mut query = Query::<(&Pos, &Health), Changed<Tag>>::new((&*positions, &*healths), &*tags);
+            assert_eq!(query.count(), 100);
+            for (pos, health) in query.iter() {
+                assert_eq!(health.0, pos.0 as u32 + 1);
+            }
+        };
+
+        runtime.spawn_prioritised(task, sync::task::Priority::VeryHigh);
+        block_on(waiter);
+    }
+
+    #[test]
+    fn resources_work() {
+        #[derive(Debug, PartialEq)]
+        struct Score(u32);
+
+        struct Camera {
+            zoom: f32,
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            assert!(!ecm.has_resource::<Score>().await);
+            assert_eq!(ecm.insert_resource(Score(1)).await, None);
+            ecm.insert_resource(Camera{ zoom: 1.0 }).await;
+            {
+                get_resources_mut!(ecm; Score, Camera => score, camera);
+                score.0 += 10;
+                camera.zoom *= 2.0;
+            }
+            {
+                get_resources!(ecm; Score, Camera => score, camera);
+                assert_eq!(score.0, 11);
+                assert_eq!(camera.zoom, 2.0);
+                let other_reader = ecm.get_resource::<Score>().await;
+                assert_eq!(*other_reader, Score(11));
+            }
+            assert_eq!(ecm.insert_resource(Score(5)).await, Some(Score(11)));
+            assert_eq!(ecm.get_resource::<Score>().await.0, 5);
+            assert!(ecm.remove_resource::<Score>().await);
+            assert!(!ecm.has_resource::<Score>().await);
+            assert!(ecm.has_resource::<Camera>().await);
+
+            // concurrent first inserts: exactly one inserts, the other replaces and gets the first value back
+            let (a, b) = futures::join!(ecm.insert_resource(Score(20)), ecm.insert_resource(Score(30)));
+            assert!(matches!((&a, &b), (None, Some(Score(20))) | (Some(Score(30)), None)));
+            let kept = ecm.get_resource::<Score>().await.0;
+            assert!(kept == 20 || kept == 30);
+            assert_ne!(Some(Score(kept)), a.or(b));
+        });
+    }
+
+    #[test]
+    fn events_work() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Damage(u32);
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let events = ecm.events::<Damage>().await;
+            let mut fixed_reader = events.reader();
+            ecm.send_event(Damage(1)).await;
+            events.send(Damage(2));
+            let mut variable_reader = events.reader();
+            events.send(Damage(3));
+
+            assert_eq!(fixed_reader.read(&events), vec![Damage(1), Damage(2), Damage(3)]);
+            assert_eq!(fixed_reader.read(&events), vec![]);
+            ecm.update_events().await;
+            events.send(Damage(4));
+            assert_eq!(fixed_reader.read(&events), vec![Damage(4)]);
+            assert_eq!(variable_reader.read(&events), vec![Damage(3), Damage(4)]);
+            assert_eq!(entity::EventReader::default().read(&events), vec![Damage(1), Damage(2), Damage(3), Damage(4)]);
+
+            ecm.update_events().await;
+            assert_eq!(entity::EventReader::default().read(&events), vec![Damage(4)]);
+            ecm.update_events().await;
+            assert!(ecm.events::<Damage>().await.is_empty());
+        });
+    }
+
+    #[test]
+    fn schedule_works() {
+        use std::sync::Mutex;
+        use async_std::channel::{Sender, Receiver, unbounded};
+
+        struct Position;
+        struct Velocity;
+
+        struct Context {
+            log: Mutex<Vec<&'static str>>,
+            a_to_b: (Sender<()>, Receiver<()>),
+            b_to_a: (Sender<()>, Receiver<()>),
+        }
+
+        impl Default for Context {
+            fn default() -> Self {
+                Self{ log: Mutex::new(Vec::new()), a_to_b: unbounded(), b_to_a: unbounded() }
+            }
+        }
+
+        let mut schedule = Schedule::<Context>::new();
+        schedule.add_system(System::new("integrate", |ctx: Arc<Context>| async move {
+            ctx.log.lock().unwrap().push("integrate");
+        }).writes::<Position>().reads::<Velocity>()).unwrap();
+        schedule.add_system(System::new("accelerate", |ctx: Arc<Context>| async move {
+            ctx.log.lock().unwrap().push("accelerate");
+        }).writes::<Velocity>().before("integrate")).unwrap();
+        let unordered = schedule.add_system(System::new("render", |ctx: Arc<Context>| async move {
+            ctx.log.lock().unwrap().push("render");
+        }).reads::<Position>());
+        assert!(matches!(unordered, Err(ScheduleError::UnorderedConflict(AccessConflict{ ref first, .. })) if first == "integrate"));
+        schedule.add_system(System::new("render", |ctx: Arc<Context>| async move {
+            ctx.log.lock().unwrap().push("render");
+        }).reads::<Position>().after("integrate")).unwrap();
+        // a and b wait for each other, so they only finish if they run at the same time
+        schedule.add_system(System::new("a", |ctx: Arc<Context>| async move {
+            ctx.a_to_b.0.send(()).await.unwrap();
+            ctx.b_to_a.1.recv().await.unwrap();
+        })).unwrap();
+        schedule.add_system(System::new("b", |ctx: Arc<Context>| async move {
+            ctx.b_to_a.0.send(()).await.unwrap();
+            ctx.a_to_b.1.recv().await.unwrap();
+        }).after("accelerate")).unwrap();
+
+        assert_eq!(schedule.add_system(System::new("a", |_: Arc<Context>| async {})), Err(ScheduleError::DuplicateSystem(String::from("a"))));
+        let conflicts = schedule.conflicts().iter().map(|c| (c.first.as_str(), c.second.as_str())).collect::<Vec<_>>();
+        assert_eq!(conflicts, vec![("integrate", "accelerate"), ("integrate", "render")]);
+        schedule.build().unwrap();
+
+        let runtime = Arc::new(Runtime::new());
+        let waiter = sync::AtomicWaiter::new();
+        let dep = waiter.make_dependency();
+        let schedule = Arc::new(schedule);
+        let context = Arc::new(Context::default());
+        let rt_clone = runtime.clone();
+        let schedule_clone = schedule.clone();
+        let context_clone = context.clone();
+        runtime.spawn(async move {
+            let _d = dep;
+            schedule_clone.run(&rt_clone, context_clone.clone()).await;
+            schedule_clone.run(&rt_clone, context_clone).await;
+        });
+        block_on(waiter);
+        assert_eq!(*context.log.lock().unwrap(), vec!["accelerate", "integrate", "render", "accelerate", "integrate", "render"]);
+
+        let mut cyclic = Schedule::<Context>::new();
+        cyclic.add_system(System::new("x", |_: Arc<Context>| async {}).before("y")).unwrap();
+        cyclic.add_system(System::new("y", |_: Arc<Context>| async {}).before("x")).unwrap();
+        assert!(matches!(cyclic.build(), Err(ScheduleError::Cycle(_))));
+        let mut unknown = Schedule::<Context>::new();
+        unknown.add_system(System::new("x", |_: Arc<Context>| async {}).after("missing")).unwrap();
+        assert_eq!(unknown.build(), Err(ScheduleError::UnknownSystem(String::from("missing"))));
+    }
+
+    #[test]
+    fn prefabs_work() {
+        use entity::{Prefab, Overrides, Parent, Children};
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Name(&'static str);
+
+        impl entity::Component for Name {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        let runtime = Arc::new(Runtime::new());
+        let waiter = sync::AtomicWaiter::new();
+        let dep = waiter.make_dependency();
+
+        let task = async {
+            let _d = dep;
+            let ecm = EntityComponentManager::new();
+            let wheel = Prefab::new().with(Name("wheel")).with(Health(10));
+            let car = Prefab::new()
+                .with(Name("car"))
+                .with(Health(100))
+                .with_child(wheel.clone())
+                .with_child(wheel.clone().with(Health(20)));
+            assert_eq!(car.get::<Health>(), Some(&Health(100)));
+            assert_eq!(car.children()[1].get::<Health>(), Some(&Health(20)));
+
+            let single = ecm.instantiate_with(&car, Overrides::new().with(Health(1))).await;
+            let many = ecm.instantiate_many_with(&car, 10, |i, overrides| overrides.set(Health(i as u32))).await;
+            let deferred = {
+                get_entities!(ecm; entities);
+                car.spawn(&ecm.commands, entities)
+            };
+            ecm.cleanup().await;
+
+            get_components!(ecm; Health, Name, Parent, Children => healths, names, parents, children);
+            get_entities!(ecm; entities);
+            assert_eq!(names.iter().count(), 3 * 12);
+            assert_eq!(healths.get(single.index), Some(&Health(1)));
+            assert_eq!(healths.get(deferred.index), Some(&Health(100)));
+            for (i, root) in many.iter().enumerate() {
+                assert!(entities.exists(*root));
+                assert_eq!(healths.get(root.index), Some(&Health(i as u32)));
+                assert_eq!(names.get(root.index), Some(&Name("car")));
+                let wheels = &children.get(root.index).unwrap().0;
+                assert_eq!(wheels.len(), 2);
+                assert_eq!(healths.get(wheels[0].index), Some(&Health(10)));
+                assert_eq!(healths.get(wheels[1].index), Some(&Health(20)));
+                for wheel in wheels {
+                    assert_eq!(parents.get(wheel.index).unwrap().0.index, root.index);
+                    assert_eq!(names.get(wheel.index), Some(&Name("wheel")));
+                }
+            }
+        };
+
+        runtime.spawn_prioritised(task, sync::task::Priority::VeryHigh);
+        block_on(waiter);
+    }
+
+    #[test]
+    fn bundles_work() {
+        use entity::ComponentStore;
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Speed(f32);
+
+        impl entity::Component for Speed {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Armor(u32);
+
+        impl entity::Component for Armor {
+            type Storage = entity::TableStore<Self>;
+        }
+
+        struct Soldier {
+            health: Health,
+            armor: Armor,
+        }
+
+        impl_bundle!(Soldier { health: Health, armor: Armor });
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let runner = ecm.spawn_bundle((Health(5), Speed(2.0))).await;
+            let soldier = ecm.spawn_bundle(Soldier{ health: Health(10), armor: Armor(3) }).await;
+            {
+                get_entities_mut!(ecm; entities);
+                let mut stores = ecm.lock_bundle::<(Speed, Armor)>().await;
+                assert!(stores.contains::<Armor>() && !stores.contains::<Health>());
+                entities.insert_bundle(&mut stores, (Armor(1), Speed(4.0)), runner);
+                entities.remove_bundle::<(Speed, Armor)>(&mut stores, soldier);
+            }
+
+            get_components!(ecm; Health, Speed, Armor => healths, speeds, armors);
+            assert_eq!(healths.get(runner.index), Some(&Health(5)));
+            assert_eq!(speeds.get(runner.index), Some(&Speed(4.0)));
+            assert_eq!(armors.get(runner.index), Some(&Armor(1)));
+            assert_eq!(healths.get(soldier.index), Some(&Health(10)));
+            assert_eq!(armors.get(soldier.index), None);
+        });
+    }
+
+    #[test]
+    fn tag_store_works() {
+        use entity::{BitSet, Query, With};
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Player;
+
+        impl entity::Component for Player {
+            type Storage = entity::TagStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Frozen;
+
+        impl entity::Component for Frozen {
+            type Storage = entity::TagStore<Self>;
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            {
+                get_components_mut!(ecm; Health, Player, Frozen => healths, players, frozen);
+                get_entities_mut!(ecm; entities);
+                for i in 0..10_000 {
+                    let entity = entities.create();
+                    entities.add(healths, Health(i), entity);
+                    if i % 3 == 0 {
+                        entities.add(players, Player, entity);
+                    }
+                    if i % 5 == 0 {
+                        entities.add(frozen, Frozen, entity);
+                    }
+                }
+                for i in (0..10_000).step_by(210) {
+                    let entity = EntityHandle{ index: i, version: entities.version_of(i).unwrap() };
+                    entities.rem(frozen, entity);
+                }
+            }
+
+            {
+                get_components!(ecm; Health, Player, Frozen => healths, players, frozen);
+                assert_eq!(players.len(), 3334);
+                assert_eq!(frozen.len(), 2000 - 48);
+
+                let both = BitSet::intersection([players.bits(), frozen.bits()]).collect::<Vec<_>>();
+                assert_eq!(both.len(), 667 - 48);
+                assert!(both.windows(2).all(|w| w[0] < w[1]));
+                assert!(both.iter().all(|i| i % 15 == 0 && i % 210 != 0));
+
+                // several tags drive the join from the intersection of their sets, a single tag only if it is the smallest store
+                assert_eq!(plan_driver!(healths, players, frozen), entity::iteration::TAG_INTERSECTION);
+                assert_eq!(plan_driver!(healths, players, not frozen), 1);
+                let frozen_players = iterate_over_entities!(stores: healths, players, frozen)
+                    .map(|(health, _, _)| health.0)
+                    .collect::<Vec<_>>();
+                assert_eq!(frozen_players, both);
+
+                let moving_players = iterate_over_entities!(stores: healths, players, not frozen).count();
+                assert_eq!(moving_players, 3334 - (667 - 48));
+
+                let mut query = Query::<(&Health,), With<Player>>::new((healths,), players);
+                assert_eq!(query.count(), 3334);
+                assert_eq!(iterate_over_entities!(stores: players, healths).map(|(_, health)| health.0).sum::<u32>(), (0..10_000).step_by(3).sum());
+            }
+
+            let runtime = Runtime::new();
+            get_components_mut!(ecm; Health, Player, Frozen => healths, players, frozen);
+            get_entities!(ecm; entities);
+            parallel_over_entities!(
+                runtime: runtime;
+                closure: |(_, health, _, _): (EntityHandle, &mut Health, &Player, &Frozen)| {
+                    health.0 += 100_000;
+                };
+                entities: entities;
+                stores: mut healths, players, frozen
+            ).await;
+            assert!(healths.iter_entity().all(|(index, health)| (health.0 >= 100_000) == (players.has(index) && frozen.has(index))));
+        });
+    }
+
+    #[test]
+    fn dense_group_works() {
+        use entity::{DenseGroup, GroupedStore};
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Name(String);
+
+        impl entity::Component for Name {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Dead;
+
+        impl entity::Component for Dead {
+            type Storage = entity::TagStore<Self>;
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            get_components_mut!(ecm; Health, Name, Dead => healths, names, dead);
+            get_entities_mut!(ecm; entities);
+            for i in 0..1000 {
+                let entity = entities.create();
+                if i % 2 == 0 {
+                    entities.add(healths, Health(i), entity);
+                }
+                if i % 3 == 0 {
+                    entities.add(names, Name(i.to_string()), entity);
+                }
+                if i % 4 == 0 {
+                    entities.add(dead, Dead, entity);
+                }
+            }
+
+            let mut group = DenseGroup::new(&mut [&mut *healths, &mut *names]);
+            assert!(!group.arrange(&mut [&mut *healths, &mut *names]));
+            assert_eq!(group.len(), 167);
+            assert_eq!(healths.dense_entities()[..167], names.dense_entities()[..167]);
+            assert!(healths.dense_entities()[..167].iter().all(|i| i % 6 == 0));
+
+            for (entity, health, name) in iterate_over_group!(group: group; entities: entities; stores: mut healths, names, not dead) {
+                assert_eq!(name.0, entity.index.to_string());
+                health.0 += 1;
+            }
+            let grouped = iterate_over_group!(group: group; stores: healths, names).map(|(health, _)| health.0).sum::<u32>();
+            let looked_up = iterate_over_entities!(stores: healths, names).map(|(health, _)| health.0).sum::<u32>();
+            assert_eq!(grouped, looked_up);
+            assert_eq!(grouped, (0..1000).step_by(6).sum::<u32>() + (0..1000).step_by(6).filter(|i| i % 4 != 0).count() as u32);
+
+            let entity = entities.create();
+            entities.add(healths, Health(0), entity);
+            entities.add(names, Name(String::from("new")), entity);
+            assert_eq!(group.len(), 168);
+            assert!(!group.is_arranged(&[&*healths, &*names]));
+            assert_eq!(iterate_over_group!(group: group; stores: healths, names).count(), 168);
+            assert_eq!(iterate_over_group!(group: group; stores: mut healths, names).count(), 168);
+            assert!(group.is_arranged(&[&*healths, &*names]));
+            assert_eq!(healths.dense_entities()[167], entity.index);
+            assert_eq!(names.dense_entities()[167], entity.index);
+
+            let removed = healths.dense_entities()[0];
+            entities.rem(healths, entity::EntityHandle{index: removed, version: entities.version_of(removed).unwrap()});
+            assert_eq!(group.len(), 167);
+            assert!(!group.is_arranged(&[&*healths, &*names]));
+            assert_eq!(iterate_over_group!(group: group; stores: healths, names).count(), 167);
+            assert_eq!(iterate_over_group!(group: group; stores: mut names, healths).count(), 167);
+            assert!(group.is_arranged(&[&*healths, &*names]));
+            assert_eq!(healths.dense_entities()[..167], names.dense_entities()[..167]);
+            assert!(!group.arrange(&mut [&mut *healths, &mut *names]));
+
+            // restoring a rollback frame resets the group, the stores arrange themselves again on their next access
+            let saved = healths.save_state().unwrap();
+            let removed = healths.dense_entities()[0];
+            entities.rem(healths, entity::EntityHandle{index: removed, version: entities.version_of(removed).unwrap()});
+            assert_eq!(group.len(), 166);
+            healths.restore_state(&*saved);
+            assert!(!group.is_arranged(&[&*healths, &*names]));
+            assert_eq!(iterate_over_group!(group: group; stores: mut healths, mut names).count(), 167);
+            assert!(group.is_arranged(&[&*healths, &*names]));
+            assert_eq!(group.len(), 167);
+            assert_eq!(healths.dense_entities()[..167], names.dense_entities()[..167]);
+            assert!(healths.dense_entities()[..167].iter().all(|i| names.get(*i).is_some()));
+            healths.restore_state(&*saved);
+            assert!(group.arrange(&mut [&mut *healths, &mut *names]));
+            assert!(group.is_arranged(&[&*healths, &*names]));
+        });
+    }
+
+    #[test]
+    fn indices_work() {
+        use entity::ComponentStore;
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Name(String);
+
+        impl entity::Component for Name {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Team(u32);
+
+        impl entity::Component for Team {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let mut handles = Vec::new();
+            {
+                get_components_mut!(ecm; Name, Team => names, teams);
+                get_entities_mut!(ecm; entities);
+                for i in 0..100 {
+                    let entity = entities.create();
+                    entities.add(names, Name(format!("unit{}", i)), entity);
+                    entities.add(teams, Team(i % 4), entity);
+                    handles.push(entity);
+                }
+            }
+            let by_name = ecm.register_hash_index(|name: &Name| name.0.clone()).await;
+            let by_team = ecm.register_ordered_index(|team: &Team| team.0).await;
+            {
+                get_components!(ecm; Name, Team => names, teams);
+                get_entities!(ecm; entities);
+                assert_eq!(by_name.lookup(names, entities).get_one(&String::from("unit42")), Some(handles[42]));
+                let teams_lookup = by_team.lookup(teams, entities);
+                assert_eq!(teams_lookup.get(&3).len(), 25);
+                assert_eq!(teams_lookup.range(1..3).len(), 50);
+            }
+
+            {
+                get_components_mut!(ecm; Name, Team => names, teams);
+                get_entities_mut!(ecm; entities);
+                names.get_mut(handles[42].index).unwrap().0 = String::from("hero");
+                entities.rem(teams, handles[3]);
+                teams.set(handles[0].index, Team(3));
+                entities.destroy(handles[7]);
+                // lookups see the writes of the current tick before cleanup
+                let names_lookup = by_name.lookup(names, entities);
+                assert_eq!(names_lookup.get_one(&String::from("hero")), Some(handles[42]));
+                assert!(!names_lookup.contains(&String::from("unit42")));
+                drop(names_lookup);
+                let teams_lookup = by_team.lookup(teams, entities);
+                assert_eq!(teams_lookup.key_of(handles[0]), Some(3));
+                assert_eq!(teams_lookup.key_of(handles[3]), None);
+                assert_eq!(teams_lookup.len(), 99);
+                drop(teams_lookup);
+                let entity = entities.create();
+                entities.add(names, Name(String::from("newcomer")), entity);
+                assert_eq!(by_name.lookup(names, entities).get(&String::from("newcomer")), vec![entity]);
+                entities.destroy(entity);
+            }
+            ecm.cleanup().await;
+
+            {
+                get_components!(ecm; Name, Team => names, teams);
+                get_entities!(ecm; entities);
+                let names_lookup = by_name.lookup(names, entities);
+                assert_eq!(names_lookup.get_one(&String::from("hero")), Some(handles[42]));
+                assert!(!names_lookup.contains(&String::from("unit42")));
+                assert!(!names_lookup.contains(&String::from("unit7")));
+                assert!(!names_lookup.contains(&String::from("newcomer")));
+                assert_eq!(names_lookup.len(), 99);
+                let teams_lookup = by_team.lookup(teams, entities);
+                assert_eq!(teams_lookup.key_of(handles[0]), Some(3));
+                assert_eq!(teams_lookup.key_of(handles[3]), None);
+                assert_eq!(teams_lookup.get(&3).len(), 25 - 2 + 1);
+                assert_eq!(teams_lookup.len(), 98);
+            }
+
+            ecm.advance_tick().await;
+            ecm.advance_tick().await;
+            let reused = {
+                get_components_mut!(ecm; Name => names);
+                get_entities_mut!(ecm; entities);
+                let entity = entities.create();
+                entities.add(names, Name(String::from("unit7")), entity);
+                entity
+            };
+            ecm.refresh_indices().await;
+            assert_eq!(reused.index, handles[7].index);
+            {
+                get_components!(ecm; Name => names);
+                get_entities!(ecm; entities);
+                assert_eq!(by_name.lookup(names, entities).get(&String::from("unit7")), vec![reused]);
+            }
+
+            // lookups only look at the logged changes, mutable iteration counts as a change of every component
+            get_components_mut!(ecm; Name => names);
+            get_entities!(ecm; entities);
+            let cursor = names.change_cursor();
+            assert_eq!(names.changes_since(cursor), Some(&[][..]));
+            names.get_mut(handles[1].index).unwrap().0 = String::from("scout");
+            names.set(handles[2].index, Name(String::from("medic")));
+            assert_eq!(names.changes_since(cursor), Some(&[handles[1].index, handles[2].index][..]));
+            assert_eq!(by_name.lookup(names, entities).get_one(&String::from("medic")), Some(handles[2]));
+            names.iter_entity_mut().for_each(|(_, name)| name.0.push('!'));
+            assert_eq!(names.changes_since(cursor), None);
+            let names_lookup = by_name.lookup(names, entities);
+            assert_eq!(names_lookup.get_one(&String::from("scout!")), Some(handles[1]));
+            assert_eq!(names_lookup.len(), 100);
+        });
+    }
+
+    #[test]
+    fn spatial_grid_works() {
+        use entity::{SpatialGrid, Transform, GlobalTransform, Parent, Children, RectRenderable};
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let mut grid = SpatialGrid::new(4.0);
+            let mut handles = Vec::new();
+            {
+                get_components_mut!(ecm; Transform, GlobalTransform, Parent, RectRenderable => transforms, globals, parents, rects);
+                get_entities_mut!(ecm; entities);
+                SpatialGrid::log_changes::<RectRenderable>(transforms, globals, parents, rects);
+                for x in 0..10 {
+                    for y in 0..10 {
+                        let entity = entities.create();
+                        entities.add(transforms, Transform{ position: Vf32x2::new(x as f32 * 3.0, y as f32 * 3.0), ..Default::default() }, entity);
+                        entities.add(rects, RectRenderable{ size: Vf32x2::new(1.0, 1.0), ..Default::default() }, entity);
+                        handles.push(entity);
+                    }
+                }
+                let big = entities.create();
+                entities.add(transforms, Transform{ position: Vf32x2::new(50.0, 50.0), ..Default::default() }, big);
+                entities.add(rects, RectRenderable{ size: Vf32x2::new(20.0, 2.0), ..Default::default() }, big);
+                handles.push(big);
+                grid.update::<RectRenderable>(entities, transforms, globals, parents, rects);
+            }
+            assert_eq!(grid.len(), 101);
+
+            let mut found = grid.query_aabb(Vf32x2::new(2.0, 2.0), Vf32x2::new(7.0, 4.0));
+            found.sort_by_key(|e| e.index);
+            assert_eq!(found, vec![handles[11], handles[21]]);
+            assert_eq!(grid.query_aabb(Vf32x2::new(35.0, 49.5), Vf32x2::new(65.0, 50.5)), vec![handles[100]]);
+            assert_eq!(grid.query_radius(Vf32x2::new(3.0, 3.0), 2.6).len(), 5);
+            assert_eq!(grid.nearest(Vf32x2::new(13.2, 7.1), 10.0).map(|(e, _)| e), Some(handles[42]));
+            assert_eq!(grid.nearest_where(Vf32x2::new(0.0, 0.0), 10.0, |e| e != handles[0]).map(|(e, _)| e.index), Some(1));
+            assert!(grid.nearest(Vf32x2::new(100.0, 0.0), 10.0).is_none());
+            // far points only walk the rings that overlap the occupied cells, points outside the i32 cell range saturate
+            assert_eq!(grid.nearest(Vf32x2::new(1.0e6, -1.0e6), f32::INFINITY).map(|(e, _)| e), Some(handles[90]));
+            assert!(grid.nearest(Vf32x2::new(1.0e30, -1.0e30), f32::INFINITY).is_some());
+            assert_eq!(grid.query_aabb(Vf32x2::new(-1.0e30, -1.0e30), Vf32x2::new(1.0e30, 1.0e30)).len(), 101);
+            assert!(grid.raycast(Vf32x2::new(1.0e30, 6.0), Vf32x2::new(-1.0, 0.0), f32::INFINITY).is_some());
+
+            let (hit, distance) = grid.raycast(Vf32x2::new(-10.0, 6.0), Vf32x2::new(1.0, 0.0), 100.0).unwrap();
+            assert_eq!(hit, handles[2]);
+            assert!((distance - 9.5).abs() < 1e-4);
+            assert_eq!(grid.raycast(Vf32x2::new(50.0, 0.0), Vf32x2::new(0.0, 1.0), 100.0).map(|(e, _)| e), Some(handles[100]));
+            assert!(grid.raycast(Vf32x2::new(-10.0, 7.0), Vf32x2::new(1.0, 0.0), 100.0).is_none());
+
+            ecm.advance_tick().await;
+            {
+                get_components_mut!(ecm; Transform, GlobalTransform, Parent, RectRenderable => transforms, globals, parents, rects);
+                get_entities_mut!(ecm; entities);
+                transforms.get_mut(handles[0].index).unwrap().position = Vf32x2::new(13.0, 7.0);
+                entities.rem(rects, handles[42]);
+                grid.update::<RectRenderable>(entities, transforms, globals, parents, rects);
+            }
+            assert_eq!(grid.len(), 100);
+            assert_eq!(grid.nearest(Vf32x2::new(13.2, 7.1), 10.0).map(|(e, _)| e), Some(handles[0]));
+            assert!(grid.query_aabb(Vf32x2::new(-1.0, -1.0), Vf32x2::new(1.0, 1.0)).is_empty());
+
+            // children are placed in world space once their transforms are propagated
+            ecm.advance_tick().await;
+            let (carrier, cargo) = {
+                get_components_mut!(ecm; Transform, GlobalTransform, Parent, Children, RectRenderable => transforms, globals, parents, children, rects);
+                get_entities_mut!(ecm; entities);
+                let carrier = entities.create();
+                let cargo = entities.create();
+                entities.add(transforms, Transform{ position: Vf32x2::new(200.0, 200.0), orientation: Vf32x2::new(0.0, 1.0) }, carrier);
+                entities.add(transforms, Transform{ position: Vf32x2::new(5.0, 0.0), ..Default::default() }, cargo);
+                entities.add(rects, RectRenderable{ size: Vf32x2::new(1.0, 1.0), ..Default::default() }, carrier);
+                entities.add(rects, RectRenderable{ size: Vf32x2::new(1.0, 1.0), ..Default::default() }, cargo);
+                entities.set_parent(parents, children, cargo, carrier);
+                grid.update::<RectRenderable>(entities, transforms, globals, parents, rects);
+                assert_eq!(grid.len(), 101);
+                assert!(grid.query_radius(Vf32x2::new(5.0, 0.0), 1.0).iter().all(|e| *e != cargo));
+
+                entity::propagate_transforms(transforms, globals, parents, children);
+                grid.update::<RectRenderable>(entities, transforms, globals, parents, rects);
+                (carrier, cargo)
+            };
+            assert_eq!(grid.len(), 102);
+            assert_eq!(grid.nearest(Vf32x2::new(200.0, 205.0), 1.0).map(|(e, _)| e), Some(cargo));
+
+            ecm.advance_tick().await;
+            {
+                get_components_mut!(ecm; Transform, GlobalTransform, Parent, Children, RectRenderable => transforms, globals, parents, children, rects);
+                get_entities_mut!(ecm; entities);
+                transforms.get_mut(carrier.index).unwrap().position = Vf32x2::new(300.0, 200.0);
+                entity::propagate_transforms(transforms, globals, parents, children);
+                grid.update::<RectRenderable>(entities, transforms, globals, parents, rects);
+            }
+            assert!(grid.query_radius(Vf32x2::new(200.0, 205.0), 1.0).is_empty());
+            assert_eq!(grid.nearest(Vf32x2::new(300.0, 205.0), 1.0).map(|(e, _)| e), Some(cargo));
+        });
+    }
+
+    #[test]
+    fn lifecycle_hooks_work() {
+        use entity::ComponentStore;
+        use std::sync::Mutex;
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Corpse;
+
+        impl entity::Component for Corpse {
+            type Storage = entity::TagStore<Self>;
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let l = log.clone();
+            ecm.on_add(move |_, entity, health: &Health| l.lock().unwrap().push(format!("add {} {}", entity.index, health.0))).await;
+            let l = log.clone();
+            ecm.on_replace(move |_, entity, old: &Health, new: &Health| l.lock().unwrap().push(format!("replace {} {} {}", entity.index, old.0, new.0))).await;
+            let l = log.clone();
+            ecm.on_remove(move |context, entity, health: &Health| {
+                l.lock().unwrap().push(format!("remove {} {}", entity.index, health.0));
+                let corpse = context.commands.create(context.entities);
+                context.commands.add(corpse, Corpse);
+            }).await;
+
+            let (a, b) = {
+                get_components_mut!(ecm; Health => healths);
+                get_entities_mut!(ecm; entities);
+                let a = entities.create();
+                let b = entities.create();
+                entities.add(healths, Health(10), a);
+                entities.add(healths, Health(20), b);
+                healths.set(a.index, Health(5));
+                (a, b)
+            };
+            assert!(log.lock().unwrap().is_empty());
+            ecm.cleanup().await;
+            assert_eq!(*log.lock().unwrap(), vec!["add 0 10", "add 1 20", "replace 0 10 5"]);
+
+            log.lock().unwrap().clear();
+            {
+                get_components_mut!(ecm; Health => healths);
+                get_entities_mut!(ecm; entities);
+                entities.rem(healths, a);
+                entities.destroy(b);
+            }
+            ecm.cleanup().await;
+            assert_eq!(*log.lock().unwrap(), vec!["remove 0 5", "remove 1 20"]);
+            assert_eq!(ecm.commands.len(), 2);
+            ecm.apply_commands().await;
+            get_components!(ecm; Corpse => corpses);
+            assert_eq!(corpses.len(), 2);
+        });
+    }
+
+    #[test]
+    fn world_migration_works() {
+        use entity::{Transform, Parent, Children, Worlds};
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Loaded;
+
+        impl entity::Component for Loaded {
+            type Storage = entity::TagStore<Self>;
+        }
+
+        block_on(async {
+            let main = EntityComponentManager::new();
+            let worlds = Worlds::new();
+            let staging = worlds.create("staging").await;
+            assert!(worlds.get("staging").await.is_some());
+
+            let (root, child, outsider) = {
+                get_components_mut!(staging; Transform, Parent, Children, Loaded => transforms, parents, children, loaded);
+                get_entities_mut!(staging; entities);
+                let outsider = entities.create();
+                let root = entities.create();
+                let child = entities.create();
+                entities.add(transforms, Transform{ position: Vf32x2::new(1.0, 2.0), orientation: Vf32x2::new(0.0, 1.0) }, root);
+                entities.add(transforms, Transform{ position: Vf32x2::new(3.0, 0.0), orientation: Vf32x2::new(0.0, 1.0) }, child);
+                entities.add(loaded, Loaded, root);
+                entities.set_parent(parents, children, child, root);
+                entities.set_parent(parents, children, root, outsider);
+                (root, child, outsider)
+            };
+
+            let copied = staging.copy_entities_to(&main, &[root]).await;
+            assert_eq!(copied.len(), 2);
+            {
+                get_components!(main; Transform, Parent, Children, Loaded => transforms, parents, children, loaded);
+                let new_root = copied.get(root).unwrap();
+                let new_child = copied.get(child).unwrap();
+                assert!(copied.get(outsider).is_none());
+                assert_eq!(transforms.get(new_root.index).unwrap().position, Vf32x2::new(1.0, 2.0));
+                assert!(loaded.has(new_root.index));
+                assert!(parents.get(new_root.index).is_none());
+                assert_eq!(parents.get(new_child.index).unwrap().0, new_root);
+                assert_eq!(children.get(new_root.index).unwrap().0, vec![new_child]);
+            }
+
+            let moved = staging.move_entities_to(&main, &[root]).await;
+            staging.cleanup().await;
+            {
+                get_entities!(staging; entities);
+                assert!(!entities.exists(root) && !entities.exists(child) && entities.exists(outsider));
+                get_components!(staging; Children => children);
+                assert!(children.get(outsider.index).is_none_or(|c| c.0.is_empty()));
+            }
+            get_entities!(main; entities);
+            assert!(moved.iter().all(|(_, to)| entities.exists(to)));
+            get_components!(main; Transform => transforms);
+            assert_eq!(transforms.iter_entity().count(), 4);
+            assert!(worlds.remove("staging").await.is_some());
+        });
+    }
+
+    #[test]
+    fn world_delta_works() {
+        use entity::{Transform, WorldState, WorldDelta, SnapshotError};
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        impl entity::SnapshotComponent for Health {
+            const NAME: &'static str = "Health";
+
+            fn save(&self, writer: &mut dyn entity::SnapshotWriter) {
+                writer.write_u32("health", self.0);
+            }
+
+            fn load(reader: &mut dyn entity::SnapshotReader) -> Result<Self, SnapshotError> {
+                Ok(Health(reader.read_u32("health")?))
+            }
+        }
+
+        block_on(async {
+            let server = EntityComponentManager::new();
+            let client = EntityComponentManager::new();
+            for ecm in [&server, &client] {
+                ecm.register_snapshot_component::<Health>().await;
+                ecm.register_snapshot_component::<Transform>().await;
+            }
+
+            let mut handles = Vec::new();
+            {
+                get_components_mut!(server; Health, Transform => healths, transforms);
+                get_entities_mut!(server; entities);
+                for i in 0..100 {
+                    let entity = entities.create();
+                    entities.add(healths, Health(i), entity);
+                    entities.add(transforms, Transform{ position: Vf32x2::new(i as f32, 0.0), orientation: Vf32x2::new(1.0, 0.0) }, entity);
+                    handles.push(entity);
+                }
+            }
+            let first = server.capture_state(1).await;
+            let full = WorldDelta::between(&WorldState::empty(), &first).to_bytes();
+            client.apply_delta_bytes(&full).await.unwrap();
+            assert_eq!(client.replicated_sequence(), 1);
+
+            {
+                get_components_mut!(server; Health, Transform => healths, transforms);
+                get_entities_mut!(server; entities);
+                healths.set(handles[3].index, Health(1000));
+                entities.rem(transforms, handles[4]);
+                entities.destroy(handles[5]);
+            }
+            server.cleanup().await;
+            let created = {
+                get_components_mut!(server; Health => healths);
+                get_entities_mut!(server; entities);
+                let entity = entities.create();
+                entities.add(healths, Health(7), entity);
+                entity
+            };
+            assert_eq!(created.index, handles[5].index);
+            let second = server.capture_state(2).await;
+            let delta = WorldDelta::between(&first, &second);
+            assert_eq!(delta.slots.len(), 1);
+            let bytes = delta.to_bytes();
+            assert!(bytes.len() < full.len() / 10);
+            assert_eq!(WorldDelta::from_bytes(&bytes).unwrap(), delta);
+            assert_eq!(client.apply_delta_bytes(&WorldDelta::between(&second, &server.capture_state(3).await).to_bytes()).await,
+                Err(SnapshotError::SequenceMismatch{ expected: 1, found: 2 }));
+            client.apply_delta_bytes(&bytes).await.unwrap();
+
+            assert!(WorldDelta::between(&client.capture_state(2).await, &server.capture_state(3).await).is_empty());
+            get_entities!(client; entities);
+            assert!(entities.exists(created) && !entities.exists(handles[5]));
+            get_components!(client; Health, Transform => healths, transforms);
+            assert_eq!(healths.get(handles[3].index), Some(&Health(1000)));
+            assert!(transforms.get(handles[4].index).is_none());
+            assert_eq!(healths.get(created.index), Some(&Health(7)));
+            assert!(transforms.get(created.index).is_none());
+        });
+    }
+
+    #[test]
+    fn rollback_works() {
+        use entity::{Transform, RollbackBuffer, RollbackFrame};
+        use entity::ComponentStore;
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Velocity(f32);
+
+        impl entity::Component for Velocity {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,PartialEq,Debug)]
+        struct Score(u32);
+
+        #[derive(Clone,PartialEq,Debug)]
+        struct Frames(u32);
+
+        #[derive(Clone,PartialEq,Debug)]
+        struct Hit(u32);
+
+        async fn step(ecm: &EntityComponentManager, input: f32) {
+            {
+                get_components_mut!(ecm; Transform, Velocity => transforms, velocities);
+                for (index, velocity) in velocities.iter_entity_mut() {
+                    velocity.0 += input;
+                    transforms.get_mut(index).unwrap().position.x += velocity.0;
+                }
+            }
+            ecm.cleanup().await;
+            ecm.advance_tick().await;
+        }
+
+        async fn positions(ecm: &EntityComponentManager) -> Vec<f32> {
+            get_components!(ecm; Transform => transforms);
+            transforms.iter().map(|transform| transform.position.x).collect()
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let handles = {
+                get_components_mut!(ecm; Transform, Velocity => transforms, velocities);
+                get_entities_mut!(ecm; entities);
+                (0..300).map(|i| {
+                    let entity = entities.create();
+                    entities.add(transforms, Transform{ position: Vf32x2::new(i as f32, 0.0), orientation: Vf32x2::new(1.0, 0.0) }, entity);
+                    if i < 10 {
+                        entities.add(velocities, Velocity(0.0), entity);
+                    }
+                    entity
+                }).collect::<Vec<_>>()
+            };
+            let by_velocity = ecm.register_ordered_index(|velocity: &Velocity| velocity.0 as i64).await;
+
+            let mut buffer = RollbackBuffer::new(8);
+            let inputs = [1.0, 0.0, 2.0, 0.0, 1.0, 1.0, 0.0, 3.0, 1.0, 0.0];
+            for (tick, input) in inputs.iter().enumerate() {
+                buffer.push(RollbackFrame{ tick: tick as u64, world: ecm.save_frame().await, input: *input });
+                step(&ecm, *input).await;
+            }
+            assert_eq!((buffer.oldest_tick(), buffer.newest_tick(), buffer.len()), (Some(2), Some(9), 8));
+            let live = positions(&ecm).await;
+
+            {
+                get_entities_mut!(ecm; entities);
+                entities.destroy(handles[20]);
+            }
+            ecm.cleanup().await;
+            buffer.get_mut(4).unwrap().input = 5.0;
+            let replay = buffer.iter_from(4).map(|frame| frame.input).collect::<Vec<_>>();
+            ecm.restore_frame(&buffer.get(4).unwrap().world).await;
+            {
+                get_entities!(ecm; entities);
+                assert!(entities.exists(handles[20]));
+            }
+            for (i, input) in replay.into_iter().enumerate() {
+                buffer.push(RollbackFrame{ tick: 4 + i as u64, world: ecm.save_frame().await, input });
+                step(&ecm, input).await;
+            }
+            assert_eq!(buffer.newest_tick(), Some(9));
+            let resimulated = positions(&ecm).await;
+
+            let reference = EntityComponentManager::new();
+            {
+                get_components_mut!(reference; Transform, Velocity => transforms, velocities);
+                get_entities_mut!(reference; entities);
+                for i in 0..300 {
+                    let entity = entities.create();
+                    entities.add(transforms, Transform{ position: Vf32x2::new(i as f32, 0.0), orientation: Vf32x2::new(1.0, 0.0) }, entity);
+                    if i < 10 {
+                        entities.add(velocities, Velocity(0.0), entity);
+                    }
+                }
+            }
+            let mut changed = inputs;
+            changed[4] = 5.0;
+            for input in changed {
+                step(&reference, input).await;
+            }
+            assert_eq!(resimulated, positions(&reference).await);
+            assert_ne!(resimulated, live);
+            {
+                get_components!(ecm; Velocity => velocities);
+                get_entities!(ecm; entities);
+                assert_eq!(by_velocity.lookup(velocities, entities).get(&(changed.iter().sum::<f32>() as i64)).len(), 10);
+            }
+
+            ecm.register_rollback_resource::<Score>().await;
+            ecm.register_rollback_resource::<Frames>().await;
+            ecm.register_rollback_events::<Hit>().await;
+            ecm.insert_resource(Score(1)).await;
+            ecm.send_event(Hit(1)).await;
+            let frame = ecm.save_frame().await;
+            ecm.insert_resource(Score(2)).await;
+            ecm.insert_resource(Frames(2)).await;
+            ecm.update_events().await;
+            ecm.update_events().await;
+            ecm.send_event(Hit(2)).await;
+            ecm.restore_frame(&frame).await;
+            assert_eq!(*ecm.get_resource::<Score>().await, Score(1));
+            assert!(!ecm.has_resource::<Frames>().await);
+            assert_eq!(entity::EventReader::default().read(&*ecm.events::<Hit>().await), vec![Hit(1)]);
+
+            ecm.restore_frame(&buffer.get(9).unwrap().world).await;
+            assert_eq!(*ecm.get_resource::<Score>().await, Score(1));
+            buffer.truncate(6);
+            assert_eq!(buffer.newest_tick(), Some(5));
+            get_components!(ecm; Velocity => velocities);
+            assert_eq!(velocities.get(handles[0].index), Some(&Velocity(changed[..9].iter().sum())));
+        });
+    }
+
+    #[test]
+    fn reflection_works() {
+        use entity::{Transform, ReflectValue, ReflectError, FieldInfo};
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Stats {
+            name: String,
+            level: u32,
+            alive: bool,
+        }
+
+        impl entity::Component for Stats {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        crate::impl_reflect!(Stats { name: String, level: u32, alive: bool });
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(f32);
+
+        impl entity::Component for Health {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        crate::impl_reflect!(Health { 0: f32 });
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            ecm.register_reflect::<Transform>().await;
+            ecm.register_reflect::<Stats>().await;
+            ecm.register_reflect::<Health>().await;
+            let infos = ecm.reflected_components().await;
+            assert_eq!(infos.iter().map(|info| info.name).collect::<Vec<_>>(), vec!["Health", "Stats", "Transform"]);
+            assert_eq!(infos[1].fields[1], FieldInfo{ name: "level", type_name: "u32" });
+
+            let entity = {
+                get_components_mut!(ecm; Transform, Stats => transforms, stats);
+                get_entities_mut!(ecm; entities);
+                let entity = entities.create();
+                entities.add(transforms, Transform{ position: Vf32x2::new(1.0, 2.0), orientation: Vf32x2::new(0.0, 1.0) }, entity);
+                entities.add(stats, Stats{ name: String::from("knight"), level: 3, alive: true }, entity);
+                entity
+            };
+            assert_eq!(ecm.reflected_components_of(entity).await, vec!["Stats", "Transform"]);
+            assert_eq!(ecm.get_field(entity, "Transform.position.y").await, Ok(ReflectValue::Float(2.0)));
+            assert_eq!(ecm.get_field(entity, "Stats.name").await, Ok(ReflectValue::String(String::from("knight"))));
+
+            ecm.set_field(entity, "Transform.position.x", ReflectValue::parse("7")).await.unwrap();
+            ecm.set_field(entity, "Stats.level", ReflectValue::parse("4")).await.unwrap();
+            ecm.set_field(entity, "Stats.alive", ReflectValue::parse("false")).await.unwrap();
+            ecm.set_field(entity, "Stats.name", ReflectValue::parse("\"sir knight\"")).await.unwrap();
+            {
+                get_components!(ecm; Transform, Stats => transforms, stats);
+                assert_eq!(transforms.get(entity.index).unwrap().position, Vf32x2::new(7.0, 2.0));
+                let stats = stats.get(entity.index).unwrap();
+                assert_eq!((stats.name.as_str(), stats.level, stats.alive), ("sir knight", 4, false));
+            }
+
+            assert_eq!(ecm.set_field(entity, "Stats.level", ReflectValue::Int(-1)).await, Err(ReflectError::TypeMismatch{ path: String::from("Stats.level"), expected: "u32" }));
+            assert_eq!(ecm.get_field(entity, "Transform.position").await, Err(ReflectError::NotAValue(String::from("Transform.position"))));
+            assert_eq!(ecm.get_field(entity, "Transform.position.q").await, Err(ReflectError::UnknownField(String::from("Transform.position.q"))));
+            assert_eq!(ecm.get_field(entity, "Health.0").await, Err(ReflectError::MissingComponent(String::from("Health"))));
+            assert_eq!(ecm.get_field(entity, "Mana.value").await, Err(ReflectError::UnknownComponent(String::from("Mana"))));
+            let position = ecm.with_reflect(entity, "Transform", |transform| transform.path("position").unwrap().type_info().fields.len()).await;
+            assert_eq!(position, Ok(2));
+        });
+    }
+
+    #[test]
+    fn dynamic_components_work() {
+        use entity::{Transform, DynamicLayout, FieldKind, LayoutError, ReflectValue};
+
+        assert_eq!(DynamicLayout::parse("Stats { hp: f32, armor: i32, }").unwrap(), DynamicLayout::new("Stats", &[("hp", FieldKind::F32), ("armor", FieldKind::I32)]).unwrap());
+        assert_eq!(DynamicLayout::parse("Stats { hp: f32, hp: f32 }"), Err(LayoutError::DuplicateField(String::from("hp"))));
+        assert_eq!(DynamicLayout::parse("Stats { hp: float }"), Err(LayoutError::UnknownType(String::from("float"))));
+        assert!(matches!(DynamicLayout::parse("Stats hp: f32"), Err(LayoutError::Syntax(_))));
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let id = ecm.register_dynamic_component(DynamicLayout::parse("Stats { hp: f32, armor: i32, alive: bool }").unwrap()).await;
+            assert_eq!(ecm.dynamic_component("Stats").await, Some(id));
+            assert_eq!(ecm.dynamic_component("Other").await, None);
+
+            let store = ecm.get_dynamic_store(id).await;
+            let (hp, armor) = {
+                let layout = store.read().await.layout().clone();
+                assert_eq!(layout.size(), 9);
+                (layout.field("hp").unwrap(), layout.field("armor").unwrap())
+            };
+
+            let handles = {
+                get_components_mut!(ecm; Transform => transforms);
+                get_entities_mut!(ecm; entities);
+                let mut stats = store.write().await;
+                (0..100).map(|i| {
+                    let entity = entities.create();
+                    entities.add(transforms, Transform{ position: Vf32x2::new(i as f32, 0.0), orientation: Vf32x2::new(1.0, 0.0) }, entity);
+                    if i % 2 == 0 {
+                        let row = stats.add(entity.index);
+                        row.write(hp, 100.0f32);
+                        row.write(armor, i);
+                    }
+                    entity
+                }).collect::<Vec<_>>()
+            };
+            ecm.advance_tick().await;
+            ecm.advance_tick().await;
+
+            {
+                get_components!(ecm; Transform => transforms);
+                let mut stats = store.write().await;
+                let stats = &mut *stats;
+                for (transform, row) in iterate_over_entities!(stores: transforms, mut stats) {
+                    let value = row.read::<f32>(hp) - transform.position.x;
+                    row.write(hp, value);
+                }
+                assert_eq!(iterate_over_entities!(stores: stats, changed transforms).count(), 0);
+                assert_eq!(iterate_over_entities!(stores: transforms, changed stats).count(), 50);
+                assert_eq!(stats.get(handles[10].index).unwrap().read::<f32>(hp), 90.0);
+                assert_eq!(stats.get(handles[10].index).unwrap().get(armor), ReflectValue::Int(10));
+                assert!(stats.get_mut(handles[4].index).unwrap().set(armor, ReflectValue::Int(-4)));
+                assert!(!stats.get_mut(handles[4].index).unwrap().set(armor, ReflectValue::Bool(true)));
+                assert!(stats.get(handles[11].index).is_none());
+            }
+
+            let frame = ecm.save_frame().await;
+            {
+                get_entities_mut!(ecm; entities);
+                entities.destroy(handles[10]);
+            }
+            ecm.cleanup().await;
+            assert_eq!(store.read().await.len(), 49);
+            assert!(!store.read().await.has(handles[10].index));
+
+            ecm.restore_frame(&frame).await;
+            let stats = store.read().await;
+            assert_eq!(stats.len(), 50);
+            assert_eq!(stats.get(handles[10].index).unwrap().read::<f32>(hp), 90.0);
+            assert_eq!(stats.get(handles[4].index).unwrap().read::<i32>(armor), -4);
+        });
+    }
+
+    #[test]
+    fn diagnostics_work() {
+        use entity::{Transform, DynamicLayout};
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Score(u64);
+
+        impl entity::Component for Score {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let id = ecm.register_dynamic_component(DynamicLayout::parse("Stats { hp: f32 }").unwrap()).await;
+            let handles = {
+                get_components_mut!(ecm; Transform, Score => transforms, scores);
+                get_entities_mut!(ecm; entities);
+                let store = ecm.get_dynamic_store(id).await;
+                let mut stats = store.write().await;
+                (0..1000).map(|i| {
+                    let entity = entities.create();
+                    entities.add(transforms, Transform::default(), entity);
+                    entities.add(scores, Score(i), entity);
+                    if i % 4 == 0 {
+                        stats.add(entity.index);
+                    }
+                    entity
+                }).collect::<Vec<_>>()
+            };
+
+            let before = ecm.diagnostics().await;
+            assert_eq!((before.entities, before.entity_slots, before.free_slots), (1000, 1000, 0));
+            assert_eq!(before.components(), 2250);
+            assert_eq!(before.store("Stats").unwrap().memory.len, 250);
+            let transforms = before.stores.iter().find(|store| store.name.contains("LinearStore")).unwrap().memory;
+            assert_eq!((transforms.len, transforms.capacity), (1000, 1024));
+            assert!(before.stores.windows(2).all(|pair| pair[0].memory.bytes >= pair[1].memory.bytes));
+            assert!(before.to_string().ends_with(&format!("total: 2250 components, {} bytes", before.bytes())));
+
+            {
+                get_entities_mut!(ecm; entities);
+                for entity in &handles[10..] {
+                    entities.destroy(*entity);
+                }
+            }
+            ecm.cleanup().await;
+            let destroyed = ecm.diagnostics().await;
+            assert_eq!((destroyed.entities, destroyed.free_slots, destroyed.components()), (10, 990, 23));
+            assert!(destroyed.stores.iter().all(|store| store.memory.bytes == before.store(&store.name).unwrap().memory.bytes));
+
+            ecm.optimize().await;
+            let optimized = ecm.diagnostics().await;
+            assert_eq!(optimized.components(), 23);
+            let transforms = optimized.stores.iter().find(|store| store.name.contains("LinearStore")).unwrap().memory;
+            assert_eq!((transforms.len, transforms.capacity), (10, 128));
+            assert!(transforms.fragmentation() > 0.9);
+            let scores = optimized.stores.iter().find(|store| store.name.contains("DenseStore")).unwrap().memory;
+            assert_eq!((scores.len, scores.capacity), (10, 10));
+            assert_eq!(scores.fragmentation(), 0.0);
+            let store_bytes = |diagnostics: &entity::WorldDiagnostics| diagnostics.bytes() - diagnostics.entity_bytes;
+            assert!(store_bytes(&optimized) < store_bytes(&destroyed) / 4);
+
+            get_components_mut!(ecm; Transform, Score => transforms, scores);
+            get_entities_mut!(ecm; entities);
+            assert!(handles[..10].iter().all(|entity| entities.has(transforms, *entity) && entities.has(scores, *entity)));
+            let entity = entities.create();
+            entities.add(transforms, Transform::default(), entity);
+            entities.add(scores, Score(7), entity);
+            assert_eq!((transforms.len(), scores.len()), (11, 11));
+            assert_eq!(entities.get(scores, handles[9]), Some(&Score(9)));
+        });
+    }
+
+    #[test]
+    fn query_planner_works() {
+        use entity::iteration::PlannedIter::{Driven, First};
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Position(u32);
+
+        impl entity::Component for Position {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Rare(u32);
+
+        impl entity::Component for Rare {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Frozen;
+
+        impl entity::Component for Frozen {
+            type Storage = entity::TagStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Boost(u32);
+
+        impl entity::Component for Boost {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        let runtime = Arc::new(Runtime::new());
+        let waiter = sync::AtomicWaiter::new();
+        let dep = waiter.make_dependency();
+
+        let rt_clone = runtime.clone();
+        let task = async {
+            let _d = dep;
+            let runtime = rt_clone;
+            let ecm = EntityComponentManager::new();
+            get_components_mut!(ecm; Position, Rare, Frozen, Boost => positions, rares, frozen, boosts);
+            get_entities_mut!(ecm; entities);
+            for i in 0..2000 {
+                let entity = entities.create();
+                entities.add(positions, Position(i), entity);
+                if i % 50 == 3 {
+                    entities.add(boosts, Boost(100_000), entity);
+                }
+                if i % 100 == 7 {
+                    entities.add(rares, Rare(i), entity);
+                }
+                if i % 200 == 7 {
+                    entities.add(frozen, Frozen, entity);
+                }
+            }
+
+            assert_eq!(plan_driver!(positions, rares), 1);
+            assert_eq!(plan_driver!(rares, positions), 0);
+            assert_eq!(plan_driver!(mut positions, not rares), 0);
+            assert_eq!(plan_driver!(positions, changed rares, frozen), 2);
+            assert_eq!(plan_driver!(frozen, rares, positions), 0);
+            // the outer layer is the intersection of the TagStores, the inner one the second store
+            assert!(matches!(iterate_over_entities!(@plan rares, positions), First(First(_))));
+            assert!(matches!(iterate_over_entities!(@plan positions, rares), First(Driven(_))));
+
+            let forward = iterate_over_entities!(entities: entities; stores: mut positions, rares)
+                .map(|(entity, position, rare)| {
+                    position.0 += 1;
+                    (entity, position.0, rare.0)
+                })
+                .collect::<Vec<_>>();
+            let mut backward = iterate_over_entities!(entities: entities; stores: rares, positions)
+                .map(|(entity, rare, position)| (entity, position.0, rare.0))
+                .collect::<Vec<_>>();
+            backward.sort_by_key(|(entity, _, _)| entity.index);
+            assert_eq!(forward.len(), 20);
+            assert_eq!(forward, backward);
+            assert!(forward.iter().all(|(_, position, rare)| *position == rare + 1));
+            assert_eq!(iterate_over_entities!(stores: positions, rares, not frozen).count(), 10);
+            assert_eq!(iterate_over_entities!(stores: positions, changed rares).count(), 20);
+
+            parallel_over_entities!(
+                runtime: runtime;
+                batch_size: 4;
+                closure: |(_, position, rare): (EntityHandle, &mut Position, &Rare)| {
+                    position.0 += rare.0;
+                };
+                entities: entities;
+                stores: mut positions, rares, not frozen
+            ).await;
+            for (index, position) in positions.iter_entity() {
+                let expected = match (rares.get(index), frozen.has(index)) {
+                    (Some(rare), false) => rare.0 * 2 + 1,
+                    (Some(rare), true) => rare.0 + 1,
+                    (None, _) => index,
+                };
+                assert_eq!(position.0, expected);
+            }
+
+            // the second store drives with its own batches, they are page aligned for the writes to the first store
+            parallel_over_entities!(
+                runtime: runtime;
+                batch_size: 4;
+                closure: |(_, position, boost): (EntityHandle, &mut Position, &Boost)| {
+                    position.0 = boost.0;
+                };
+                entities: entities;
+                stores: mut positions, boosts
+            ).await;
+            assert_eq!(positions.iter().filter(|position| position.0 == 100_000).count(), 40);
+            assert!(boosts.iter_entity().all(|(index, _)| positions.get(index) == Some(&Position(100_000))));
+        };
+
+        runtime.spawn_prioritised(task, sync::task::Priority::VeryHigh);
+        block_on(waiter);
+    }
+
+    #[test]
+    fn adaptive_batching_works() {
+        use entity::iteration::{auto_batch_size, page_aligned_batches, SplitWork, WorkSplitter};
+        use std::sync::Mutex;
+
+        assert_eq!(auto_batch_size(10_000, 4), 625);
+        assert_eq!(auto_batch_size(10, 4), 64);
+        assert_eq!(page_aligned_batches(&(0..300).collect::<Vec<_>>(), 10), vec![0..128, 128..256, 256..300]);
+        assert_eq!(page_aligned_batches(&[1, 5, 130, 131, 140, 600], 2), vec![1..6, 130..141, 600..601]);
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Mana(u32);
+
+        impl entity::Component for Mana {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        // a dense first store writing other stores is driven by its sorted indices, so its batches are page aligned too
+        let mut manas = DenseStore::<Mana>::new();
+        for i in [300, 5, 129, 7] {
+            manas.add(i, Mana(i));
+        }
+        assert_eq!(entity::iteration::align_driver(&manas, true), Some(vec![5, 7, 129, 300]));
+        assert_eq!(entity::iteration::align_driver(&manas, false), None);
+        assert_eq!(entity::iteration::align_driver(&entity::LinearStore::<Health>::new(), true), None);
+
+        let runtime = Arc::new(Runtime::new());
+        assert!(runtime.worker_count() >= 1);
+        let waiter = sync::AtomicWaiter::new();
+        let dep = waiter.make_dependency();
+
+        let rt_clone = runtime.clone();
+        let task = async {
+            let _d = dep;
+            let runtime = rt_clone;
+            let ecm = EntityComponentManager::new();
+            get_components_mut!(ecm; Health => healths);
+            get_entities_mut!(ecm; entities);
+            for i in 0..1000 {
+                let entity = entities.create();
+                entities.add(healths, Health(i), entity);
+            }
+
+            let pages = healths.iter_entity_batch(200)
+                .map(|batch| batch.map(|(index, _)| index as usize / entity::LINEAR_STORE_PAGE_SIZE).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            assert_eq!(pages.len(), 4);
+            assert!(pages.iter().all(|batch| batch.first().unwrap() + 1 >= *batch.last().unwrap()));
+
+            // a slow task hands ranges of its remaining positions to new tasks, which share its work
+            let ranges = Arc::new(Mutex::new(Vec::new()));
+            let split_waiter = sync::AtomicWaiter::new();
+            let splitter = WorkSplitter::new(&runtime, sync::task::Priority::High, split_waiter.make_dependency());
+            let work: SplitWork = {
+                let ranges = ranges.clone();
+                Arc::new(move |split_ranges| {
+                    let mut visited = Vec::new();
+                    for range in split_ranges {
+                        let start = std::time::Instant::now();
+                        while start.elapsed() < std::time::Duration::from_micros(5 * range.len() as u64) {}
+                        visited.push(range);
+                    }
+                    ranges.lock().unwrap().push(visited);
+                })
+            };
+            splitter.spawn(0..1000, 1, work);
+            drop(splitter);
+            split_waiter.await;
+            let mut ranges = std::mem::take(&mut *ranges.lock().unwrap());
+            assert!(ranges.len() > 1);
+            let mut visited = ranges.drain(..).flatten().collect::<Vec<_>>();
+            visited.sort_by_key(|range| range.start);
+            assert!(visited.windows(2).all(|pair| pair[0].end == pair[1].start));
+            assert_eq!((visited.first().unwrap().start, visited.last().unwrap().end), (0, 1000));
+
+            // the closure is shared, not cloned, so it may capture state that is not Clone
+            let slow = Mutex::new(std::time::Duration::from_micros(5));
+            parallel_over_entities!(
+                runtime: runtime;
+                priority: sync::task::Priority::High;
+                batch_size: 500;
+                closure: move |(_, health): (EntityHandle, &mut Health)| {
+                    let slow = *slow.lock().unwrap();
+                    let start = std::time::Instant::now();
+                    while start.elapsed() < slow {}
+                    health.0 += 1;
+                };
+                entities: entities;
+                stores: mut healths
+            ).await;
+
+            parallel_over_entities!(
+                runtime: runtime;
+                closure: |(_, health): (EntityHandle, &mut Health)| {
+                    health.0 += 1;
+                };
+                entities: entities;
+                stores: mut healths
+            ).await;
+            assert!(healths.iter_entity().all(|(index, health)| health.0 == index + 2));
+        };
+
+        runtime.spawn_prioritised(task, sync::task::Priority::VeryHigh);
+        block_on(waiter);
+    }
+
+    #[test]
+    fn parallel_write_to_shared_pages_works() {
+        use entity::GenericComponentStore;
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = entity::LinearStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Regeneration(u32);
+
+        impl entity::Component for Regeneration {
+            type Storage = entity::DenseStore<Self>;
+        }
+
+        let runtime = Arc::new(Runtime::new());
+        let waiter = sync::AtomicWaiter::new();
+        let dep = waiter.make_dependency();
+
+        let rt_clone = runtime.clone();
+        let task = async {
+            let _d = dep;
+            let runtime = rt_clone;
+            let ecm = EntityComponentManager::new();
+            get_components_mut!(ecm; Regeneration, Health => regenerations, healths);
+            get_entities_mut!(ecm; entities);
+            for i in 0..1000 {
+                let entity = entities.create();
+                entities.add(healths, Health(i), entity);
+                if i % 3 == 0 {
+                    entities.add(regenerations, Regeneration(1), entity);
+                }
+            }
+            // a saved state shares every page, the parallel writes must copy them once up front, not per batch
+            let saved = healths.save_state().unwrap();
+            parallel_over_entities!(
+                runtime: runtime;
+                batch_size: 16;
+                closure: |(_, regeneration, health): (EntityHandle, &mut Regeneration, &mut Health)| {
+                    health.0 += regeneration.0;
+                };
+                entities: entities;
+                stores: mut regenerations, mut healths
+            ).await;
+            assert!(healths.iter_entity().all(|(index, health)| health.0 == index + (index % 3 == 0) as u32));
+
+            healths.restore_state(&*saved);
+            assert!(healths.iter_entity().all(|(index, health)| health.0 == index));
+        };
+
+        runtime.spawn_prioritised(task, sync::task::Priority::VeryHigh);
+        block_on(waiter);
+    }
+
+    #[test]
+    fn table_store_rollback_works() {
+        use entity::ComponentStore;
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Speed(f32);
+
+        impl entity::Component for Speed {
+            type Storage = entity::TableStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Distance(f32);
+
+        impl entity::Component for Distance {
+            type Storage = entity::TableStore<Self>;
+        }
+
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Fuel(f32);
+
+        impl entity::Component for Fuel {
+            type Storage = entity::TableStore<Self>;
+        }
+
+        block_on(async {
+            let ecm = EntityComponentManager::new();
+            let handles = {
+                get_components_mut!(ecm; Speed, Distance => speeds, distances);
+                get_entities_mut!(ecm; entities);
+                (0..100).map(|i| {
+                    let entity = entities.create();
+                    entities.add(speeds, Speed(i as f32), entity);
+                    if i % 3 == 0 {
+                        entities.add(distances, Distance(i as f32), entity);
+                    }
+                    entity
+                }).collect::<Vec<_>>()
+            };
+            let frame = ecm.save_frame().await;
+
+            {
+                get_components_mut!(ecm; Speed, Distance, Fuel => speeds, distances, fuels);
+                get_entities_mut!(ecm; entities);
+                for i in (0..100).step_by(2) {
+                    entities.rem(speeds, handles[i]);
+                }
+                for i in (1..100).step_by(3) {
+                    entities.add(distances, Distance(0.0), handles[i]);
+                    entities.add(fuels, Fuel(1.0), handles[i]);
+                }
+                entities.destroy(handles[99]);
+            }
+            ecm.cleanup().await;
+            ecm.restore_frame(&frame).await;
+
+            {
+                get_components_mut!(ecm; Speed, Distance, Fuel => speeds, distances, fuels);
+                get_entities_mut!(ecm; entities);
+                assert_eq!(fuels.len(), 0);
+                for (i, handle) in handles.iter().enumerate() {
+                    assert_eq!(speeds.get(handle.index), Some(&Speed(i as f32)));
+                    let expected = if i % 3 == 0 { Some(&Distance(i as f32)) } else { None };
+                    assert_eq!(distances.get(handle.index), expected);
+                }
+                assert_eq!(iterate_over_entities!(stores: speeds, distances).filter(|(speed, distance)| speed.0 == distance.0).count(), 34);
+                assert_eq!(iterate_over_entities!(stores: speeds, not distances).count(), 66);
+
+                entities.add(fuels, Fuel(2.0), handles[3]);
+                entities.rem(distances, handles[3]);
+                assert_eq!(iterate_over_entities!(stores: speeds, fuels, not distances).count(), 1);
+                assert_eq!(iterate_over_entities!(stores: speeds, distances).count(), 33);
+            }
+        });
+    }
+
+    #[test]
+    fn dynamic_components_follow_snapshots_and_deltas() {
+        use entity::{Transform, DynamicLayout, WorldState, WorldDelta};
+
+        block_on(async {
+            let server = EntityComponentManager::new();
+            let client = EntityComponentManager::new();
+            server.register_snapshot_component::<Transform>().await;
+            client.register_snapshot_component::<Transform>().await;
+            let id = client.register_dynamic_component(DynamicLayout::parse("Stats { hp: f32 }").unwrap()).await;
+            let stats = client.get_dynamic_store(id).await;
+
+            let handles = {
+                get_components_mut!(server; Transform => transforms);
+                get_entities_mut!(server; entities);
+                (0..10).map(|_| {
+                    let entity = entities.create();
+                    entities.add(transforms, Transform::default(), entity);
+                    entity
+                }).collect::<Vec<_>>()
+            };
+            {
+                get_entities_mut!(client; entities);
+                let mut stats = stats.write().await;
+                for _ in 0..3 {
+                    stats.add(entities.create().index);
+                }
+            }
+
+            let first = server.capture_state(1).await;
+            client.apply_delta(&WorldDelta::between(&WorldState::empty(), &first)).await.unwrap();
+            assert_eq!(stats.read().await.len(), 0);
+
+            {
+                let mut stats = stats.write().await;
+                stats.add(handles[0].index);
+                stats.add(handles[1].index);
+            }
+            {
+                get_entities_mut!(server; entities);
+                entities.destroy(handles[0]);
+            }
+            server.cleanup().await;
+            client.apply_delta(&WorldDelta::between(&first, &server.capture_state(2).await)).await.unwrap();
+            {
+                let stats = stats.read().await;
+                assert!(!stats.has(handles[0].index) && stats.has(handles[1].index));
+            }
+
+            let snapshot = server.save_snapshot_binary().await;
+            client.load_snapshot_binary(&snapshot).await.unwrap();
+            assert_eq!(stats.read().await.len(), 0);
+            get_entities!(client; entities);
+            assert!(entities.exists(handles[1]) && !entities.exists(handles[0]));
+        });
+    }
+
+    #[test]
+    fn dense_store_removal_works() {
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = DenseStore<Self>;
+        }
+
+        let mut store = DenseStore::<Health>::new();
+        for i in 0..4 {
+            store.add(i, Health(i * 10));
+        }
+        // removing from the middle moves the last component into the hole
+        store.rem(1);
+        assert_eq!(store.get(3), Some(&Health(30)));
+        assert_eq!(store.get(1), None);
+        // removing the last component must not touch the hole it leaves
+        store.rem(3);
+        store.rem(0);
+        assert_eq!(store.get(2), Some(&Health(20)));
+        assert_eq!(store.iter_entity().map(|(index, health)| (index, health.0)).collect::<Vec<_>>(), vec![(2, 20)]);
+        store.rem(2);
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn dense_store_sort_works() {
+        #[derive(Clone,Default,PartialEq,Debug)]
+        struct Health(u32);
+
+        impl entity::Component for Health {
+            type Storage = DenseStore<Self>;
+        }
+
+        let mut store = DenseStore::<Health>::new();
+        for i in [5, 2, 7, 0] {
+            store.add(i, Health(i * 10));
+        }
+        store.sort();
+        // the dense arrays hold entity indices in ascending order, each next to its own value
+        assert_eq!(store.iter_entity().map(|(index, health)| (index, health.0)).collect::<Vec<_>>(), vec![(0, 0), (2, 20), (5, 50), (7, 70)]);
+        for i in [5, 2, 7, 0] {
+            assert_eq!(store.get(i), Some(&Health(i * 10)));
+        }
+        store.rem(2);
+        assert_eq!(store.get(7), Some(&Health(70)));
+    }
+
     #[test]
     fn runtime_works() {
         let rt = crate::sync::Runtime::new();
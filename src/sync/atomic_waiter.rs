@@ -44,6 +44,14 @@ impl AtomicWaiter {
         }
     }
 
+    /**
+     * True, if all dependencies were dropped.
+     */
+    #[allow(unused)]
+    pub fn is_done(&self) -> bool {
+        self.data.count.load(std::sync::atomic::Ordering::Acquire) == 0
+    }
+
     #[allow(unused)]
     pub fn make_dependency(&self) -> AtomicDependency {
         self.data.count.fetch_add(1, std::sync::atomic::Ordering::Acquire);
@@ -117,6 +117,37 @@ fn worker(meta: Arc<RuntimeMeta>, worker_index: usize) {
     println!("INFO:   Runtime worker ended.");
 }
 
+#[allow(unused)]
+fn send_closure(meta: &RuntimeMeta, closure: impl FnOnce() + Send + 'static, priority: Priority) {
+    match priority {
+        Priority::Low => meta.execution_sender_low.send(ExecutionOrder::ExecuteClosure(Box::new(closure))).unwrap(),
+        Priority::Normal => meta.execution_sender_normal.send(ExecutionOrder::ExecuteClosure(Box::new(closure))).unwrap(),
+        Priority::High => meta.execution_sender_high.send(ExecutionOrder::ExecuteClosure(Box::new(closure))).unwrap(),
+        Priority::VeryHigh => meta.execution_sender_very_high.send(ExecutionOrder::ExecuteClosure(Box::new(closure))).unwrap(),
+    }
+    meta.signal_sender.send(RuntimeInfo::WakeUp);
+}
+
+/**
+ * Cloneable handle to the closure queues of a runtime, used by work that submits more work from within the threadpool.
+ */
+#[derive(Clone)]
+pub struct RuntimeHandle {
+    meta: Arc<RuntimeMeta>,
+}
+
+impl RuntimeHandle {
+    #[allow(unused)]
+    pub fn exec_prioritised(&self, closure: impl FnOnce() + Send + 'static, priority: Priority) {
+        send_closure(&self.meta, closure, priority);
+    }
+
+    #[allow(unused)]
+    pub fn worker_count(&self) -> usize {
+        self.meta.worker_count.load(Ordering::Relaxed) as usize
+    }
+}
+
 pub struct Runtime {
     pub(crate) meta: Arc<RuntimeMeta>,
     worker_joins: Mutex<Option<Vec<std::thread::JoinHandle<()>>>>,
@@ -179,7 +210,7 @@ impl Runtime {
      * Submitted future should have a short runtime (<200mics) or yield periodicly.
      */
     #[allow(unused)]
-    pub fn spawn_prioritised(&self, future: impl Future<Output = ()> + Send + Sync + 'static, priority: Priority) {
+    pub fn spawn_prioritised(&self, future: impl Future<Output = ()> + Send + 'static, priority: Priority) {
         let sender = match priority {
             Priority::Low => &self.meta.execution_sender_low,
             Priority::Normal => &self.meta.execution_sender_normal,
@@ -206,7 +237,7 @@ impl Runtime {
      * Submitted future should have a short runtime (<200mics) or yield periodicly.
      */
     #[allow(unused)]
-    pub fn spawn(&self, future: impl Future<Output = ()> + Send + Sync + 'static) {
+    pub fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
         self.spawn_prioritised(future, Priority::Normal);
     }
 
@@ -218,13 +249,7 @@ impl Runtime {
      */
     #[allow(unused)]
     pub fn exec_prioritised(&self, closure: impl FnOnce() + Send + 'static, priority: Priority) {
-        match priority {
-            Priority::Low => self.meta.execution_sender_low.send(ExecutionOrder::ExecuteClosure(Box::new(closure))).unwrap(),
-            Priority::Normal => self.meta.execution_sender_normal.send(ExecutionOrder::ExecuteClosure(Box::new(closure))).unwrap(),
-            Priority::High => self.meta.execution_sender_high.send(ExecutionOrder::ExecuteClosure(Box::new(closure))).unwrap(),
-            Priority::VeryHigh => self.meta.execution_sender_very_high.send(ExecutionOrder::ExecuteClosure(Box::new(closure))).unwrap(),
-        }
-        self.meta.signal_sender.send(RuntimeInfo::WakeUp);
+        send_closure(&self.meta, closure, priority);
     }
     
     /**
@@ -238,6 +263,19 @@ impl Runtime {
         self.exec_prioritised(closure, Priority::Normal);
     }
 
+    /**
+     * Number of worker threads that are still running.
+     */
+    #[allow(unused)]
+    pub fn worker_count(&self) -> usize {
+        self.meta.worker_count.load(Ordering::Relaxed) as usize
+    }
+
+    #[allow(unused)]
+    pub fn handle(&self) -> RuntimeHandle {
+        RuntimeHandle{ meta: self.meta.clone() }
+    }
+
     /**
      * Kills threadpool.
      * All worker threads will terminate AFTER all open tasks are completed.
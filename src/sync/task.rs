@@ -16,7 +16,7 @@ pub enum RuntimeInfo {
     WakeUp
 }
 
-type TaskFutureBox = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+type TaskFutureBox = Pin<Box<dyn Future<Output = ()> + Send>>;
 type ClosureBox = Box<dyn FnOnce() + Send>;
 
 #[derive(Clone,Copy)]